@@ -0,0 +1,214 @@
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use steamm_ox::{
+    math::{decimal::Decimal, fixed_point::FixedPoint64, u256::U256},
+    omm::{
+        QuoterType, SteammPool,
+        omm_v2_legacy::{NewtonRaphsonMode, newton_raphson_with_mode, quote_swap_inner},
+        omm_v2_new::{get_d, get_d_u128, get_d_u256, get_y, get_y_u128, get_y_u256},
+    },
+};
+
+const AMPLIFIERS: [u32; 4] = [1, 10, 100, 1000];
+
+// (label, reserve x, reserve y) in btoken units of a 9/6 decimals SUI/USDC pool
+const RESERVES: [(&str, u64, u64); 3] = [
+    ("small", 1_000_000_000_000, 3_000_000_000),
+    ("medium", 1_000_000_000_000_000, 3_000_000_000_000),
+    ("large", 10_000_000_000_000_000_000, 30_000_000_000_000_000),
+];
+
+// (label, reserve a, reserve b) in scaled USD
+const D_RESERVES: [(&str, u64, u64); 2] = [
+    ("balanced", 646_604_101_554_903, 430_825_829_860_939),
+    ("imbalanced", 646_604_101_554_903, 430_825_829_860),
+];
+
+fn bench_get_d(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_d");
+    for (reserve_name, reserve_a, reserve_b) in D_RESERVES {
+        for amp in AMPLIFIERS {
+            let scaled_amp = U256::from(amp * 2 * 100);
+            group.bench_with_input(
+                BenchmarkId::new(reserve_name, amp),
+                &scaled_amp,
+                |b, amp| {
+                    b.iter(|| {
+                        get_d(
+                            black_box(U256::from(reserve_a)),
+                            black_box(U256::from(reserve_b)),
+                            black_box(*amp),
+                        )
+                        .unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_get_y(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_y");
+    for amp in AMPLIFIERS {
+        let scaled_amp = U256::from(amp * 2 * 100);
+        let d = get_d(
+            U256::from(646_604_101_554_903u64),
+            U256::from(430_825_829_860_939u64),
+            scaled_amp,
+        )
+        .unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(amp), &scaled_amp, |b, amp| {
+            b.iter(|| {
+                get_y(
+                    black_box(U256::from(700_000_000_000_000u64)),
+                    black_box(*amp),
+                    black_box(d),
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Compares the u128 fast path against the U256 path on the same inputs.
+fn bench_fast_path(c: &mut Criterion) {
+    let (reserve_a, reserve_b, amp) = (646_604_101_554_903u64, 430_825_829_860_939u64, 10_000u64);
+    let d = get_d_u256(
+        U256::from(reserve_a),
+        U256::from(reserve_b),
+        U256::from(amp),
+    )
+    .unwrap();
+    let reserve_in = 1_045_311_940_606_135u64;
+
+    let mut group = c.benchmark_group("fast_path");
+    group.bench_function("get_d/u128", |b| {
+        b.iter(|| {
+            get_d_u128(
+                black_box(reserve_a as u128),
+                black_box(reserve_b as u128),
+                black_box(amp as u128),
+            )
+        })
+    });
+    group.bench_function("get_d/u256", |b| {
+        b.iter(|| {
+            get_d_u256(
+                black_box(U256::from(reserve_a)),
+                black_box(U256::from(reserve_b)),
+                black_box(U256::from(amp)),
+            )
+            .unwrap()
+        })
+    });
+    group.bench_function("get_y/u128", |b| {
+        b.iter(|| {
+            get_y_u128(
+                black_box(reserve_in as u128),
+                black_box(amp as u128),
+                black_box(d.as_u128()),
+            )
+        })
+    });
+    group.bench_function("get_y/u256", |b| {
+        b.iter(|| {
+            get_y_u256(
+                black_box(U256::from(reserve_in)),
+                black_box(U256::from(amp)),
+                black_box(d),
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_newton_raphson(c: &mut Criterion) {
+    let mut group = c.benchmark_group("newton_raphson");
+    let k = FixedPoint64::from_rational(1, 10).unwrap();
+    for amp in AMPLIFIERS {
+        let a = FixedPoint64::from(amp as u128).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(amp), &a, |b, a| {
+            b.iter(|| {
+                newton_raphson_with_mode(
+                    black_box(&k),
+                    black_box(a),
+                    black_box(&k),
+                    NewtonRaphsonMode::EarlyExit,
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Runs both quoter families over the same pools so they can be compared
+/// side by side in the report.
+fn bench_quote_swap(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quote_swap");
+    for (quoter_name, quoter_type) in [
+        ("legacy", QuoterType::Ommv2Legacy),
+        ("new", QuoterType::Ommv2),
+    ] {
+        for (reserve_name, reserve_x, reserve_y) in RESERVES {
+            for amp in AMPLIFIERS {
+                let pool = SteammPool::new(reserve_x, reserve_y, 9, 6, amp, 30, quoter_type);
+                group.bench_with_input(
+                    BenchmarkId::new(format!("{}/{}", quoter_name, reserve_name), amp),
+                    &pool,
+                    |b, pool| {
+                        b.iter(|| {
+                            pool.quote_swap(
+                                black_box(reserve_x / 100),
+                                Decimal::from("3"),
+                                Decimal::from("1"),
+                                true,
+                                Decimal::from("1.0"),
+                                Decimal::from("1.0"),
+                                Some(Decimal::from("0.001")),
+                                Some(Decimal::from("0.0001")),
+                            )
+                            .unwrap()
+                        })
+                    },
+                );
+            }
+        }
+    }
+    group.finish();
+}
+
+/// Quotes a USDC/USDT-like pool where both tokens have 6 decimals.
+fn bench_legacy_equal_decimals(c: &mut Criterion) {
+    c.bench_function("legacy_equal_decimals", |b| {
+        b.iter(|| {
+            quote_swap_inner(
+                black_box(10_000_000),
+                black_box(1_000_000_000_000),
+                black_box(1_000_000_000_000),
+                Decimal::from("1"),
+                Decimal::from("1"),
+                6,
+                6,
+                100,
+                true,
+            )
+            .unwrap()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_get_d,
+    bench_get_y,
+    bench_fast_path,
+    bench_newton_raphson,
+    bench_quote_swap,
+    bench_legacy_equal_decimals
+);
+criterion_main!(benches);
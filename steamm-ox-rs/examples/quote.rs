@@ -0,0 +1,104 @@
+//! Quotes a swap against a pool state read from a JSON file.
+//!
+//! ```text
+//! cargo run --example quote --features serde -- examples/pool.json [--quoter legacy|new]
+//! ```
+//!
+//! The file holds the pool, the oracle prices and the swap to quote. Decimal
+//! values are strings to avoid any floating point rounding:
+//!
+//! ```json
+//! {
+//!   "pool": {
+//!     "b_token_reserve_x": 1000000000000,
+//!     "b_token_reserve_y": 3000000000,
+//!     "decimals_x": 9,
+//!     "decimals_y": 6,
+//!     "amplifier": 10,
+//!     "swap_fee_bps": 30,
+//!     "quoter_type": "Ommv2"
+//!   },
+//!   "price_x": "3",
+//!   "price_y": "1",
+//!   "price_confidence_x": "0.001",
+//!   "price_confidence_y": "0.0001",
+//!   "b_token_ratio_x": "1.0",
+//!   "b_token_ratio_y": "1.0",
+//!   "b_token_amount_in": 10000000000,
+//!   "x2y": true
+//! }
+//! ```
+//!
+//! `--quoter` overrides the quoter type of the pool.
+
+use std::{env, fs};
+
+use anyhow::Result;
+use serde::Deserialize;
+use steamm_ox::{
+    math::decimal::Decimal,
+    omm::{QuoterType, SteammPool},
+};
+
+#[derive(Deserialize)]
+struct QuoteRequest {
+    pool: SteammPool,
+    price_x: Decimal,
+    price_y: Decimal,
+    price_confidence_x: Decimal,
+    price_confidence_y: Decimal,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+    b_token_amount_in: u64,
+    x2y: bool,
+}
+
+const USAGE: &str = "usage: quote <pool.json> [--quoter legacy|new]";
+
+fn parse_quoter_type(value: &str) -> Result<QuoterType> {
+    match value {
+        "legacy" => Ok(QuoterType::Ommv2Legacy),
+        "new" => Ok(QuoterType::Ommv2),
+        _ => Err(anyhow::anyhow!("Unknown quoter {:?}\n{}", value, USAGE)),
+    }
+}
+
+fn main() -> Result<()> {
+    let mut path = None;
+    let mut quoter_type = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quoter" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing value for --quoter\n{}", USAGE))?;
+                quoter_type = Some(parse_quoter_type(&value)?);
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(anyhow::anyhow!("Unexpected argument {:?}\n{}", arg, USAGE)),
+        }
+    }
+    let path = path.ok_or_else(|| anyhow::anyhow!(USAGE))?;
+
+    let mut request: QuoteRequest = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    if let Some(quoter_type) = quoter_type {
+        request.pool.quoter_type = quoter_type;
+    }
+
+    let quote = request.pool.quote_swap(
+        request.b_token_amount_in,
+        request.price_x,
+        request.price_y,
+        request.x2y,
+        request.b_token_ratio_x,
+        request.b_token_ratio_y,
+        Some(request.price_confidence_x),
+        Some(request.price_confidence_y),
+    )?;
+
+    println!("{}", serde_json::to_string_pretty(&quote)?);
+
+    Ok(())
+}
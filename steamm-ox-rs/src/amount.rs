@@ -0,0 +1,155 @@
+//! Typed amounts, so that btoken and underlying amounts cannot be mixed up.
+//!
+//! The raw `u64` APIs remain available. The typed variants only differ in
+//! their signatures, e.g. passing an underlying amount where a btoken amount
+//! is expected does not compile:
+//!
+//! ```compile_fail
+//! use steamm_ox::{
+//!     amount::{BTokenRatio, UnderlyingAmount},
+//!     math::decimal::Decimal,
+//!     omm::{QuoterType, SteammPool},
+//! };
+//!
+//! let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 100, 30, QuoterType::Ommv2);
+//! let ratio = BTokenRatio(Decimal::from("1.5"));
+//!
+//! // 10 SUI, but the pool trades bSUI: convert with `to_b_token` first
+//! let amount_in = UnderlyingAmount(10_000_000_000);
+//! let quote = pool.quote_swap_typed(
+//!     amount_in,
+//!     Decimal::from("3"),
+//!     Decimal::from("1"),
+//!     true,
+//!     ratio,
+//!     ratio,
+//!     Some(Decimal::from("0")),
+//!     Some(Decimal::from("0")),
+//! );
+//! ```
+//!
+//! whereas converting first does:
+//!
+//! ```
+//! # use steamm_ox::{
+//! #     amount::{BTokenRatio, UnderlyingAmount},
+//! #     math::decimal::Decimal,
+//! #     omm::{QuoterType, SteammPool},
+//! # };
+//! # let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 100, 30, QuoterType::Ommv2);
+//! # let ratio = BTokenRatio(Decimal::from("1.5"));
+//! let amount_in = UnderlyingAmount(10_000_000_000).to_b_token(ratio)?;
+//! let quote = pool.quote_swap_typed(
+//!     amount_in,
+//!     Decimal::from("3"),
+//!     Decimal::from("1"),
+//!     true,
+//!     ratio,
+//!     ratio,
+//!     Some(Decimal::from("0")),
+//!     Some(Decimal::from("0")),
+//! )?;
+//! assert!(quote.amount_out > 0);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::Result;
+
+use crate::{math::decimal::Decimal, to_b_token, to_underlying};
+
+/// An amount of btokens, e.g. bSUI.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BTokenAmount(pub u64);
+
+/// An amount of the underlying token, e.g. SUI.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UnderlyingAmount(pub u64);
+
+/// Underlying tokens per btoken.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BTokenRatio(pub Decimal);
+
+impl BTokenAmount {
+    /// Same as `to_underlying`.
+    pub fn to_underlying(self, b_token_ratio: BTokenRatio) -> Result<UnderlyingAmount> {
+        to_underlying(self.0, &b_token_ratio.0).map(UnderlyingAmount)
+    }
+}
+
+impl UnderlyingAmount {
+    /// Same as `to_b_token`.
+    pub fn to_b_token(self, b_token_ratio: BTokenRatio) -> Result<BTokenAmount> {
+        to_b_token(self.0, &b_token_ratio.0).map(BTokenAmount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::omm::{QuoterType, SteammPool};
+
+    #[test]
+    fn test_typed_conversions_match_raw() -> Result<()> {
+        for ratio in ["1", "1.5", "0.9", "2.718281828459045235"] {
+            let ratio = Decimal::from(ratio);
+            for amount in [0, 1, 999, 1_000_000_000, u64::MAX / 4] {
+                assert_eq!(
+                    BTokenAmount(amount).to_underlying(BTokenRatio(ratio))?,
+                    UnderlyingAmount(to_underlying(amount, &ratio)?)
+                );
+                assert_eq!(
+                    UnderlyingAmount(amount).to_b_token(BTokenRatio(ratio))?,
+                    BTokenAmount(to_b_token(amount, &ratio)?)
+                );
+            }
+        }
+
+        assert!(
+            BTokenAmount(1)
+                .to_underlying(BTokenRatio(Decimal::ZERO))
+                .is_err()
+        );
+        assert!(
+            UnderlyingAmount(1)
+                .to_b_token(BTokenRatio(Decimal::ZERO))
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_typed_matches_raw() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (ratio_x, ratio_y) = (Decimal::from("1.5"), Decimal::from("1.1"));
+        let confidence = Some(Decimal::from("0.0"));
+
+        for quoter_type in [QuoterType::Ommv2, QuoterType::Ommv2Legacy] {
+            let pool =
+                SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 100, 30, quoter_type);
+            for x2y in [true, false] {
+                let amount_in = if x2y { 1_000_000_000 } else { 3_000_000 };
+                assert_eq!(
+                    pool.quote_swap_typed(
+                        BTokenAmount(amount_in),
+                        price_x,
+                        price_y,
+                        x2y,
+                        BTokenRatio(ratio_x),
+                        BTokenRatio(ratio_y),
+                        confidence,
+                        confidence,
+                    )?,
+                    pool.quote_swap(
+                        amount_in, price_x, price_y, x2y, ratio_x, ratio_y, confidence, confidence,
+                    )?
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
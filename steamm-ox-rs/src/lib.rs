@@ -1,13 +1,16 @@
-use crate::math::{decimal::Decimal, safe_mul_div_up};
+use crate::math::{RoundingMode, decimal::Decimal, safe_mul_div};
 use anyhow::Result;
 
+pub mod amount;
 pub mod math;
 pub mod omm;
+pub mod oracle;
 
 pub const BPS_SCALE: u64 = 10_000; // Basis points scale factor
 const PROTOCOL_FEE_NUMERATOR: u64 = 2_000;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SwapQuote {
     pub amount_in: u64,
     pub amount_out: u64,
@@ -16,64 +19,797 @@ pub struct SwapQuote {
     pub a2b: bool,
 }
 
+/// A `SwapQuote` whose fees are not rounded, so that summing many quotes does
+/// not accumulate the rounding of each. `amount_out` plus both fees is the
+/// btoken amount out of the curve before fees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecimalSwapQuote {
+    pub amount_in: u64,
+    pub amount_out: Decimal,
+    pub protocol_fees: Decimal,
+    pub pool_fees: Decimal,
+    pub a2b: bool,
+}
+
+impl DecimalSwapQuote {
+    /// Returns the sum of protocol and pool fees.
+    pub fn total_fees(&self) -> Decimal {
+        Decimal(self.protocol_fees.0 + self.pool_fees.0)
+    }
+}
+
+/// Same as `get_quote`, deducting the exact fee at `fee_bps` instead of
+/// rounding it up. Flooring `amount_out` gives the `amount_out` of `get_quote`
+/// with the same effective fee.
+pub fn get_decimal_quote(
+    amount_in: u64,
+    amount_out: u64,
+    a2b: bool,
+    fee_bps: u64,
+) -> Result<DecimalSwapQuote> {
+    let (protocol_fees, pool_fees) = compute_swap_fees_decimal(amount_out, fee_bps, None)?;
+
+    Ok(DecimalSwapQuote {
+        amount_in,
+        amount_out: Decimal::from(amount_out)
+            .saturating_sub(&protocol_fees)
+            .saturating_sub(&pool_fees),
+        protocol_fees,
+        pool_fees,
+        a2b,
+    })
+}
+
+/// Quotes are ordered by how good they are for the trader: by `amount_out`,
+/// then by lower total fees, then by lower `amount_in`. The remaining fields
+/// only break ties between quotes that differ in the fee split or direction,
+/// so that the order is total and consistent with `Eq`.
+impl Ord for SwapQuote {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.amount_out
+            .cmp(&other.amount_out)
+            .then_with(|| other.total_fees().cmp(&self.total_fees()))
+            .then_with(|| other.amount_in.cmp(&self.amount_in))
+            .then_with(|| self.protocol_fees.cmp(&other.protocol_fees))
+            .then_with(|| self.a2b.cmp(&other.a2b))
+    }
+}
+
+impl PartialOrd for SwapQuote {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl SwapQuote {
+    pub fn new(
+        amount_in: u64,
+        amount_out: u64,
+        protocol_fees: u64,
+        pool_fees: u64,
+        a2b: bool,
+    ) -> Self {
+        Self {
+            amount_in,
+            amount_out,
+            protocol_fees,
+            pool_fees,
+            a2b,
+        }
+    }
+
+    /// Returns the sum of protocol and pool fees, as a u128 so that it cannot
+    /// overflow.
+    pub fn total_fees(&self) -> u128 {
+        self.protocol_fees as u128 + self.pool_fees as u128
+    }
+
+    /// Returns the fees charged relative to the gross output, in basis points,
+    /// rounded down.
+    pub fn realized_fee_bps(&self) -> u64 {
+        let gross_amount_out = self.amount_out as u128 + self.total_fees();
+        if gross_amount_out == 0 {
+            return 0;
+        }
+
+        (self.total_fees() * BPS_SCALE as u128 / gross_amount_out) as u64
+    }
+
+    /// Returns how far the execution price, net of fees, is below
+    /// `spot_price`, in basis points rounded down. `spot_price` is the btoken
+    /// amount out per btoken in, e.g. from `SteammPool::spot_price`. Unlike
+    /// the price impact, this includes the fees.
+    pub fn total_slippage_bps(&self, spot_price: Decimal) -> u64 {
+        shortfall_bps(self.amount_in, self.amount_out, &spot_price).unwrap_or(0)
+    }
+}
+
+/// Returns how far `amount_out / amount_in` is below `spot_price`, in basis
+/// points rounded down, or zero if it is above. Returns `None` if the amount
+/// in or the spot price is zero.
+pub(crate) fn shortfall_bps(amount_in: u64, amount_out: u64, spot_price: &Decimal) -> Option<u64> {
+    let execution_price_bps = Decimal::from(amount_out)
+        .checked_mul_int(BPS_SCALE)?
+        .checked_div(&Decimal::from(amount_in))?
+        .checked_div(spot_price)?;
+
+    Some(
+        Decimal::from(BPS_SCALE)
+            .checked_sub(&execution_price_bps)
+            .map_or(0, |shortfall| shortfall.checked_floor().unwrap_or(0)),
+    )
+}
+
+/// Splits the swap fee on `amount` into `(protocol_fees, pool_fees)`.
+/// Both the total fee and the protocol share are rounded up, so fees never
+/// decrease as `amount` grows, but any nonzero amount with a nonzero fee pays
+/// at least one unit, all of which goes to the protocol.
 pub fn compute_swap_fees(
     amount: u64,
     swap_fee_bps: u64,
     swap_fee_override_numerator: Option<u64>,
+) -> Result<(u64, u64)> {
+    compute_swap_fees_rounded(
+        amount,
+        swap_fee_bps,
+        swap_fee_override_numerator,
+        RoundingMode::Ceil,
+    )
+}
+
+/// Same as `compute_swap_fees`, rounding both the total fee and the protocol
+/// share with `rounding_mode`. The pool fees are always the remainder.
+///
+/// Errors if the effective fee exceeds 100%, so the fees never exceed
+/// `amount`.
+pub fn compute_swap_fees_rounded(
+    amount: u64,
+    swap_fee_bps: u64,
+    swap_fee_override_numerator: Option<u64>,
+    rounding_mode: RoundingMode,
 ) -> Result<(u64, u64)> {
     let (protocol_fee_num, protocol_fee_denom) = (PROTOCOL_FEE_NUMERATOR, BPS_SCALE);
-    let (pool_fee_num, pool_fee_denom) = if let Some(override_num) = swap_fee_override_numerator {
-        let (pool_fee_num_default, pool_fee_denom_default) = (swap_fee_bps, BPS_SCALE);
-        if override_num * pool_fee_denom_default > pool_fee_num_default * BPS_SCALE {
-            (override_num, BPS_SCALE)
-        } else {
-            (pool_fee_num_default, pool_fee_denom_default)
-        }
-    } else {
-        (swap_fee_bps, BPS_SCALE)
-    };
+    let (pool_fee_num, pool_fee_denom) = (
+        effective_fee_bps(swap_fee_bps, swap_fee_override_numerator),
+        BPS_SCALE,
+    );
+    if pool_fee_num > pool_fee_denom {
+        return Err(anyhow::anyhow!(
+            "Swap fee {} bps exceeds {} bps",
+            pool_fee_num,
+            BPS_SCALE
+        ));
+    }
 
-    let total_fees = safe_mul_div_up(amount, pool_fee_num, pool_fee_denom)?;
-    let protocol_fees = safe_mul_div_up(total_fees, protocol_fee_num, protocol_fee_denom)?;
+    let total_fees = safe_mul_div(amount, pool_fee_num, pool_fee_denom, rounding_mode)?;
+    let protocol_fees = safe_mul_div(
+        total_fees,
+        protocol_fee_num,
+        protocol_fee_denom,
+        rounding_mode,
+    )?;
     let pool_fees = total_fees - protocol_fees;
 
+    // Both rates are at most 100%, which rounding cannot push past the amount
+    debug_assert!(total_fees <= amount);
+
     Ok((protocol_fees, pool_fees))
 }
 
+/// Same as `compute_swap_fees`, returning the exact `(protocol_fees,
+/// pool_fees)` without rounding, e.g. 0.0024 units of pool fees for 30 bps on
+/// an amount of one. Callers summing many fees can round once at the end.
+pub fn compute_swap_fees_decimal(
+    amount: u64,
+    swap_fee_bps: u64,
+    swap_fee_override_numerator: Option<u64>,
+) -> Result<(Decimal, Decimal)> {
+    let fee_bps = effective_fee_bps(swap_fee_bps, swap_fee_override_numerator);
+    if fee_bps > BPS_SCALE {
+        return Err(anyhow::anyhow!(
+            "Swap fee {} bps exceeds {} bps",
+            fee_bps,
+            BPS_SCALE
+        ));
+    }
+
+    // Exact, as WAD has more decimal places than two divisions by BPS_SCALE
+    let total_fees = Decimal::from(amount)
+        .checked_mul_int(fee_bps)
+        .and_then(|fees| fees.checked_div_int(BPS_SCALE))
+        .ok_or_else(|| anyhow::anyhow!("Fee computation overflow"))?;
+    let protocol_fees = total_fees
+        .checked_mul_int(PROTOCOL_FEE_NUMERATOR)
+        .and_then(|fees| fees.checked_div_int(BPS_SCALE))
+        .ok_or_else(|| anyhow::anyhow!("Fee computation overflow"))?;
+
+    Ok((protocol_fees, total_fees.saturating_sub(&protocol_fees)))
+}
+
+/// Returns the pool (LP) share of the swap fee on `amount`, i.e. the
+/// `pool_fees` returned by `compute_swap_fees`.
+pub fn compute_lp_fee_only(
+    amount: u64,
+    swap_fee_bps: u64,
+    swap_fee_override_numerator: Option<u64>,
+) -> Result<u64> {
+    let (_, pool_fees) = compute_swap_fees(amount, swap_fee_bps, swap_fee_override_numerator)?;
+    Ok(pool_fees)
+}
+
+/// Returns the fee, in basis points, that `compute_swap_fees` charges. An
+/// override numerator, also in basis points, only applies when it is larger
+/// than `swap_fee_bps`.
+pub fn effective_fee_bps(swap_fee_bps: u64, swap_fee_override_numerator: Option<u64>) -> u64 {
+    swap_fee_override_numerator.map_or(swap_fee_bps, |override_num| override_num.max(swap_fee_bps))
+}
+
+/// Builds a quote from the gross amount out by deducting the swap fees.
+/// Errors if the fees exceed the gross amount out, which signals a
+/// misconfigured fee or fee override.
 pub fn get_quote(
     amount_in: u64,
     amount_out: u64,
     a2b: bool,
     swap_fee_bps: u64,
     swap_fee_override_numerator: Option<u64>,
-) -> SwapQuote {
+) -> Result<SwapQuote> {
     let (protocol_fees, pool_fees) =
-        compute_swap_fees(amount_out, swap_fee_bps, swap_fee_override_numerator).unwrap();
+        compute_swap_fees(amount_out, swap_fee_bps, swap_fee_override_numerator)?;
     let amount_out_net = amount_out
-        .saturating_sub(protocol_fees)
-        .saturating_sub(pool_fees);
+        .checked_sub(protocol_fees + pool_fees)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Swap fees {} exceed amount out {}",
+                protocol_fees + pool_fees,
+                amount_out
+            )
+        })?;
 
-    SwapQuote {
+    Ok(SwapQuote {
         amount_in,
         amount_out: amount_out_net,
         protocol_fees,
         pool_fees,
         a2b,
-    }
+    })
 }
 
 /// Converts a btoken amount to its underlying amount using the btoken ratio.
-pub fn to_underlying(btoken_amount: u64, b_token_ratio: &Decimal) -> u64 {
+/// Errors if the ratio is zero or the underlying amount does not fit in a u64.
+pub fn to_underlying(btoken_amount: u64, b_token_ratio: &Decimal) -> Result<u64> {
+    validate_b_token_ratio(b_token_ratio)?;
+    if b_token_ratio.is_one() {
+        return Ok(btoken_amount);
+    }
+
     (Decimal::from(btoken_amount)
         .checked_mul(b_token_ratio)
-        .unwrap())
+        .ok_or_else(|| anyhow::anyhow!("Underlying conversion overflow"))?)
     .checked_floor::<u64>()
-    .unwrap()
+    .ok_or_else(|| anyhow::anyhow!("Underlying amount exceeds u64"))
 }
 
 /// Converts an underlying amount to its btoken amount using the btoken ratio.
-pub fn to_b_token(amount: u64, b_token_ratio: &Decimal) -> u64 {
-    (Decimal::from(amount).checked_div(b_token_ratio).unwrap())
-        .checked_floor::<u64>()
-        .unwrap()
+/// Errors if the ratio is zero or the btoken amount does not fit in a u64.
+///
+/// Both this and `to_underlying` round down, so a round trip
+/// `to_b_token(to_underlying(x, r), r)` never exceeds `x` and falls short by
+/// at most `ceil(1 / r)` units: one unit for any ratio of at least one, which
+/// btoken ratios are since they only accrue interest. The swap path converts
+/// the amount in with `to_underlying` and the amount out with `to_b_token`, so
+/// both conversions favor the pool.
+pub fn to_b_token(amount: u64, b_token_ratio: &Decimal) -> Result<u64> {
+    validate_b_token_ratio(b_token_ratio)?;
+    if b_token_ratio.is_one() {
+        return Ok(amount);
+    }
+
+    (Decimal::from(amount)
+        .checked_div(b_token_ratio)
+        .ok_or_else(|| anyhow::anyhow!("BToken conversion failed"))?)
+    .checked_floor::<u64>()
+    .ok_or_else(|| anyhow::anyhow!("BToken amount exceeds u64"))
+}
+
+/// Same as `to_b_token`, rounding the btoken amount with `rounding_mode`.
+/// Rounding up gives the smallest btoken amount worth at least `amount` of
+/// the underlying token, e.g. the minimum deposit for a target amount.
+pub fn to_b_token_round(
+    amount: u64,
+    b_token_ratio: &Decimal,
+    rounding_mode: RoundingMode,
+) -> Result<u64> {
+    validate_b_token_ratio(b_token_ratio)?;
+    if b_token_ratio.is_one() {
+        return Ok(amount);
+    }
+
+    let b_token_amount = match rounding_mode {
+        RoundingMode::Floor => return to_b_token(amount, b_token_ratio),
+        // The quotient itself is rounded up, so a remainder beyond 18 decimal
+        // places still rounds up to the next btoken
+        RoundingMode::Ceil => Decimal::from(amount)
+            .checked_div_ceil(b_token_ratio)
+            .and_then(|b_token_amount| b_token_amount.checked_ceil::<u64>()),
+        RoundingMode::Nearest | RoundingMode::NearestEven => Decimal::from(amount)
+            .checked_div_precise(b_token_ratio)
+            .and_then(|b_token_amount| b_token_amount.checked_round_with(rounding_mode)),
+    };
+
+    b_token_amount.ok_or_else(|| anyhow::anyhow!("BToken amount exceeds u64"))
+}
+
+/// A btoken ratio of zero would mean the btokens are backed by nothing, and
+/// cannot be inverted.
+fn validate_b_token_ratio(b_token_ratio: &Decimal) -> Result<()> {
+    if b_token_ratio.is_zero() {
+        return Err(anyhow::anyhow!("BToken ratio must be greater than zero"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::omm::test_utils::Rng;
+
+    #[test]
+    fn test_total_fees_and_realized_fee_bps() {
+        for swap_fee_bps in [1, 5, 30, 100, 10_000] {
+            let quote = get_quote(1_000, 1_000_000, true, swap_fee_bps, None).unwrap();
+            assert_eq!(
+                quote.total_fees(),
+                (quote.protocol_fees + quote.pool_fees) as u128
+            );
+            assert_eq!(quote.total_fees(), 100 * swap_fee_bps as u128);
+            assert_eq!(quote.realized_fee_bps(), swap_fee_bps);
+        }
+
+        // Fees are rounded up, so small outputs pay a higher realized rate
+        let quote = get_quote(1_000, 100, true, 30, None).unwrap();
+        assert_eq!(quote.total_fees(), 1);
+        assert_eq!(quote.realized_fee_bps(), 100);
+
+        let quote = get_quote(0, 0, true, 30, None).unwrap();
+        assert_eq!(quote.total_fees(), 0);
+        assert_eq!(quote.realized_fee_bps(), 0);
+
+        // Fees at the limit of a u64 do not overflow
+        let quote = SwapQuote::new(1, u64::MAX, u64::MAX, u64::MAX, true);
+        assert_eq!(quote.total_fees(), 2 * u64::MAX as u128);
+        assert_eq!(quote.realized_fee_bps(), 6_666);
+    }
+
+    #[test]
+    fn test_get_quote_rejects_fees_above_amount_out() {
+        // A 150% override consumes more than the gross output
+        let err = get_quote(1_000, 1_000_000, true, 30, Some(15_000)).unwrap_err();
+        assert_eq!(err.to_string(), "Swap fee 15000 bps exceeds 10000 bps");
+
+        // A 100% fee is allowed and leaves nothing
+        let quote = get_quote(1_000, 1_000_000, true, 30, Some(10_000)).unwrap();
+        assert_eq!(quote.amount_out, 0);
+        assert_eq!(quote.total_fees(), 1_000_000);
+    }
+
+    #[test]
+    fn test_compute_swap_fees_monotonic_in_amount() {
+        for swap_fee_bps in [0, 1, 5, 30, 100, 10_000] {
+            let mut prev = (0, 0);
+            for amount in (0..5_000).chain([u64::MAX / 10_000, u64::MAX / 10_000 + 1]) {
+                let (protocol_fees, pool_fees) =
+                    compute_swap_fees(amount, swap_fee_bps, None).unwrap();
+                assert!(protocol_fees + pool_fees >= prev.0 + prev.1);
+                assert!(protocol_fees >= prev.0);
+                assert!(pool_fees >= prev.1);
+                prev = (protocol_fees, pool_fees);
+            }
+        }
+
+        // The smallest trade pays a whole unit, and it all goes to the protocol
+        for swap_fee_bps in [1, 30, 10_000] {
+            assert_eq!(compute_swap_fees(1, swap_fee_bps, None).unwrap(), (1, 0));
+        }
+        assert_eq!(compute_swap_fees(1, 0, None).unwrap(), (0, 0));
+        assert_eq!(compute_swap_fees(0, 30, None).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_compute_swap_fees_rounded() {
+        // 30 bps of 1_234_567 is 3_703.701, and the protocol takes 20% of that
+        let fees = |rounding_mode| compute_swap_fees_rounded(1_234_567, 30, None, rounding_mode);
+
+        assert_eq!(fees(RoundingMode::Ceil).unwrap(), (741, 2_963));
+        assert_eq!(fees(RoundingMode::Floor).unwrap(), (740, 2_963));
+        assert_eq!(fees(RoundingMode::Nearest).unwrap(), (741, 2_963));
+        assert_eq!(
+            fees(RoundingMode::Ceil).unwrap(),
+            compute_swap_fees(1_234_567, 30, None).unwrap()
+        );
+
+        // Flooring lets dust trades through without fees
+        assert_eq!(
+            compute_swap_fees_rounded(1, 30, None, RoundingMode::Floor).unwrap(),
+            (0, 0)
+        );
+        assert_eq!(
+            compute_swap_fees_rounded(1, 30, None, RoundingMode::Ceil).unwrap(),
+            (1, 0)
+        );
+
+        // Divisible amounts are unaffected by the rounding mode
+        for rounding_mode in [
+            RoundingMode::Floor,
+            RoundingMode::Ceil,
+            RoundingMode::Nearest,
+        ] {
+            assert_eq!(
+                compute_swap_fees_rounded(1_000_000, 30, Some(50), rounding_mode).unwrap(),
+                (1_000, 4_000)
+            );
+        }
+    }
+
+    #[test]
+    fn test_b_token_ratio_zero_and_one() {
+        for amount in [0, 1, 123_456_789, u64::MAX] {
+            assert_eq!(to_underlying(amount, &Decimal::ONE).unwrap(), amount);
+            assert_eq!(to_b_token(amount, &Decimal::ONE).unwrap(), amount);
+            assert_eq!(
+                to_underlying(amount, &Decimal::ZERO)
+                    .unwrap_err()
+                    .to_string(),
+                "BToken ratio must be greater than zero"
+            );
+            assert_eq!(
+                to_b_token(amount, &Decimal::ZERO).unwrap_err().to_string(),
+                "BToken ratio must be greater than zero"
+            );
+        }
+
+        // Ratios just above one still take the general, rounded conversion
+        let ratio = Decimal::from("1.000000000000000001");
+        assert_eq!(to_underlying(1_000_000, &ratio).unwrap(), 1_000_000);
+        assert_eq!(to_b_token(1_000_000, &ratio).unwrap(), 999_999);
+    }
+
+    #[test]
+    fn test_to_b_token_round() -> Result<()> {
+        // 1_000 underlying at a ratio of 3 is 333.33.. btokens
+        let ratio = Decimal::from("3");
+        assert_eq!(to_b_token_round(1_000, &ratio, RoundingMode::Floor)?, 333);
+        assert_eq!(to_b_token_round(1_000, &ratio, RoundingMode::Ceil)?, 334);
+        assert_eq!(to_b_token_round(1_000, &ratio, RoundingMode::Nearest)?, 333);
+        assert_eq!(to_b_token_round(1_001, &ratio, RoundingMode::Nearest)?, 334);
+
+        // Rounding up gives the minimum deposit worth the target underlying
+        for ratio in ["1.1", "1.5", "1.000000000000000001", "2.718281828459045235"] {
+            let ratio = Decimal::from(ratio);
+            for amount in [1, 7, 1_000, 999_999_999, 123_456_789_012] {
+                let b_token_amount = to_b_token_round(amount, &ratio, RoundingMode::Ceil)?;
+                assert!(to_underlying(b_token_amount, &ratio)? >= amount);
+                assert!(to_underlying(b_token_amount - 1, &ratio)? < amount);
+                assert_eq!(
+                    to_b_token_round(amount, &ratio, RoundingMode::Floor)?,
+                    to_b_token(amount, &ratio)?
+                );
+            }
+        }
+
+        // A quotient whose fraction lies beyond 18 decimal places still
+        // rounds up
+        let ratio = Decimal::from("1.000000000000000001");
+        assert_eq!(
+            to_b_token_round(1_000_000, &ratio, RoundingMode::Ceil)?,
+            1_000_000
+        );
+        assert_eq!(
+            to_b_token_round(1_000_000, &ratio, RoundingMode::Floor)?,
+            999_999
+        );
+
+        // Exact quotients are unchanged whatever the mode
+        for rounding_mode in [
+            RoundingMode::Floor,
+            RoundingMode::Ceil,
+            RoundingMode::Nearest,
+            RoundingMode::NearestEven,
+        ] {
+            assert_eq!(
+                to_b_token_round(1_500, &Decimal::from("1.5"), rounding_mode)?,
+                1_000
+            );
+            assert_eq!(to_b_token_round(42, &Decimal::ONE, rounding_mode)?, 42);
+            assert!(to_b_token_round(42, &Decimal::ZERO, rounding_mode).is_err());
+        }
+
+        assert!(to_b_token_round(u64::MAX, &Decimal::from("0.5"), RoundingMode::Ceil).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_b_token_round_trip_drift() -> Result<()> {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+        // (ratio, ceil(1 / ratio))
+        for (ratio, max_drift) in [
+            ("1", 1),
+            ("1.000000000000000001", 1),
+            ("1.05", 1),
+            ("1.5", 1),
+            ("2.718281828459045235", 1),
+            ("1000.123", 1),
+            ("0.999999999999999999", 1),
+            ("0.7", 2),
+            ("0.3", 4),
+        ] {
+            let ratio = Decimal::from(ratio);
+            let mut worst = 0;
+            for _ in 0..10_000 {
+                let amount = rng.next() >> (rng.next() % 64);
+                let Ok(underlying) = to_underlying(amount, &ratio) else {
+                    continue;
+                };
+                let round_trip = to_b_token(underlying, &ratio)?;
+                assert!(round_trip <= amount);
+                worst = worst.max(amount - round_trip);
+            }
+            assert!(worst <= max_drift, "ratio = {}, drift = {}", ratio, worst);
+        }
+
+        // The bound is reached: 1 btoken is 1.5 units, floored to 1 unit,
+        // which is worth 0.66.. btokens
+        let ratio = Decimal::from("1.5");
+        assert_eq!(to_b_token(to_underlying(1, &ratio)?, &ratio)?, 0);
+        // Below one, several btokens can floor to the same underlying amount
+        let ratio = Decimal::from("0.3");
+        assert_eq!(to_b_token(to_underlying(3, &ratio)?, &ratio)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_quote_new() {
+        let quote = get_quote(1_000, 1_000_000, true, 30, None).unwrap();
+        assert_eq!(quote, SwapQuote::new(1_000, 997_000, 600, 2_400, true));
+        assert_eq!(quote.clone(), quote);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_swap_quote_serde_round_trip() {
+        let quote = SwapQuote::new(1_000, 997_000, 600, 2_400, true);
+        let json = serde_json::to_string(&quote).unwrap();
+        assert_eq!(
+            json,
+            r#"{"amount_in":1000,"amount_out":997000,"protocol_fees":600,"pool_fees":2400,"a2b":true}"#
+        );
+        assert_eq!(serde_json::from_str::<SwapQuote>(&json).unwrap(), quote);
+    }
+
+    #[test]
+    fn test_compute_swap_fees_decimal() -> Result<()> {
+        // A single unit pays a fraction of a unit instead of a whole unit
+        assert_eq!(compute_swap_fees(1, 30, None)?, (1, 0));
+        assert_eq!(
+            compute_swap_fees_decimal(1, 30, None)?,
+            (Decimal::from("0.0006"), Decimal::from("0.0024"))
+        );
+
+        // Summing before rounding charges the exact fee
+        let (protocol_fees, pool_fees) = (0..1_000).try_fold(
+            (Decimal::ZERO, Decimal::ZERO),
+            |(protocol_total, pool_total), _| {
+                let (protocol_fees, pool_fees) = compute_swap_fees_decimal(1, 30, None)?;
+                Ok::<_, anyhow::Error>((
+                    protocol_total.checked_add(&protocol_fees).unwrap(),
+                    pool_total.checked_add(&pool_fees).unwrap(),
+                ))
+            },
+        )?;
+        assert_eq!(
+            (protocol_fees, pool_fees),
+            (Decimal::from("0.6"), Decimal::from("2.4"))
+        );
+
+        // Rounding up the exact fees gives at most the u64 fees
+        for (swap_fee_bps, override_num) in
+            [(0, None), (30, None), (30, Some(100)), (BPS_SCALE, None)]
+        {
+            for amount in [0, 1, 3, 999, 1_000_000, 123_456_789, u64::MAX] {
+                let (protocol_fees, pool_fees) =
+                    compute_swap_fees(amount, swap_fee_bps, override_num)?;
+                let (protocol_decimal, pool_decimal) =
+                    compute_swap_fees_decimal(amount, swap_fee_bps, override_num)?;
+                let total_decimal = protocol_decimal.checked_add(&pool_decimal).unwrap();
+
+                assert_eq!(
+                    total_decimal.checked_ceil::<u64>(),
+                    Some(protocol_fees + pool_fees)
+                );
+                assert!(protocol_decimal.checked_ceil::<u64>().unwrap() <= protocol_fees);
+            }
+        }
+
+        assert_eq!(
+            compute_swap_fees_decimal(1_000, 30, Some(BPS_SCALE + 1))
+                .unwrap_err()
+                .to_string(),
+            "Swap fee 10001 bps exceeds 10000 bps"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_decimal_quote() -> Result<()> {
+        // 30 bps of 1_000 is 3, of which the protocol takes 20%
+        assert_eq!(
+            get_decimal_quote(10, 1_000, true, 30)?,
+            DecimalSwapQuote {
+                amount_in: 10,
+                amount_out: Decimal::from("997"),
+                protocol_fees: Decimal::from("0.6"),
+                pool_fees: Decimal::from("2.4"),
+                a2b: true,
+            }
+        );
+
+        for fee_bps in [0, 1, 30, 100, 9_999, BPS_SCALE] {
+            for amount_out in [0, 1, 3, 999, 123_456_789, u64::MAX] {
+                let quote = get_quote(7, amount_out, false, fee_bps, None)?;
+                let decimal_quote = get_decimal_quote(7, amount_out, false, fee_bps)?;
+
+                assert_eq!(
+                    decimal_quote.amount_out.checked_floor(),
+                    Some(quote.amount_out)
+                );
+                assert_eq!(
+                    decimal_quote.total_fees().checked_ceil(),
+                    Some(quote.total_fees())
+                );
+                assert_eq!(
+                    decimal_quote
+                        .amount_out
+                        .checked_add(&decimal_quote.total_fees()),
+                    Some(Decimal::from(amount_out))
+                );
+            }
+        }
+
+        assert!(get_decimal_quote(7, 1_000, true, BPS_SCALE + 1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_effective_fee_bps() -> Result<()> {
+        assert_eq!(effective_fee_bps(30, None), 30);
+        // The larger override applies
+        assert_eq!(effective_fee_bps(30, Some(100)), 100);
+        // The smaller override is ignored
+        assert_eq!(effective_fee_bps(30, Some(5)), 30);
+        assert_eq!(effective_fee_bps(30, Some(30)), 30);
+        assert_eq!(effective_fee_bps(0, Some(0)), 0);
+
+        // Charging the effective fee without an override gives the same fees
+        for (swap_fee_bps, override_num) in [(30, Some(100)), (30, Some(5)), (100, None)] {
+            for amount in [0, 1, 999, 1_000_000, u64::MAX / BPS_SCALE] {
+                assert_eq!(
+                    compute_swap_fees(amount, swap_fee_bps, override_num)?,
+                    compute_swap_fees(amount, effective_fee_bps(swap_fee_bps, override_num), None)?
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_lp_fee_only() -> Result<()> {
+        for (swap_fee_bps, override_num) in [
+            (0, None),
+            (1, None),
+            (30, None),
+            (30, Some(100)),
+            (100, Some(5)),
+            (BPS_SCALE, None),
+        ] {
+            for amount in [0, 1, 3, 7, 999, 1_000_000, 123_456_789, u64::MAX] {
+                let (_, pool_fees) = compute_swap_fees(amount, swap_fee_bps, override_num)?;
+                assert_eq!(
+                    compute_lp_fee_only(amount, swap_fee_bps, override_num)?,
+                    pool_fees
+                );
+            }
+        }
+
+        // 30 bps of 1_000_000 is 3_000, of which the protocol takes 20%
+        assert_eq!(compute_lp_fee_only(1_000_000, 30, None)?, 2_400);
+        // A single unit of fee goes entirely to the protocol
+        assert_eq!(compute_lp_fee_only(1, 30, None)?, 0);
+
+        assert!(compute_lp_fee_only(1_000, BPS_SCALE + 1, None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_swap_fees_never_exceed_amount() -> Result<()> {
+        for rounding_mode in [
+            RoundingMode::Floor,
+            RoundingMode::Ceil,
+            RoundingMode::Nearest,
+        ] {
+            for amount in [0, 1, 3, 999, 1_000_000, u64::MAX] {
+                for override_num in [None, Some(0), Some(9_999), Some(BPS_SCALE)] {
+                    let (protocol_fees, pool_fees) =
+                        compute_swap_fees_rounded(amount, 30, override_num, rounding_mode)?;
+                    assert!(protocol_fees as u128 + pool_fees as u128 <= amount as u128);
+                }
+
+                // An override above 100% is rejected whatever the amount
+                for override_num in [BPS_SCALE + 1, 15_000, u64::MAX] {
+                    assert_eq!(
+                        compute_swap_fees_rounded(amount, 30, Some(override_num), rounding_mode)
+                            .unwrap_err()
+                            .to_string(),
+                        format!("Swap fee {} bps exceeds 10000 bps", override_num)
+                    );
+                }
+            }
+        }
+
+        // As is a default fee above 100%
+        assert!(compute_swap_fees(1_000, BPS_SCALE + 1, None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_quote_ord() {
+        let quote = |amount_in, amount_out, protocol_fees, pool_fees| {
+            SwapQuote::new(amount_in, amount_out, protocol_fees, pool_fees, true)
+        };
+
+        let mut quotes = vec![
+            quote(1_000, 990, 2, 8),
+            quote(1_000, 995, 1, 4),
+            // Same output for less in
+            quote(900, 990, 2, 8),
+            // Same output for lower fees
+            quote(1_000, 990, 1, 4),
+            // Same output and total fees, split differently
+            quote(1_000, 990, 3, 7),
+            quote(1_000, 980, 0, 0),
+        ];
+        quotes.sort();
+        assert_eq!(
+            quotes,
+            vec![
+                quote(1_000, 980, 0, 0),
+                quote(1_000, 990, 2, 8),
+                quote(1_000, 990, 3, 7),
+                quote(900, 990, 2, 8),
+                quote(1_000, 990, 1, 4),
+                quote(1_000, 995, 1, 4),
+            ]
+        );
+
+        assert_eq!(quotes.iter().max(), Some(&quote(1_000, 995, 1, 4)));
+        assert_eq!(quotes.iter().min(), Some(&quote(1_000, 980, 0, 0)));
+
+        // Consistent with Eq
+        for a in &quotes {
+            for b in &quotes {
+                assert_eq!(a.cmp(b) == std::cmp::Ordering::Equal, a == b);
+            }
+        }
+        let y2x = SwapQuote::new(1_000, 990, 2, 8, false);
+        assert_ne!(y2x.cmp(&quote(1_000, 990, 2, 8)), std::cmp::Ordering::Equal);
+    }
 }
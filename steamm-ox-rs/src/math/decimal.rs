@@ -11,9 +11,17 @@
 #![allow(missing_docs, clippy::missing_docs_in_private_items)]
 
 // use spl_math::{precise_number, uint::U256};
-use std::{convert::TryFrom, fmt};
+use std::{convert::TryFrom, fmt, str::FromStr};
 
-use crate::math::u256::U256;
+use anyhow::Result;
+
+use crate::{
+    BPS_SCALE,
+    math::{
+        RoundingMode, pow10,
+        u256::{U256, U512, U4096},
+    },
+};
 
 mod consts {
     /// Scale of precision.
@@ -26,10 +34,13 @@ mod consts {
 }
 
 /// Large decimal values, precise to 18 digits
-#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct Decimal(pub U256);
 
 impl Decimal {
+    pub const ZERO: Self = Self(U256([0, 0, 0, 0]));
+    pub const ONE: Self = Self(U256([consts::WAD, 0, 0, 0]));
+
     // OPTIMIZE: use const slice when fixed in BPF toolchain
     pub fn wad() -> U256 {
         U256::from(consts::WAD)
@@ -40,14 +51,49 @@ impl Decimal {
         U256::from(consts::HALF_WAD)
     }
 
-    pub fn from_scaled_val(scaled_val: u128) -> Self {
-        Self(U256::from(scaled_val))
+    /// Creates a decimal from a raw WAD-scaled value, e.g. one read back from
+    /// storage.
+    pub fn from_scaled<T: Into<U256>>(scaled_val: T) -> Self {
+        Self(scaled_val.into())
+    }
+
+    /// Returns the raw WAD-scaled value.
+    pub fn to_scaled(&self) -> U256 {
+        self.0
     }
 
     pub fn from_scaled_u256(scaled_val: U256) -> Self {
         Self(scaled_val)
     }
 
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_one(&self) -> bool {
+        self.0 == Self::wad()
+    }
+
+    /// Returns the scaled value as 32 little-endian bytes.
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        self.0.to_little_endian()
+    }
+
+    /// Reads a scaled value written by `to_le_bytes`.
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        Self(U256::from_little_endian(&bytes))
+    }
+
+    /// Returns the scaled value as 32 big-endian bytes.
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        self.0.to_big_endian()
+    }
+
+    /// Reads a scaled value written by `to_be_bytes`.
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        Self(U256::from_big_endian(&bytes))
+    }
+
     pub fn checked_add(self, rhs: &Self) -> Option<Self> {
         self.0.checked_add(rhs.0).map(Self)
     }
@@ -56,6 +102,11 @@ impl Decimal {
         self.0.checked_sub(rhs.0).map(Self)
     }
 
+    /// Subtracts `rhs`, clamping the result to zero instead of underflowing.
+    pub fn saturating_sub(self, rhs: &Self) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
     pub fn checked_div(self, rhs: &Self) -> Option<Self> {
         // Both the numerator `self.0` and the denominator `rhs.0` are scaled up
         // to 1E+18. Since we divide the numerator by the denominator we will
@@ -87,6 +138,44 @@ impl Decimal {
         }
     }
 
+    /// Same as `checked_div`, but computes `self * WAD / rhs` in a U512
+    /// intermediate so that no precision is lost when `self.0 * WAD` overflows
+    /// U256, which `checked_div` handles by rescaling. The result is rounded
+    /// down. Returns `None` on division by zero or if the result overflows.
+    pub fn checked_div_precise(self, rhs: &Self) -> Option<Self> {
+        if rhs.0.is_zero() {
+            return None;
+        }
+        let quotient = U512::from(self.0) * U512::from(Self::wad()) / U512::from(rhs.0);
+        U256::try_from(quotient).ok().map(Self)
+    }
+
+    /// Computes `self * mul / div` in a U512 with a single rescale, rounding
+    /// down once instead of after both the multiplication and the division.
+    /// Returns `None` if `div` is zero or the result does not fit in a U256.
+    pub fn checked_mul_div(&self, mul: &Self, div: &Self) -> Option<Self> {
+        if div.0.is_zero() {
+            return None;
+        }
+        let quotient = U512::from(self.0) * U512::from(mul.0) / U512::from(div.0);
+        U256::try_from(quotient).ok().map(Self)
+    }
+
+    /// Same as `checked_div_precise`, but rounds the result up to the next
+    /// multiple of 10^-18 when the quotient does not terminate within it.
+    pub fn checked_div_ceil(self, rhs: &Self) -> Option<Self> {
+        if rhs.0.is_zero() {
+            return None;
+        }
+        let numerator = U512::from(self.0) * U512::from(Self::wad());
+        let denominator = U512::from(rhs.0);
+        let mut quotient = numerator / denominator;
+        if !(numerator % denominator).is_zero() {
+            quotient += U512::one();
+        }
+        U256::try_from(quotient).ok().map(Self)
+    }
+
     pub fn checked_mul(self, rhs: &Self) -> Option<Self> {
         match self.0.checked_mul(rhs.0) {
             Some(v) => Some(Self(v.checked_div(Self::wad())?)),
@@ -106,6 +195,42 @@ impl Decimal {
         }
     }
 
+    /// Multiplies by an integer directly on the scaled value, without
+    /// converting `n` to a `Decimal` and rescaling by WAD.
+    pub fn checked_mul_int(&self, n: u64) -> Option<Self> {
+        self.0.checked_mul(U256::from(n)).map(Self)
+    }
+
+    /// Divides by an integer directly on the scaled value, without
+    /// converting `n` to a `Decimal` and rescaling by WAD. Rounds down.
+    pub fn checked_div_int(&self, n: u64) -> Option<Self> {
+        self.0.checked_div(U256::from(n)).map(Self)
+    }
+
+    /// Multiplies by `10^exponent`, e.g. to convert a whole token amount into
+    /// its smallest unit.
+    pub fn checked_mul_pow10(&self, exponent: u32) -> Option<Self> {
+        self.0.checked_mul(pow10(exponent)?).map(Self)
+    }
+
+    /// Divides by `10^exponent`, rounding down.
+    pub fn checked_div_pow10(&self, exponent: u32) -> Option<Self> {
+        // A divisor beyond U256 rounds every value down to zero
+        Some(pow10(exponent).map_or(Self(U256::zero()), |pow| Self(self.0 / pow)))
+    }
+
+    /// Adds an integer, scaling it by WAD once instead of building a
+    /// `Decimal` from it.
+    pub fn checked_add_int(&self, n: u64) -> Option<Self> {
+        self.0.checked_add(U256::from(n) * Self::wad()).map(Self)
+    }
+
+    /// Subtracts an integer, scaling it by WAD once instead of building a
+    /// `Decimal` from it.
+    pub fn checked_sub_int(&self, n: u64) -> Option<Self> {
+        self.0.checked_sub(U256::from(n) * Self::wad()).map(Self)
+    }
+
     /// Approximate the square root using Newton's method.
     ///
     /// Based on <https://docs.rs/spl-math/0.1.0/spl_math/precise_number/struct.PreciseNumber.html#method.sqrt>
@@ -119,6 +244,64 @@ impl Decimal {
         newtonian_root_approximation(self.clone(), two, guess)
     }
 
+    /// Returns `Σ(value * weight) / Σweight`, rounded down, e.g. for a time
+    /// weighted average price. The sums are computed in a U512 so the result
+    /// is exact up to rounding. Returns `None` if the weights sum to zero.
+    pub fn weighted_average(values: &[(Decimal, u64)]) -> Option<Self> {
+        let (weighted_sum, total_weight) = values.iter().fold(
+            (U512::zero(), U512::zero()),
+            |(weighted_sum, total_weight), (value, weight)| {
+                (
+                    weighted_sum + U512::from(value.0) * U512::from(*weight),
+                    total_weight + U512::from(*weight),
+                )
+            },
+        );
+        if total_weight.is_zero() {
+            return None;
+        }
+
+        // The average is at most the largest value, so it fits in a U256
+        U256::try_from(weighted_sum / total_weight).ok().map(Self)
+    }
+
+    /// Returns the geometric mean of the values, i.e. the nth root of their
+    /// product, rounded down. The scaled mean is the nth root of the product of
+    /// the scaled values, which is never rescaled by the WAD, so small values
+    /// do not underflow and the result is zero only if one of the values is.
+    /// The product is kept to 2048 significant bits, so the result is exact
+    /// for any number of values, except that a root within about 2^-1984 of
+    /// the next unit rounds up to it. Returns `None` for an empty slice.
+    pub fn geometric_mean(values: &[Decimal]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        if values.iter().any(|value| value.0.is_zero()) {
+            return Some(Self::ZERO);
+        }
+        let product = values
+            .iter()
+            .fold(TruncatedFloat::new(U4096::one()), |product, value| {
+                product.mul(TruncatedFloat::new(U4096::from(value.0)))
+            });
+
+        nth_root_floor(product, values.len() as u64).map(Self)
+    }
+
+    /// Returns the floor of the base 10 logarithm, e.g. 3 for 1000 and -3 for
+    /// 0.001, from the number of digits of the scaled value. Returns `None`
+    /// for zero.
+    pub fn checked_log10(&self) -> Option<i32> {
+        if self.0.is_zero() {
+            return None;
+        }
+        // Number of digits of the scaled value, minus one
+        let exponent = (1..)
+            .take_while(|&exponent| pow10(exponent).is_some_and(|pow| pow <= self.0))
+            .count() as i32;
+        Some(exponent - consts::SCALE as i32)
+    }
+
     pub fn checked_floor<T>(&self) -> Option<T>
     where
         T: TryFrom<U256>,
@@ -127,6 +310,13 @@ impl Decimal {
         T::try_from(ceil_val).ok()
     }
 
+    /// Converts the value into an integer amount of a token with `decimals`
+    /// decimals, rounding down, e.g. 1.5 is `1_500_000` at 6 decimals. Returns
+    /// `None` if the amount does not fit in a u64.
+    pub fn to_token_amount(&self, decimals: u32) -> Option<u64> {
+        self.checked_mul_pow10(decimals)?.checked_floor()
+    }
+
     pub fn checked_ceil<T>(&self) -> Option<T>
     where
         T: TryFrom<U256>,
@@ -169,6 +359,30 @@ impl Decimal {
         u64::try_from(rounded_val).ok()
     }
 
+    /// Rounds to an integer with `rounding_mode`. Returns `None` if the
+    /// result does not fit in a u64.
+    pub fn checked_round_with(&self, rounding_mode: RoundingMode) -> Option<u64> {
+        let quotient = self.0 / Self::wad();
+        let remainder = self.0 % Self::wad();
+        let round_up = match rounding_mode {
+            RoundingMode::Floor => false,
+            RoundingMode::Ceil => !remainder.is_zero(),
+            RoundingMode::Nearest => remainder >= Self::half_wad(),
+            RoundingMode::NearestEven => match remainder.cmp(&Self::half_wad()) {
+                std::cmp::Ordering::Less => false,
+                std::cmp::Ordering::Equal => quotient.bit(0),
+                std::cmp::Ordering::Greater => true,
+            },
+        };
+
+        let rounded_val = if round_up {
+            quotient + U256::one()
+        } else {
+            quotient
+        };
+        u64::try_from(rounded_val).ok()
+    }
+
     /// If the difference between self and other is less than 10^(dec_places -
     /// precision), return true.
     ///
@@ -176,13 +390,62 @@ impl Decimal {
     /// If we have 18 decimal places, than having precision of 6 would mean that
     /// any difference beyond 12th dec place is considered as equal.
     pub fn almost_eq(&self, other: &Self, precision: u32) -> bool {
-        let precision = Self::from_scaled_val(10u128.pow(precision));
+        let precision = Self::from_scaled(10u128.pow(precision));
         match self.cmp(other) {
             std::cmp::Ordering::Equal => true,
-            std::cmp::Ordering::Less => other.clone().checked_sub(self).unwrap() < precision,
-            std::cmp::Ordering::Greater => self.clone().checked_sub(other).unwrap() < precision,
+            std::cmp::Ordering::Less => other.saturating_sub(self) < precision,
+            std::cmp::Ordering::Greater => self.saturating_sub(other) < precision,
         }
     }
+
+    /// Returns `percent / 100`, e.g. `from_percent(5)` is 0.05.
+    pub fn from_percent(percent: u64) -> Self {
+        Self(U256::from(percent) * Self::wad() / U256::from(100u8))
+    }
+
+    /// Returns `bps / 10_000`, e.g. `from_bps(30)` is 0.003.
+    pub fn from_bps(bps: u64) -> Self {
+        Self(U256::from(bps) * Self::wad() / U256::from(BPS_SCALE))
+    }
+
+    /// Returns the value in basis points, rounded down and saturating at
+    /// `u64::MAX`. Inverse of `from_bps`.
+    pub fn to_bps(&self) -> u64 {
+        self.checked_mul_int(BPS_SCALE)
+            .and_then(|bps| bps.checked_floor())
+            .unwrap_or(u64::MAX)
+    }
+
+    /// Returns true if `|self - other| <= other * bps / 10_000`, i.e. if self
+    /// is within `bps` basis points of other.
+    pub fn within_bps(&self, other: &Self, bps: u64) -> bool {
+        let diff = if self.0 >= other.0 {
+            self.0 - other.0
+        } else {
+            other.0 - self.0
+        };
+
+        diff.saturating_mul(U256::from(BPS_SCALE)) <= other.0.saturating_mul(U256::from(bps))
+    }
+
+    /// Returns the larger of `x` and `y`, like `FixedPoint64::max`.
+    pub fn max(x: Self, y: Self) -> Self {
+        Ord::max(x, y)
+    }
+
+    /// Returns the smaller of `x` and `y`, like `FixedPoint64::min`.
+    pub fn min(x: Self, y: Self) -> Self {
+        Ord::min(x, y)
+    }
+
+    /// Restricts the value to the inclusive range `[min, max]`.
+    ///
+    /// # Panics
+    /// Panics if `min > max`.
+    pub fn clamp(self, min: &Self, max: &Self) -> Self {
+        assert!(min <= max, "Invalid clamp range: {} > {}", min, max);
+        Self::min(Self::max(self, *min), *max)
+    }
 }
 
 impl From<u64> for Decimal {
@@ -199,29 +462,70 @@ impl From<u128> for Decimal {
 
 impl From<&str> for Decimal {
     /// Converts a decimal string to U60x18 by scaling it up by 1e18.
+    ///
+    /// Panics if the string is not a valid decimal, see `FromStr`.
     fn from(value: &str) -> Self {
-        // Split the value into integer and fractional parts
-        let parts: Vec<&str> = value.split('.').collect();
-
-        let integer_part = parts[0];
-        let fractional_part = if parts.len() > 1 { parts[1] } else { "0" };
+        value.parse().unwrap()
+    }
+}
 
-        // Parse integer part
-        let integer_value = U256::from_dec_str(integer_part).unwrap();
-        let mut result = integer_value * consts::WAD;
+impl FromStr for Decimal {
+    type Err = anyhow::Error;
 
-        // Parse fractional part and scale it appropriately
-        let mut fractional_value = U256::from(0);
-        let scale_factor = 10u64.pow(fractional_part.len() as u32);
+    /// Parses plain (`"3.5"`) or scientific (`"3.5e9"`, `"1E-6"`) decimal
+    /// notation. Digits beyond the 18 decimal places supported are truncated.
+    fn from_str(value: &str) -> Result<Self> {
+        let (mantissa, exponent) = match value.find(['e', 'E']) {
+            Some(index) => (&value[..index], Some(&value[index + 1..])),
+            None => (value, None),
+        };
 
-        if let Ok(parsed_fractional_value) = U256::from_dec_str(fractional_part) {
-            fractional_value = (parsed_fractional_value * consts::WAD) / U256::from(scale_factor);
+        let (integer_part, fractional_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if integer_part.len() + fractional_part.len() == 0
+            || !is_digits(integer_part)
+            || !is_digits(fractional_part)
+        {
+            return Err(anyhow::anyhow!("Invalid decimal: {:?}", value));
         }
 
-        // Combine integer and fractional parts
-        result += fractional_value;
+        let exponent = match exponent {
+            Some(exponent) => {
+                let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+                if digits.is_empty() || !is_digits(digits) {
+                    return Err(anyhow::anyhow!("Invalid exponent: {:?}", value));
+                }
+                exponent
+                    .parse::<i64>()
+                    .map_err(|_| anyhow::anyhow!("Exponent out of range: {:?}", value))?
+            }
+            None => 0,
+        };
+
+        // value = digits * 10^(exponent - fractional digits), scaled by WAD
+        let digits = U256::from_dec_str(&[integer_part, fractional_part].concat())
+            .map_err(|_| anyhow::anyhow!("Decimal overflow: {:?}", value))?;
+        let shift = exponent
+            .checked_add(consts::SCALE as i64)
+            .and_then(|shift| shift.checked_sub(fractional_part.len() as i64))
+            .ok_or_else(|| anyhow::anyhow!("Exponent out of range: {:?}", value))?;
 
-        Decimal(result)
+        let scaled_val = if digits.is_zero() {
+            digits
+        } else if shift >= 0 {
+            U256::from(10u8)
+                .checked_pow(U256::from(shift))
+                .and_then(|pow| digits.checked_mul(pow))
+                .ok_or_else(|| anyhow::anyhow!("Decimal overflow: {:?}", value))?
+        } else {
+            match U256::from(10u8).checked_pow(U256::from(shift.unsigned_abs())) {
+                Some(pow) => digits / pow,
+                // The divisor exceeds U256, so the value rounds down to zero
+                None => U256::zero(),
+            }
+        };
+
+        Ok(Decimal(scaled_val))
     }
 }
 
@@ -238,6 +542,105 @@ impl fmt::Display for Decimal {
     }
 }
 
+/// A non-negative `mantissa * 2^exponent`, whose mantissa is truncated to
+/// `MANTISSA_BITS` so that the product of two mantissas fits in a U4096.
+#[derive(Clone, Copy, Debug)]
+struct TruncatedFloat {
+    mantissa: U4096,
+    exponent: u64,
+}
+
+impl TruncatedFloat {
+    const MANTISSA_BITS: u64 = 2048;
+    /// Low bits ignored by `le` once a value has been truncated. Each product
+    /// rounds down by less than one unit in the last place, so this covers the
+    /// error of up to 2^64 products.
+    const GUARD_BITS: u64 = 64;
+
+    fn new(value: U4096) -> Self {
+        Self {
+            mantissa: value,
+            exponent: 0,
+        }
+        .truncated()
+    }
+
+    fn truncated(self) -> Self {
+        let excess = (self.mantissa.bits() as u64).saturating_sub(Self::MANTISSA_BITS);
+        Self {
+            mantissa: self.mantissa >> excess as usize,
+            exponent: self.exponent + excess,
+        }
+    }
+
+    fn bits(&self) -> u64 {
+        self.mantissa.bits() as u64 + self.exponent
+    }
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            mantissa: self.mantissa * other.mantissa,
+            exponent: self.exponent + other.exponent,
+        }
+        .truncated()
+    }
+
+    fn pow(self, mut exp: u64) -> Self {
+        let mut base = self;
+        let mut result = Self::new(U4096::one());
+        while exp > 0 {
+            if exp % 2 == 1 {
+                result = result.mul(base);
+            }
+            base = base.mul(base);
+            exp /= 2;
+        }
+        result
+    }
+
+    /// Returns whether `self <= other`. Exact if neither value was truncated,
+    /// and otherwise ignores the last `GUARD_BITS` of the larger exponent, so
+    /// that equal values truncated along different paths still compare equal.
+    fn le(&self, other: &Self) -> bool {
+        let exponent = self.exponent.max(other.exponent);
+        if exponent == 0 {
+            return self.mantissa <= other.mantissa;
+        }
+        let shift = exponent + Self::GUARD_BITS;
+        let aligned = |value: &Self| {
+            let shift = shift - value.exponent;
+            if shift >= 4096 {
+                U4096::zero()
+            } else {
+                value.mantissa >> shift as usize
+            }
+        };
+        aligned(self) <= aligned(other)
+    }
+}
+
+/// Returns the largest `root` below 2^256 with `root^n <= product`, by
+/// bisection, or `None` if the root is at least 2^256.
+fn nth_root_floor(product: TruncatedFloat, n: u64) -> Option<U256> {
+    // With c = ceil(bits / n), the root is in [2^(c - 1), 2^c)
+    let root_bits = product.bits().div_ceil(n);
+    if root_bits > 256 {
+        return None;
+    }
+    let mut low = U4096::one() << (root_bits as usize - 1);
+    let mut high = U4096::one() << root_bits as usize;
+    while high - low > U4096::one() {
+        let mid = (low + high) >> 1;
+        if TruncatedFloat::new(mid).pow(n).le(&product) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    U256::try_from(low).ok()
+}
+
 /// Approximate the nth root of a number using Newton's method
 /// <https://en.wikipedia.org/wiki/Newton%27s_method>
 /// NOTE: this function is private because its accurate range and precision
@@ -283,3 +686,770 @@ fn newtonian_root_approximation(
 
     Some(guess)
 }
+
+/// Serialized as a decimal string, e.g. `"3.41"`, so that no precision is lost
+/// in formats with floating point numbers such as JSON.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Decimal {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Decimal {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Generates values whose scaled representation fits in a u128, i.e. up to
+/// about 3.4e20, so that the product of any two generated values fits.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Decimal {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::from_scaled(u.arbitrary::<u128>()?))
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        u128::size_hint(depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scaled_round_trip() {
+        let values = [
+            Decimal::from(0u64),
+            Decimal::from(1u64),
+            Decimal::from("0.000000000000000001"),
+            Decimal::from("3.141592653589793238"),
+            Decimal::from(u64::MAX),
+            Decimal::from_scaled_u256(U256::MAX),
+        ];
+
+        for x in values {
+            assert_eq!(Decimal::from_scaled(x.to_scaled()), x);
+        }
+
+        assert_eq!(
+            Decimal::from_scaled(1_500_000_000_000_000_000u128),
+            Decimal::from("1.5")
+        );
+        assert_eq!(
+            Decimal::from("1.5").to_scaled(),
+            U256::from(1_500_000_000_000_000_000u128)
+        );
+    }
+
+    #[test]
+    fn test_within_bps() {
+        let reference = Decimal::from("100");
+
+        // 30 bps of 100 is 0.3
+        assert!(Decimal::from("100.3").within_bps(&reference, 30));
+        assert!(Decimal::from("99.7").within_bps(&reference, 30));
+        assert!(!Decimal::from("100.300000000000000001").within_bps(&reference, 30));
+        assert!(!Decimal::from("99.699999999999999999").within_bps(&reference, 30));
+
+        assert!(reference.within_bps(&reference, 0));
+        assert!(!Decimal::from("100.000000000000000001").within_bps(&reference, 0));
+
+        // The tolerance is relative to `other`
+        assert!(Decimal::from("0").within_bps(&reference, 10_000));
+        assert!(!Decimal::from("3").within_bps(&Decimal::from("1"), 10_000));
+        assert!(Decimal::from("2").within_bps(&Decimal::from("1"), 10_000));
+    }
+
+    #[test]
+    fn test_hash_map_key() {
+        use std::collections::HashMap;
+
+        let prices = ["0.000001", "1", "1.5", "3.41234567", "67890.12345678"];
+        let mut map = HashMap::new();
+        for (i, price) in prices.iter().enumerate() {
+            map.insert(Decimal::from(*price), i);
+        }
+
+        assert_eq!(map.len(), prices.len());
+        for (i, price) in prices.iter().enumerate() {
+            assert_eq!(map.get(&Decimal::from(*price)), Some(&i));
+        }
+
+        // Equal values hash equally regardless of how they were built
+        assert_eq!(map.get(&Decimal::from(1u64)), Some(&1));
+        assert_eq!(map.get(&Decimal::from("2")), None);
+    }
+
+    #[test]
+    fn test_checked_mul_div_int() {
+        let values = [
+            "0",
+            "0.000000000000000001",
+            "1",
+            "3.41234567",
+            "123456.789012345678",
+        ];
+        let ints = [0u64, 1, 7, 1_000_000_000, u64::MAX];
+
+        for value in values {
+            let value = Decimal::from(value);
+            for n in ints {
+                assert_eq!(
+                    value.checked_mul_int(n),
+                    value.checked_mul(&Decimal::from(n))
+                );
+                if n != 0 {
+                    assert_eq!(
+                        value.checked_div_int(n),
+                        value.checked_div(&Decimal::from(n))
+                    );
+                }
+            }
+        }
+
+        assert_eq!(Decimal::from(1u64).checked_div_int(0), None);
+        assert_eq!(
+            Decimal::from_scaled_u256(U256::MAX).checked_mul_int(2),
+            None
+        );
+
+        // Large values overflow the WAD rescaling in `checked_mul` and
+        // `checked_div`, which then drop the fractional digits
+        let large = Decimal::from("1000000000000000000000000000000.123456789012345678");
+        let n = 10_000_000_000_000_000_000;
+
+        let product = Decimal::from("10000000000000000000000000000001234567890123456780");
+        assert_eq!(large.checked_mul_int(n), Some(product));
+        assert_ne!(large.checked_mul(&Decimal::from(n)), Some(product));
+
+        assert_eq!(product.checked_div_int(n), Some(large));
+        assert_ne!(product.checked_div(&Decimal::from(n)), Some(large));
+    }
+
+    #[test]
+    fn test_from_str_scientific_notation() -> Result<()> {
+        assert_eq!("1e18".parse::<Decimal>()?, Decimal::from(10u64.pow(18)));
+        assert_eq!("1E18".parse::<Decimal>()?, Decimal::from(10u64.pow(18)));
+        assert_eq!("1e+18".parse::<Decimal>()?, Decimal::from(10u64.pow(18)));
+        assert_eq!("2.5e-3".parse::<Decimal>()?, Decimal::from("0.0025"));
+        assert_eq!("1e-6".parse::<Decimal>()?, Decimal::from("0.000001"));
+        assert_eq!("3.5E9".parse::<Decimal>()?, Decimal::from(3_500_000_000u64));
+        assert_eq!("0.00035e4".parse::<Decimal>()?, Decimal::from("3.5"));
+        assert_eq!("1e-18".parse::<Decimal>()?, Decimal::from_scaled(1u64));
+
+        // Beyond WAD precision is truncated
+        assert_eq!("1e-19".parse::<Decimal>()?, Decimal::from(0u64));
+        assert_eq!("1e-999999".parse::<Decimal>()?, Decimal::from(0u64));
+        assert_eq!(
+            "1e-9223372036854775808".parse::<Decimal>()?,
+            Decimal::from(0u64)
+        );
+
+        // Exponents whose shift does not fit in an i64 are rejected
+        for value in [
+            "1e9223372036854775807",
+            "1e9223372036854775790",
+            "0.0000000000000000001e-9223372036854775808",
+        ] {
+            let err = value.parse::<Decimal>().unwrap_err();
+            assert!(err.to_string().contains("Exponent out of range"), "{err}");
+        }
+        assert_eq!("0e999999".parse::<Decimal>()?, Decimal::from(0u64));
+
+        for invalid in [
+            "1e", "1e+", "1e-", "e5", "1e5.5", "1e1e1", "1.2.3", "", ".", "-1", "1x",
+        ] {
+            assert!(invalid.parse::<Decimal>().is_err(), "{:?}", invalid);
+        }
+        assert!("1e99999999999999999999".parse::<Decimal>().is_err());
+        assert!("1e60".parse::<Decimal>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_geometric_mean() {
+        let mean = |values: &[&str]| {
+            let values: Vec<Decimal> = values.iter().map(|v| Decimal::from(*v)).collect();
+            Decimal::geometric_mean(&values)
+        };
+
+        assert!(
+            mean(&["4", "9"])
+                .unwrap()
+                .almost_eq(&Decimal::from(6u64), 3)
+        );
+        assert!(
+            mean(&["1", "1", "1"])
+                .unwrap()
+                .almost_eq(&Decimal::from(1u64), 3)
+        );
+        assert!(
+            mean(&["2", "4", "8"])
+                .unwrap()
+                .almost_eq(&Decimal::from(4u64), 3)
+        );
+        assert!(
+            mean(&["0.5", "0.02"])
+                .unwrap()
+                .almost_eq(&Decimal::from("0.1"), 3)
+        );
+        assert_eq!(mean(&["3.5"]), Some(Decimal::from("3.5")));
+        assert_eq!(mean(&["4", "0"]), Some(Decimal::from(0u64)));
+        assert_eq!(mean(&[]), None);
+
+        // Exact for perfect powers, and rounded down otherwise
+        assert_eq!(mean(&["4", "9"]), Some(Decimal::from(6u64)));
+        assert_eq!(mean(&["2", "4", "8"]), Some(Decimal::from(4u64)));
+        assert_eq!(
+            mean(&["2", "1"]),
+            Some(Decimal::from("1.414213562373095048"))
+        );
+
+        // Many values
+        assert_eq!(mean(&["1000"; 10]), Some(Decimal::from(1000u64)));
+        assert_eq!(
+            mean(&[
+                "1", "2", "4", "8", "16", "32", "64", "128", "256", "512", "1024"
+            ]),
+            Some(Decimal::from(32u64))
+        );
+        assert_eq!(mean(&["65000.5"; 16]), Some(Decimal::from("65000.5")));
+        let prices = ["0.9998", "1.0001", "1.0003", "0.9999", "1.0002", "0.9997"];
+        let mean_price = mean(&prices.repeat(2)).unwrap();
+        assert!(mean_price.within_bps(&Decimal::from(1u64), 1));
+        assert!(mean_price < Decimal::from("1.0003"));
+        assert!(mean_price > Decimal::from("0.9997"));
+
+        // Very small values do not underflow
+        assert_eq!(
+            mean(&["0.000000001"; 3]),
+            Some(Decimal::from("0.000000001"))
+        );
+        assert_eq!(
+            mean(&["0.000000000000000001", "1"]),
+            Some(Decimal::from("0.000000001"))
+        );
+        assert_eq!(
+            mean(&["0.000000000000000001"; 50]),
+            Some(Decimal::from("0.000000000000000001"))
+        );
+        assert_eq!(
+            mean(&["0.000000000000000001", "0.000000000000000002"]),
+            Some(Decimal::from("0.000000000000000001"))
+        );
+
+        // Products beyond 2048 bits are truncated but stay exact
+        assert_eq!(mean(&["1"; 68]), Some(Decimal::from(1u64)));
+        assert_eq!(mean(&["1"; 69]), Some(Decimal::from(1u64)));
+        assert_eq!(mean(&["3.41"; 1_000]), Some(Decimal::from("3.41")));
+        assert_eq!(
+            mean(&["100000000000000000000000000000000000000000"; 500]),
+            Some(Decimal::from("100000000000000000000000000000000000000000"))
+        );
+        assert_eq!(mean(&["2", "8"].repeat(300)), Some(Decimal::from(4u64)));
+        assert_eq!(
+            mean(&["2", "1"].repeat(100)),
+            Some(Decimal::from("1.414213562373095048"))
+        );
+        let mean_price = mean(&prices.repeat(20)).unwrap();
+        assert!(mean_price.within_bps(&Decimal::from(1u64), 1));
+
+        // The mean must fit in a Decimal
+        let max = Decimal::from_scaled_u256(U256::MAX).to_string();
+        assert_eq!(
+            mean(&[max.as_str(), max.as_str()]),
+            Some(Decimal::from_scaled_u256(U256::MAX))
+        );
+    }
+
+    #[test]
+    fn test_consts() {
+        assert_eq!(Decimal::ONE, Decimal::from(1u64));
+        assert_eq!(Decimal::ZERO, Decimal::from(0u64));
+    }
+
+    #[test]
+    fn test_checked_div_precise() {
+        // Below the boundary both paths agree
+        for (lhs, rhs) in [
+            ("1", "3"),
+            ("123456.789", "0.001"),
+            ("2", "7.5"),
+            ("0", "5"),
+        ] {
+            let (lhs, rhs) = (Decimal::from(lhs), Decimal::from(rhs));
+            assert_eq!(lhs.checked_div_precise(&rhs), lhs.checked_div(&rhs));
+        }
+
+        // Just below the boundary, `self.0 * WAD` still fits in U256
+        let boundary = U256::MAX / Decimal::wad();
+        let lhs = Decimal::from_scaled_u256(boundary);
+        let rhs = Decimal::from(3u64);
+        assert_eq!(lhs.checked_div_precise(&rhs), lhs.checked_div(&rhs));
+
+        // Above the boundary `checked_div` drops the fractional digits
+        let lhs = Decimal::from_scaled_u256(boundary + 1);
+        let precise = lhs.checked_div_precise(&rhs).unwrap();
+        let lossy = lhs.checked_div(&rhs).unwrap();
+        assert_eq!(precise.0, (boundary + 1) / 3);
+        assert_eq!(lossy.0 % Decimal::wad(), U256::zero());
+        assert!(lossy < precise);
+
+        let lhs = Decimal::from("1000000000000000000000000000000000000000000.5");
+        assert_eq!(
+            lhs.checked_div_precise(&Decimal::from(2u64)),
+            Some(Decimal::from(
+                "500000000000000000000000000000000000000000.25"
+            ))
+        );
+        assert_eq!(
+            lhs.checked_div(&Decimal::from(2u64)),
+            Some(Decimal::from("500000000000000000000000000000000000000000"))
+        );
+
+        assert_eq!(Decimal::ONE.checked_div_precise(&Decimal::ZERO), None);
+        assert_eq!(
+            Decimal::from_scaled_u256(U256::MAX).checked_div_precise(&Decimal::from("0.5")),
+            None
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        for value in [
+            "0",
+            "1",
+            "3.41234567",
+            "0.000000000000000001",
+            "123456789.5",
+        ] {
+            let decimal = Decimal::from(value);
+            let json = serde_json::to_string(&decimal).unwrap();
+            assert_eq!(json, format!("\"{}\"", decimal));
+            assert_eq!(serde_json::from_str::<Decimal>(&json).unwrap(), decimal);
+        }
+
+        assert_eq!(
+            serde_json::from_str::<Decimal>("\"2.5e-3\"").unwrap(),
+            Decimal::from("0.0025")
+        );
+        assert!(serde_json::from_str::<Decimal>("\"abc\"").is_err());
+        assert!(serde_json::from_str::<Decimal>("1.5").is_err());
+    }
+
+    #[test]
+    fn test_saturating_sub() {
+        let (small, large) = (Decimal::from("1.5"), Decimal::from("2.25"));
+
+        assert_eq!(large.saturating_sub(&small), Decimal::from("0.75"));
+        assert_eq!(small.saturating_sub(&large), Decimal::ZERO);
+        assert_eq!(small.saturating_sub(&small), Decimal::ZERO);
+        assert_eq!(Decimal::ZERO.saturating_sub(&Decimal::ONE), Decimal::ZERO);
+        assert_eq!(small.checked_sub(&large), None);
+
+        assert!(small.almost_eq(&Decimal::from("1.500000000000000999"), 3));
+        assert!(Decimal::from("1.500000000000000999").almost_eq(&small, 3));
+        assert!(!small.almost_eq(&large, 3));
+    }
+
+    #[test]
+    fn test_from_str_overflow() -> Result<()> {
+        // The largest integer part that can be scaled by WAD
+        let max_integer = U256::MAX / Decimal::wad();
+        let max = max_integer.to_string().parse::<Decimal>()?;
+        assert_eq!(max.0, max_integer * Decimal::wad());
+        assert_eq!(
+            Decimal::from_scaled_u256(U256::MAX)
+                .to_string()
+                .parse::<Decimal>()?
+                .0,
+            U256::MAX
+        );
+
+        let err = (max_integer + 1)
+            .to_string()
+            .parse::<Decimal>()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Decimal overflow"));
+
+        // Integer parts that do not fit in U256 at all
+        let err = format!("{}0", U256::MAX).parse::<Decimal>().unwrap_err();
+        assert!(err.to_string().starts_with("Decimal overflow"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clamp() {
+        let min = Decimal::from("0.5");
+        let max = Decimal::from("2");
+
+        assert_eq!(Decimal::from("0.1").clamp(&min, &max), min);
+        assert_eq!(Decimal::ZERO.clamp(&min, &max), min);
+        assert_eq!(Decimal::ONE.clamp(&min, &max), Decimal::ONE);
+        assert_eq!(min.clamp(&min, &max), min);
+        assert_eq!(max.clamp(&min, &max), max);
+        assert_eq!(Decimal::from("2.000000000000000001").clamp(&min, &max), max);
+        assert_eq!(Decimal::from(100u64).clamp(&min, &max), max);
+
+        // A degenerate range pins every value
+        assert_eq!(
+            Decimal::from(100u64).clamp(&Decimal::ONE, &Decimal::ONE),
+            Decimal::ONE
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid clamp range")]
+    fn test_clamp_rejects_inverted_range() {
+        Decimal::ONE.clamp(&Decimal::from(2u64), &Decimal::ONE);
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let max = Decimal::from_scaled_u256(U256::MAX);
+        for value in [
+            Decimal::ZERO,
+            Decimal::ONE,
+            Decimal::from("1234.000000000000000001"),
+            max,
+        ] {
+            assert_eq!(Decimal::from_le_bytes(value.to_le_bytes()), value);
+            assert_eq!(Decimal::from_be_bytes(value.to_be_bytes()), value);
+        }
+
+        // The bytes hold the WAD-scaled value
+        let mut le = [0u8; 32];
+        le[..8].copy_from_slice(&1_000_000_000_000_000_000u64.to_le_bytes());
+        assert_eq!(Decimal::ONE.to_le_bytes(), le);
+        le.reverse();
+        assert_eq!(Decimal::ONE.to_be_bytes(), le);
+        assert_eq!(max.to_le_bytes(), [u8::MAX; 32]);
+    }
+
+    #[test]
+    fn test_checked_div_ceil() {
+        let one = Decimal::ONE;
+        let three = Decimal::from(3u64);
+
+        let floor = one.checked_div(&three).unwrap();
+        let ceil = one.checked_div_ceil(&three).unwrap();
+        assert_eq!(floor, Decimal::from("0.333333333333333333"));
+        assert_eq!(ceil, Decimal::from("0.333333333333333334"));
+        assert_eq!(ceil.0 - floor.0, U256::one());
+
+        // Terminating quotients are not rounded
+        assert_eq!(
+            Decimal::from(3u64).checked_div_ceil(&Decimal::from(4u64)),
+            Some(Decimal::from("0.75"))
+        );
+        assert_eq!(Decimal::ZERO.checked_div_ceil(&three), Some(Decimal::ZERO));
+
+        assert_eq!(one.checked_div_ceil(&Decimal::ZERO), None);
+        assert_eq!(
+            Decimal::from_scaled_u256(U256::MAX).checked_div_ceil(&Decimal::from("0.5")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_zero_and_is_one() {
+        assert!(Decimal::ZERO.is_zero());
+        assert!(!Decimal::ZERO.is_one());
+        assert!(Decimal::ONE.is_one());
+        assert!(!Decimal::ONE.is_zero());
+        assert!(!Decimal::from_scaled(1u64).is_zero());
+        assert!(!Decimal::from("1.000000000000000001").is_one());
+        assert!(Decimal::from("1.0").is_one());
+    }
+
+    #[test]
+    fn test_checked_add_sub_int() {
+        let values = [
+            "0",
+            "0.000000000000000001",
+            "1",
+            "3.41234567",
+            "123456.789012345678",
+        ];
+        let ints = [0u64, 1, 7, 1_000_000_000, u64::MAX];
+
+        for value in values {
+            let value = Decimal::from(value);
+            for n in ints {
+                assert_eq!(
+                    value.checked_add_int(n),
+                    value.checked_add(&Decimal::from(n))
+                );
+                assert_eq!(
+                    value.checked_sub_int(n),
+                    value.checked_sub(&Decimal::from(n))
+                );
+            }
+        }
+
+        assert_eq!(Decimal::ONE.checked_sub_int(2), None);
+        assert_eq!(
+            Decimal::from_scaled_u256(U256::MAX).checked_add_int(1),
+            None
+        );
+    }
+
+    #[test]
+    fn test_checked_round_with() {
+        let round =
+            |value: &str, rounding_mode| Decimal::from(value).checked_round_with(rounding_mode);
+
+        assert_eq!(round("2.5", RoundingMode::Nearest), Some(3));
+        assert_eq!(round("3.5", RoundingMode::Nearest), Some(4));
+        assert_eq!(round("2.5", RoundingMode::NearestEven), Some(2));
+        assert_eq!(round("3.5", RoundingMode::NearestEven), Some(4));
+
+        // Only exact halves are affected
+        assert_eq!(
+            round("2.500000000000000001", RoundingMode::NearestEven),
+            Some(3)
+        );
+        assert_eq!(
+            round("2.499999999999999999", RoundingMode::Nearest),
+            Some(2)
+        );
+        assert_eq!(round("2.3", RoundingMode::Floor), Some(2));
+        assert_eq!(round("2.3", RoundingMode::Ceil), Some(3));
+        assert_eq!(round("2", RoundingMode::Ceil), Some(2));
+
+        // `checked_round` stays half up
+        for value in ["0.5", "1.5", "2.5", "2.4", "7"] {
+            assert_eq!(
+                Decimal::from(value).checked_round(),
+                round(value, RoundingMode::Nearest)
+            );
+        }
+
+        assert_eq!(
+            Decimal::from(u64::MAX).checked_round_with(RoundingMode::Nearest),
+            Some(u64::MAX)
+        );
+        assert_eq!(
+            Decimal::from(u64::MAX)
+                .checked_add(&Decimal::from("0.5"))
+                .unwrap()
+                .checked_round_with(RoundingMode::Nearest),
+            None
+        );
+    }
+
+    #[test]
+    fn test_percent_and_bps() {
+        assert_eq!(Decimal::from_percent(5), Decimal::from("0.05"));
+        assert_eq!(Decimal::from_percent(100), Decimal::ONE);
+        assert_eq!(Decimal::from_percent(250), Decimal::from("2.5"));
+        assert_eq!(Decimal::from_bps(30), Decimal::from("0.003"));
+        assert_eq!(Decimal::from_bps(BPS_SCALE), Decimal::ONE);
+        assert_eq!(Decimal::from_percent(20), Decimal::from_bps(2000));
+
+        for bps in [0, 1, 30, 2000, BPS_SCALE, u64::MAX] {
+            assert_eq!(Decimal::from_bps(bps).to_bps(), bps);
+        }
+
+        // Rounds down and saturates
+        assert_eq!(Decimal::from("0.00309").to_bps(), 30);
+        assert_eq!(Decimal::from("0.000099").to_bps(), 0);
+        assert_eq!(Decimal::from(u64::MAX).to_bps(), u64::MAX);
+    }
+
+    #[test]
+    fn test_min_max() {
+        let (a, b) = (Decimal::from("1.5"), Decimal::from("2"));
+        assert_eq!(Decimal::max(a, b), b);
+        assert_eq!(Decimal::max(b, a), b);
+        assert_eq!(Decimal::min(a, b), a);
+        assert_eq!(Decimal::min(b, a), a);
+        assert_eq!(Decimal::max(a, a), a);
+        assert_eq!(Decimal::min(Decimal::from(0u64), a), Decimal::from(0u64));
+
+        // Method syntax still goes through `Ord`
+        assert_eq!(a.max(b), Decimal::max(a, b));
+        assert_eq!(a.min(b), Decimal::min(a, b));
+    }
+
+    #[test]
+    fn test_to_token_amount() {
+        let value = Decimal::from("1.5");
+        assert_eq!(value.to_token_amount(0), Some(1));
+        assert_eq!(value.to_token_amount(6), Some(1_500_000));
+        assert_eq!(value.to_token_amount(9), Some(1_500_000_000));
+        assert_eq!(value.to_token_amount(18), Some(1_500_000_000_000_000_000));
+
+        // Rounds down below the token's smallest unit
+        assert_eq!(Decimal::from("0.1234567").to_token_amount(6), Some(123_456));
+        assert_eq!(Decimal::from("0.0000009").to_token_amount(6), Some(0));
+
+        assert_eq!(Decimal::from(u64::MAX).to_token_amount(0), Some(u64::MAX));
+        assert_eq!(Decimal::from(u64::MAX).to_token_amount(1), None);
+        assert_eq!(value.to_token_amount(19), Some(15_000_000_000_000_000_000));
+        assert_eq!(value.to_token_amount(20), None);
+        assert_eq!(value.to_token_amount(u32::MAX), None);
+    }
+
+    #[test]
+    fn test_mul_div_pow10() {
+        let value = Decimal::from("1.5");
+        assert_eq!(
+            value.checked_mul_pow10(6),
+            Some(Decimal::from(1_500_000u64))
+        );
+        assert_eq!(value.checked_div_pow10(2), Some(Decimal::from("0.015")));
+        assert_eq!(value.checked_mul_pow10(0), Some(value));
+        assert_eq!(value.checked_div_pow10(0), Some(value));
+
+        // Matches multiplying and dividing by the power as a Decimal
+        for exponent in [0, 6, 9, 18, 19] {
+            let pow = Decimal::from(10u128.pow(exponent));
+            assert_eq!(value.checked_mul_pow10(exponent), value.checked_mul(&pow));
+            assert_eq!(value.checked_div_pow10(exponent), value.checked_div(&pow));
+        }
+
+        assert_eq!(
+            Decimal::from("0.000000000000000001").checked_div_pow10(1),
+            Some(Decimal::from(0u64))
+        );
+        assert_eq!(value.checked_div_pow10(u32::MAX), Some(Decimal::from(0u64)));
+        assert_eq!(value.checked_mul_pow10(60), None);
+        assert_eq!(value.checked_mul_pow10(u32::MAX), None);
+    }
+
+    #[test]
+    fn test_weighted_average() {
+        let (a, b) = (Decimal::from("3.40"), Decimal::from("3.50"));
+
+        // Equal weights give the arithmetic mean
+        assert_eq!(
+            Decimal::weighted_average(&[(a, 1), (b, 1)]),
+            Some(Decimal::from("3.45"))
+        );
+        assert_eq!(
+            Decimal::weighted_average(&[(a, 3), (b, 1)]),
+            Some(Decimal::from("3.425"))
+        );
+        assert_eq!(Decimal::weighted_average(&[(a, 60)]), Some(a));
+
+        // Zero weights are ignored, but cannot all be zero
+        assert_eq!(
+            Decimal::weighted_average(&[(a, 1), (Decimal::from("1000"), 0)]),
+            Some(a)
+        );
+        assert_eq!(Decimal::weighted_average(&[(a, 0), (b, 0)]), None);
+        assert_eq!(Decimal::weighted_average(&[]), None);
+
+        // Rounds down
+        assert_eq!(
+            Decimal::weighted_average(&[(Decimal::from(0u64), 2), (Decimal::from(1u64), 1)]),
+            Some(Decimal::from("0.333333333333333333"))
+        );
+
+        // No intermediate overflow at the extremes
+        let max = Decimal(U256::MAX);
+        assert_eq!(
+            Decimal::weighted_average(&[(max, u64::MAX), (max, u64::MAX)]),
+            Some(max)
+        );
+    }
+
+    #[test]
+    fn test_checked_mul_div() {
+        let chained = |a: Decimal, b: Decimal, c: Decimal| a.checked_mul(&b)?.checked_div(&c);
+
+        // The chained product rounds to zero before the small divisor can scale
+        // it back up
+        let (a, b, c) = (
+            Decimal::from("0.000000000000000001"),
+            Decimal::from("0.5"),
+            Decimal::from("0.000000000000000001"),
+        );
+        assert_eq!(chained(a, b, c), Some(Decimal::from(0u64)));
+        assert_eq!(a.checked_mul_div(&b, &c), Some(Decimal::from("0.5")));
+
+        let (a, b, c) = (
+            Decimal::from("1.000000000000000001"),
+            Decimal::from("0.333333333333333333"),
+            Decimal::from("0.000001"),
+        );
+        assert_eq!(chained(a, b, c), Some(Decimal::from("333333.333333333333")));
+        assert_eq!(
+            a.checked_mul_div(&b, &c),
+            Some(Decimal::from("333333.333333333333333333"))
+        );
+
+        // Same result when nothing is lost
+        let (a, b, c) = (Decimal::from("3"), Decimal::from("1.5"), Decimal::from("2"));
+        assert_eq!(a.checked_mul_div(&b, &c), chained(a, b, c));
+
+        // The intermediate product may exceed U256 as long as the result fits
+        let max = Decimal(U256::MAX);
+        assert_eq!(max.checked_mul_div(&max, &max), Some(max));
+        assert_eq!(
+            max.checked_mul_div(&Decimal::from(2u64), &Decimal::ONE),
+            None
+        );
+        assert_eq!(a.checked_mul_div(&b, &Decimal::from(0u64)), None);
+    }
+
+    #[test]
+    fn test_checked_log10() {
+        assert_eq!(Decimal::from(1000u64).checked_log10(), Some(3));
+        assert_eq!(Decimal::from("0.001").checked_log10(), Some(-3));
+        assert_eq!(Decimal::from(1u64).checked_log10(), Some(0));
+
+        // Rounds down
+        assert_eq!(Decimal::from(999u64).checked_log10(), Some(2));
+        assert_eq!(Decimal::from("9.99").checked_log10(), Some(0));
+        assert_eq!(
+            Decimal::from("0.999999999999999999").checked_log10(),
+            Some(-1)
+        );
+        assert_eq!(Decimal::from("0.0015").checked_log10(), Some(-3));
+        assert_eq!(Decimal::from("3.41").checked_log10(), Some(0));
+
+        // Smallest and largest representable values
+        assert_eq!(
+            Decimal::from_scaled_u256(U256::one()).checked_log10(),
+            Some(-18)
+        );
+        assert_eq!(
+            Decimal::from_scaled_u256(U256::MAX).checked_log10(),
+            Some(59)
+        );
+
+        assert_eq!(Decimal::ZERO.checked_log10(), None);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_values_in_range() {
+        use crate::omm::test_utils::Rng;
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let mut bytes = vec![0u8; 16_384];
+        Rng(0x2545_f491_4f6c_dd1d).fill_bytes(&mut bytes);
+        let mut u = Unstructured::new(&bytes);
+
+        while !u.is_empty() {
+            let x = Decimal::arbitrary(&mut u).unwrap();
+            let y = Decimal::arbitrary(&mut u).unwrap();
+            assert!(x.0 <= U256::from(u128::MAX));
+            assert!(x.checked_mul(&y).is_some());
+            assert!(x.checked_add(&y).is_some());
+        }
+
+        // Exhausted input still produces a value
+        assert_eq!(Decimal::arbitrary(&mut u).unwrap(), Decimal::ZERO);
+    }
+}
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use std::convert::TryInto;
 
-use crate::math::u256::U256;
+use crate::math::{RoundingMode, u256::U256};
 use std::fmt;
 
 const LN2: u128 = 12_786_308_645_202_655_660; // ln(2) in fixed 64 representation
@@ -36,7 +36,7 @@ const MAX_U128: u128 = 340_282_366_920_938_463_463_374_607_431_768_211_455; // 2
 // impl std::error::Error for FixedPointError {}
 
 // === FixedPoint64 Struct ===
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct FixedPoint64 {
     value: u128,
 }
@@ -61,6 +61,9 @@ impl fmt::Display for FixedPoint64 {
 }
 
 impl FixedPoint64 {
+    pub const ZERO: Self = Self { value: 0 };
+    pub const ONE: Self = Self { value: 1 << 64 };
+
     pub fn new(value: u128) -> Result<Self> {
         if value > MAX_U128 {
             return Err(anyhow::anyhow!("Value out of range: {}", value));
@@ -74,11 +77,18 @@ impl FixedPoint64 {
     }
 
     // === Convert Functions ===
+    /// Converts an integer into fixed point. The integer part has 64 bits, so
+    /// only values up to `u64::MAX` (about 1.8e19) are representable.
     pub fn from(value: u128) -> Result<Self> {
-        let scaled_value = value
-            .checked_shl(64)
-            .ok_or_else(|| anyhow::anyhow!("Shift overflow"))?;
-        Self::new(scaled_value)
+        // `checked_shl` only fails for shifts of 128 bits or more, so larger
+        // values would silently lose their high bits
+        if value > u64::MAX as u128 {
+            return Err(anyhow::anyhow!(
+                "Value {} too large for FixedPoint64, max ~1.8e19",
+                value
+            ));
+        }
+        Self::new(value << 64)
     }
 
     pub fn one() -> Result<Self> {
@@ -110,6 +120,44 @@ impl FixedPoint64 {
         Self::new(quotient)
     }
 
+    /// Same as `from_rational`, rounding the quotient with `rounding_mode`
+    /// instead of flooring it. Results too small to represent round to zero
+    /// (or to the smallest positive value with `Ceil`) instead of erroring.
+    pub fn from_rational_rounded(
+        numerator: u128,
+        denominator: u128,
+        rounding_mode: RoundingMode,
+    ) -> Result<Self> {
+        if denominator == 0 {
+            return Err(anyhow::anyhow!("Zero division"));
+        }
+        let scaled_numerator = U256::from(numerator) << 64;
+        let denominator = U256::from(denominator);
+        let quotient = scaled_numerator / denominator;
+        let remainder = scaled_numerator % denominator;
+
+        let round_up = match rounding_mode {
+            RoundingMode::Floor => false,
+            RoundingMode::Ceil => !remainder.is_zero(),
+            RoundingMode::Nearest => remainder >= denominator - remainder,
+            RoundingMode::NearestEven => match remainder.cmp(&(denominator - remainder)) {
+                std::cmp::Ordering::Less => false,
+                std::cmp::Ordering::Equal => quotient.bit(0),
+                std::cmp::Ordering::Greater => true,
+            },
+        };
+        let quotient = if round_up {
+            quotient + U256::one()
+        } else {
+            quotient
+        };
+
+        if quotient > U256::from(MAX_U128) {
+            return Err(anyhow::anyhow!("Result too large"));
+        }
+        Self::new(quotient.as_u128())
+    }
+
     pub fn to_u128(&self) -> u128 {
         let floored_num = self.to_u128_down() << 64;
         let boundary = floored_num + (1_u128 << 63);
@@ -258,6 +306,17 @@ impl FixedPoint64 {
     pub fn multiply_divide(
         numerators: &mut Vec<FixedPoint64>,
         denominators: &mut Vec<FixedPoint64>,
+    ) -> Result<FixedPoint64> {
+        Self::multiply_divide_with_mode(numerators, denominators, MultiplyDivideMode::Lenient)
+    }
+
+    /// Same as `multiply_divide`. In strict mode, a failed multiplication or
+    /// division is reported as a `MultiplyDivideError` carrying the operand and
+    /// the partial result, instead of a generic message.
+    pub fn multiply_divide_with_mode(
+        numerators: &mut Vec<FixedPoint64>,
+        denominators: &mut Vec<FixedPoint64>,
+        mode: MultiplyDivideMode,
     ) -> Result<FixedPoint64> {
         if numerators.is_empty() {
             return Err(anyhow::anyhow!("No numerators"));
@@ -267,6 +326,16 @@ impl FixedPoint64 {
         sort_descending(numerators);
         sort_descending(denominators);
 
+        let fail = |error: anyhow::Error, operation, operand, partial| match mode {
+            MultiplyDivideMode::Lenient => error,
+            MultiplyDivideMode::Strict => MultiplyDivideError {
+                operation,
+                operand,
+                partial,
+            }
+            .into(),
+        };
+
         // Initialize result to 1.0 (2^64 in FixedPoint64)
         let mut result = FixedPoint64::one()?;
 
@@ -284,10 +353,17 @@ impl FixedPoint64 {
                 Err(_) => {
                     // Multiplication failed (overflow), try to divide
                     if den_idx == 0 {
-                        return Err(anyhow::anyhow!("Multiplication overflow"));
+                        return Err(fail(
+                            anyhow::anyhow!("Multiplication overflow"),
+                            "multiply",
+                            numerator,
+                            result,
+                        ));
                     }
                     let denominator = denominators[den_idx - 1];
-                    result = result.div(&denominator)?;
+                    result = result
+                        .div(&denominator)
+                        .map_err(|e| fail(e, "divide", denominator, result))?;
                     den_idx -= 1;
                 }
             }
@@ -296,7 +372,9 @@ impl FixedPoint64 {
         // Process remaining denominators
         while den_idx > 0 {
             let denominator = denominators[den_idx - 1];
-            result = result.div(&denominator)?;
+            result = result
+                .div(&denominator)
+                .map_err(|e| fail(e, "divide", denominator, result))?;
             den_idx -= 1;
         }
 
@@ -304,10 +382,46 @@ impl FixedPoint64 {
     }
 }
 
+/// How `FixedPoint64::multiply_divide_with_mode` reports failures.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MultiplyDivideMode {
+    /// Generic error messages, as returned by `multiply_divide`
+    #[default]
+    Lenient,
+    /// Failures are returned as a `MultiplyDivideError`
+    Strict,
+}
+
+/// A multiplication or division in `multiply_divide` failed. Returned, wrapped
+/// in an `anyhow::Error`, in strict mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MultiplyDivideError {
+    /// `"multiply"` or `"divide"`
+    pub operation: &'static str,
+    /// The numerator or denominator that could not be applied
+    pub operand: FixedPoint64,
+    /// The result accumulated before the failing operation
+    pub partial: FixedPoint64,
+}
+
+impl fmt::Display for MultiplyDivideError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "multiply_divide failed to {} {} by {}",
+            self.operation, self.partial, self.operand
+        )
+    }
+}
+
+impl std::error::Error for MultiplyDivideError {}
+
 // === Private Helper Functions ===
 
 /// Sorts a mutable slice of FixedPoint64 in descending order using insertion sort.
-/// Efficient for small slices (length <= 3).
+/// Efficient for small slices (length <= 3). Equal values are never swapped, and
+/// since a FixedPoint64 is just its value the output only depends on the
+/// multiset of inputs, not on their order.
 fn sort_descending(v: &mut [FixedPoint64]) {
     let len = v.len();
     if len <= 1 {
@@ -387,3 +501,283 @@ pub(crate) fn log2_64(x: u128) -> Result<FixedPoint64> {
         .ok_or_else(|| anyhow::anyhow!("Addition overflow"))?;
     FixedPoint64::from_raw_value(result)
 }
+
+/// Generates values whose integer part is below 2^32, so that the product of
+/// any two generated values fits.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for FixedPoint64 {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self {
+            value: u.int_in_range(0..=ARBITRARY_MAX_VALUE)?,
+        })
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        u128::size_hint(depth)
+    }
+}
+
+// Largest raw value generated by `Arbitrary`, just below 2^32 in value
+#[cfg(feature = "arbitrary")]
+const ARBITRARY_MAX_VALUE: u128 = (1 << 96) - 1;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_map_key() -> Result<()> {
+        use std::collections::HashMap;
+
+        let values = [
+            FixedPoint64::from_rational(1, 100)?,
+            FixedPoint64::from_rational(1, 2)?,
+            FixedPoint64::from(1)?,
+            FixedPoint64::from_rational(3, 2)?,
+            FixedPoint64::from(1000)?,
+        ];
+        let mut map = HashMap::new();
+        for (i, value) in values.iter().enumerate() {
+            map.insert(*value, i);
+        }
+
+        assert_eq!(map.len(), values.len());
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(map.get(value), Some(&i));
+        }
+
+        // Equal values hash equally regardless of how they were built
+        assert_eq!(map.get(&FixedPoint64::from_rational(2, 4)?), Some(&1));
+        assert_eq!(map.get(&FixedPoint64::from(2)?), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_rational_rounded() -> Result<()> {
+        let third = 6_148_914_691_236_517_205; // floor(2^64 / 3)
+        let raw = |numerator, denominator, rounding_mode| {
+            FixedPoint64::from_rational_rounded(numerator, denominator, rounding_mode)
+                .map(|v| v.get_value())
+        };
+
+        // 1/3 = 0x5555...5.55, so nearest rounds down
+        assert_eq!(raw(1, 3, RoundingMode::Floor)?, third);
+        assert_eq!(raw(1, 3, RoundingMode::Nearest)?, third);
+        assert_eq!(raw(1, 3, RoundingMode::Ceil)?, third + 1);
+        assert_eq!(
+            raw(1, 3, RoundingMode::Floor)?,
+            FixedPoint64::from_rational(1, 3)?.get_value()
+        );
+
+        // 2/3 = 0xAAAA...A.AA, so nearest rounds up
+        assert_eq!(raw(2, 3, RoundingMode::Floor)?, 2 * third);
+        assert_eq!(raw(2, 3, RoundingMode::Nearest)?, 2 * third + 1);
+        assert_eq!(raw(2, 3, RoundingMode::Ceil)?, 2 * third + 1);
+
+        // Exact results are unaffected
+        for rounding_mode in [
+            RoundingMode::Floor,
+            RoundingMode::Ceil,
+            RoundingMode::Nearest,
+        ] {
+            assert_eq!(raw(3, 2, rounding_mode)?, 3 << 63);
+        }
+
+        // Too small to represent: half a unit
+        assert!(FixedPoint64::from_rational(1, 1 << 65).is_err());
+        assert_eq!(raw(1, 1 << 65, RoundingMode::Floor)?, 0);
+        assert_eq!(raw(1, 1 << 65, RoundingMode::Nearest)?, 1);
+        assert_eq!(raw(1, 1 << 65, RoundingMode::Ceil)?, 1);
+        assert_eq!(raw(1, (1 << 65) + 1, RoundingMode::Nearest)?, 0);
+
+        assert!(raw(1, 0, RoundingMode::Floor).is_err());
+        assert!(raw(u128::MAX, 1, RoundingMode::Floor).is_err());
+        assert_eq!(
+            raw(u64::MAX as u128, 1, RoundingMode::Floor)?,
+            (u64::MAX as u128) << 64
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_consts() -> Result<()> {
+        assert_eq!(FixedPoint64::ONE, FixedPoint64::one()?);
+        assert_eq!(FixedPoint64::ZERO, FixedPoint64::zero()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_rational_rounded_nearest_even() -> Result<()> {
+        let raw = |numerator, denominator, rounding_mode| {
+            FixedPoint64::from_rational_rounded(numerator, denominator, rounding_mode)
+                .map(|value| value.get_value())
+        };
+
+        // 5 / 2^65 and 3 / 2^65 are exact halves of the smallest raw step
+        let half_step = 1u128 << 65;
+        assert_eq!(raw(5, half_step, RoundingMode::Nearest)?, 3);
+        assert_eq!(raw(5, half_step, RoundingMode::NearestEven)?, 2);
+        assert_eq!(raw(3, half_step, RoundingMode::NearestEven)?, 2);
+        assert_eq!(
+            raw(1, 3, RoundingMode::NearestEven)?,
+            raw(1, 3, RoundingMode::Nearest)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_out_of_range() -> Result<()> {
+        assert_eq!(
+            FixedPoint64::from(u64::MAX as u128)?.get_value(),
+            (u64::MAX as u128) << 64
+        );
+
+        for value in [u64::MAX as u128 + 1, u128::MAX] {
+            assert_eq!(
+                FixedPoint64::from(value).unwrap_err().to_string(),
+                format!("Value {} too large for FixedPoint64, max ~1.8e19", value)
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_descending() -> Result<()> {
+        let raw =
+            |values: &[FixedPoint64]| values.iter().map(|v| v.get_value()).collect::<Vec<_>>();
+
+        // Every sequence of length 0 to 4 over three values, which covers all
+        // orderings with and without duplicates
+        let pool = [0, 1, u128::MAX];
+        for len in 0..=4u32 {
+            for index in 0..pool.len().pow(len) {
+                let mut values = (0..len)
+                    .map(|digit| {
+                        FixedPoint64::from_raw_value(
+                            pool[index / pool.len().pow(digit) % pool.len()],
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let mut expected = raw(&values);
+                expected.sort_unstable_by(|a, b| b.cmp(a));
+
+                sort_descending(&mut values);
+                assert_eq!(raw(&values), expected);
+            }
+        }
+
+        let mut equal = vec![FixedPoint64::from(7)?; 4];
+        sort_descending(&mut equal);
+        assert_eq!(equal, vec![FixedPoint64::from(7)?; 4]);
+
+        // multiply_divide does not depend on the order of its inputs
+        let (a, b, c) = (
+            FixedPoint64::from(3)?,
+            FixedPoint64::from_rational(1, 3)?,
+            FixedPoint64::from(1 << 40)?,
+        );
+        let expected = FixedPoint64::multiply_divide(&mut vec![a, b, c], &mut vec![c, a])?;
+        for (numerators, denominators) in [
+            (vec![c, b, a], vec![a, c]),
+            (vec![b, a, c], vec![c, a]),
+            (vec![b, c, a], vec![a, c]),
+        ] {
+            assert_eq!(
+                FixedPoint64::multiply_divide(&mut numerators.clone(), &mut denominators.clone())?,
+                expected
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiply_divide_strict() -> Result<()> {
+        let big = FixedPoint64::from(1 << 30)?;
+        let small = FixedPoint64::from_rational(1, 1 << 30)?;
+        let partial = FixedPoint64::from(1 << 60)?;
+
+        let strict = |mut numerators: Vec<FixedPoint64>, mut denominators: Vec<FixedPoint64>| {
+            FixedPoint64::multiply_divide_with_mode(
+                &mut numerators,
+                &mut denominators,
+                MultiplyDivideMode::Strict,
+            )
+        };
+
+        // 2^90 overflows, so the third numerator is applied after dividing
+        assert_eq!(strict(vec![big; 3], vec![big])?, partial);
+        assert_eq!(
+            FixedPoint64::multiply_divide(&mut vec![big; 3], &mut vec![big])?,
+            partial
+        );
+
+        // With no denominators left to fall back on
+        let lenient = FixedPoint64::multiply_divide(&mut vec![big; 3], &mut vec![]);
+        assert_eq!(lenient.unwrap_err().to_string(), "Multiplication overflow");
+
+        let error = strict(vec![big; 3], vec![])
+            .unwrap_err()
+            .downcast::<MultiplyDivideError>()?;
+        assert_eq!(
+            error,
+            MultiplyDivideError {
+                operation: "multiply",
+                operand: big,
+                partial,
+            }
+        );
+
+        // Falling back on a denominator below one overflows the division
+        let lenient = FixedPoint64::multiply_divide(&mut vec![big; 3], &mut vec![small]);
+        assert_eq!(
+            lenient.unwrap_err().to_string(),
+            "U256 to u128 conversion overflow (div)"
+        );
+
+        let error = strict(vec![big; 3], vec![small])
+            .unwrap_err()
+            .downcast::<MultiplyDivideError>()?;
+        assert_eq!(
+            error,
+            MultiplyDivideError {
+                operation: "divide",
+                operand: small,
+                partial,
+            }
+        );
+
+        let error = strict(vec![big], vec![FixedPoint64::ZERO]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "multiply_divide failed to divide 1073741824.000000000000000000 by 0.000000000000000000"
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_values_in_range() {
+        use crate::omm::test_utils::Rng;
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let mut bytes = vec![0u8; 16_384];
+        Rng(0x2545_f491_4f6c_dd1d).fill_bytes(&mut bytes);
+        let mut u = Unstructured::new(&bytes);
+
+        while !u.is_empty() {
+            let x = FixedPoint64::arbitrary(&mut u).unwrap();
+            let y = FixedPoint64::arbitrary(&mut u).unwrap();
+            assert!(x.get_value() <= ARBITRARY_MAX_VALUE);
+            assert!(x.get_value() <= MAX_U128);
+            assert!(FixedPoint64::from_raw_value(x.get_value()).is_ok());
+            assert!(x.mul(&y).is_ok());
+            assert!(x.add(&y).is_ok());
+        }
+    }
+}
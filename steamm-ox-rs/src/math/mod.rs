@@ -7,13 +7,49 @@ use anyhow::Result;
 
 pub mod decimal;
 pub mod fixed_point;
+pub mod scaling;
 pub mod u256;
 
-const SCALE_64: u128 = 18446744073709551616;
-const MAX_U128: u128 = 340282366920938463463374607431768211455;
 const MAX_U64: u128 = 18_446_744_073_709_551_615u128;
 
+// Powers of ten up to the largest that fits in a u128
+const POW10: [u128; 39] = {
+    let mut table = [1u128; 39];
+    let mut i = 1;
+    while i < table.len() {
+        table[i] = table[i - 1] * 10;
+        i += 1;
+    }
+    table
+};
+
+/// Returns `10^exponent`, from a lookup table for exponents up to 38. Returns
+/// `None` if the result does not fit in a U256, i.e. above 77.
+pub fn pow10(exponent: u32) -> Option<U256> {
+    match POW10.get(exponent as usize) {
+        Some(&pow) => Some(U256::from(pow)),
+        None => U256::from(10u8).checked_pow(U256::from(exponent)),
+    }
+}
+
+/// Rounding applied when a result cannot be represented exactly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    Floor,
+    Ceil,
+    /// Rounds to the nearest value, with halves rounded up
+    Nearest,
+    /// Rounds to the nearest value, with halves rounded to the even neighbour
+    NearestEven,
+}
+
 pub fn safe_mul_div_up(x: u64, y: u64, z: u64) -> Result<u64> {
+    safe_mul_div(x, y, z, RoundingMode::Ceil)
+}
+
+/// Computes `x * y / z` with the given rounding, erroring on a zero divisor
+/// or if the result does not fit in a u64.
+pub fn safe_mul_div(x: u64, y: u64, z: u64, rounding_mode: RoundingMode) -> Result<u64> {
     if z == 0 {
         return Err(anyhow::anyhow!("Division by zero"));
     }
@@ -22,7 +58,22 @@ pub fn safe_mul_div_up(x: u64, y: u64, z: u64) -> Result<u64> {
     let y_128 = y as u128;
     let z_128 = z as u128;
 
-    let res = num_divide_and_round_up(x_128 * y_128, z_128);
+    let res = match rounding_mode {
+        RoundingMode::Floor => x_128 * y_128 / z_128,
+        RoundingMode::Ceil => num_divide_and_round_up(x_128 * y_128, z_128),
+        RoundingMode::Nearest | RoundingMode::NearestEven => {
+            let quotient = x_128 * y_128 / z_128;
+            let remainder = x_128 * y_128 % z_128;
+            let round_up = match remainder.cmp(&(z_128 - remainder)) {
+                std::cmp::Ordering::Less => false,
+                std::cmp::Ordering::Equal => {
+                    rounding_mode == RoundingMode::Nearest || quotient % 2 == 1
+                }
+                std::cmp::Ordering::Greater => true,
+            };
+            quotient + u128::from(round_up)
+        }
+    };
 
     if res > MAX_U64 {
         return Err(anyhow::anyhow!("Math overflow"));
@@ -38,19 +89,31 @@ fn num_divide_and_round_up(x: u128, y: u128) -> u128 {
 }
 
 pub fn decimal_to_fixedpoint64(d: Decimal) -> Result<FixedPoint64> {
-    let decimal_value = d.0;
-
-    // It's safe to upscale the decimal value, given that
-    // the maximum value inside a decimal type is MAX_U64 * WAD which is
-    // roughly ≈ 1.844 × 10^37
-    //
-    // Multiplying it by 2^64 (SCALE_64) gives us a value of 3.4 × 10^56 which
-    // is smaller than MAX_U256 (1.1579 × 10^77)
-    let scaled_value = decimal_value * U256::from(SCALE_64) / Decimal::wad();
-    if scaled_value > MAX_U128.into() {
-        return Err(anyhow::anyhow!(
-            "Failed to convert decimal to fixed point: value too large"
-        ));
+    let scaled_value = scaling::wad_to_scale64(d.0).map_err(|_| {
+        anyhow::anyhow!("Failed to convert decimal to fixed point: value too large")
+    })?;
+    FixedPoint64::from_raw_value(scaled_value)
+}
+
+/// Converts a FixedPoint64 into a Decimal, rounding down to 18 decimal places.
+pub fn fixedpoint64_to_decimal(fp: FixedPoint64) -> Decimal {
+    Decimal::from_scaled_u256(scaling::scale64_to_wad(fp.get_value()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pow10() {
+        for exponent in 0..=77 {
+            assert_eq!(
+                pow10(exponent),
+                Some(U256::from(10u8).pow(U256::from(exponent)))
+            );
+        }
+        assert_eq!(pow10(38), Some(U256::from(POW10[38])));
+        assert_eq!(pow10(78), None);
+        assert_eq!(pow10(u32::MAX), None);
     }
-    FixedPoint64::from_raw_value(scaled_value.as_u128())
 }
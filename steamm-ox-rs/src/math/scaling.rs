@@ -0,0 +1,104 @@
+//! Fixed point scales used across the crate.
+//!
+//! - `WAD` (10^18) is the scale of `Decimal`, used for prices, btoken ratios
+//!   and USD amounts.
+//! - `SCALE_64` (2^64) is the scale of `FixedPoint64`, used by the legacy
+//!   quoter.
+//! - `SCALE_10` (10^10) is the precision of the bounds in the on-chain OMM v2
+//!   quoter, which the legacy quoter mirrors.
+//!
+//! Conversions from a finer to a coarser scale round down.
+
+use anyhow::Result;
+
+use crate::math::{fixed_point::FixedPoint64, u256::U256};
+
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+pub const SCALE_64: u128 = 1 << 64;
+pub const SCALE_10: u128 = 10_000_000_000;
+
+// WAD / SCALE_10
+const WAD_PER_SCALE_10: u128 = 100_000_000;
+
+/// Converts a raw WAD value into a raw 10^10 scaled value, rounding down.
+pub fn wad_to_scale10(value: U256) -> U256 {
+    value / U256::from(WAD_PER_SCALE_10)
+}
+
+/// Converts a raw 10^10 scaled value into a raw WAD value.
+pub fn scale10_to_wad(value: U256) -> Result<U256> {
+    value
+        .checked_mul(U256::from(WAD_PER_SCALE_10))
+        .ok_or_else(|| anyhow::anyhow!("Scale conversion overflow"))
+}
+
+/// Converts a raw 10^10 scaled value into a `FixedPoint64`, rounding down.
+pub fn scale10_to_fixedpoint64(value: u128) -> Result<FixedPoint64> {
+    FixedPoint64::from_rational(value, SCALE_10)
+}
+
+/// Converts a raw WAD value into a raw 2^64 scaled value, rounding down.
+pub fn wad_to_scale64(value: U256) -> Result<u128> {
+    let scaled_value = value
+        .checked_mul(U256::from(SCALE_64))
+        .ok_or_else(|| anyhow::anyhow!("Scale conversion overflow"))?
+        / U256::from(WAD);
+    if scaled_value > U256::from(u128::MAX) {
+        return Err(anyhow::anyhow!("Scale conversion overflow"));
+    }
+    Ok(scaled_value.as_u128())
+}
+
+/// Converts a raw 2^64 scaled value into a raw WAD value, rounding down.
+pub fn scale64_to_wad(value: u128) -> U256 {
+    U256::from(value) * U256::from(WAD) / U256::from(SCALE_64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{decimal::Decimal, decimal_to_fixedpoint64};
+
+    #[test]
+    fn test_consts() {
+        assert_eq!(U256::from(WAD), Decimal::wad());
+        assert_eq!(SCALE_64, FixedPoint64::ONE.get_value());
+        assert_eq!(WAD_PER_SCALE_10 * SCALE_10, WAD);
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        // Values exactly representable in all three scales
+        for value in ["0", "0.5", "0.75", "1", "3", "1234.0625"] {
+            let wad = Decimal::from(value).0;
+
+            let scale10 = wad_to_scale10(wad);
+            assert_eq!(scale10_to_wad(scale10)?, wad);
+
+            let fp = scale10_to_fixedpoint64(scale10.as_u128())?;
+            assert_eq!(fp, decimal_to_fixedpoint64(Decimal(wad))?);
+            assert_eq!(wad_to_scale64(wad)?, fp.get_value());
+            assert_eq!(scale64_to_wad(fp.get_value()), wad);
+        }
+
+        // Going through a coarser scale rounds down
+        let wad = Decimal::from("0.123456789012345678").0;
+        assert_eq!(wad_to_scale10(wad), U256::from(1_234_567_890u64));
+        assert_eq!(
+            scale10_to_wad(wad_to_scale10(wad))?,
+            Decimal::from("0.123456789").0
+        );
+
+        let third = FixedPoint64::from_rational(1, 3)?;
+        assert_eq!(
+            scale64_to_wad(third.get_value()),
+            Decimal::from("0.333333333333333333").0
+        );
+
+        assert!(scale10_to_wad(U256::MAX).is_err());
+        assert!(wad_to_scale64(U256::MAX).is_err());
+        assert!(wad_to_scale64(Decimal::from(u64::MAX).0 * U256::from(2u8)).is_err());
+
+        Ok(())
+    }
+}
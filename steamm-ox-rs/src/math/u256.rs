@@ -3,3 +3,56 @@ use uint::construct_uint;
 construct_uint! {
     pub struct U256(4);
 }
+
+construct_uint! {
+    /// Intermediate type for U256 products that would otherwise overflow.
+    pub struct U512(8);
+}
+
+impl From<U256> for U512 {
+    fn from(value: U256) -> Self {
+        let mut words = [0u64; 8];
+        words[..4].copy_from_slice(&value.0);
+        U512(words)
+    }
+}
+
+impl TryFrom<U512> for U256 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: U512) -> anyhow::Result<Self> {
+        if value.0[4..].iter().any(|word| *word != 0) {
+            return Err(anyhow::anyhow!("U512 value does not fit in U256"));
+        }
+        let mut words = [0u64; 4];
+        words.copy_from_slice(&value.0[..4]);
+        Ok(U256(words))
+    }
+}
+
+construct_uint! {
+    /// Intermediate type for products of many U256 values, e.g. in
+    /// `Decimal::geometric_mean`.
+    pub struct U4096(64);
+}
+
+impl From<U256> for U4096 {
+    fn from(value: U256) -> Self {
+        let mut words = [0u64; 64];
+        words[..4].copy_from_slice(&value.0);
+        U4096(words)
+    }
+}
+
+impl TryFrom<U4096> for U256 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: U4096) -> anyhow::Result<Self> {
+        if value.0[4..].iter().any(|word| *word != 0) {
+            return Err(anyhow::anyhow!("U4096 value does not fit in U256"));
+        }
+        let mut words = [0u64; 4];
+        words.copy_from_slice(&value.0[..4]);
+        Ok(U256(words))
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::Result;
+
+use crate::{
+    SwapQuote,
+    omm::{PriceInputs, QuoterType, SteammPool},
+};
+
+/// Everything a quote depends on. Any change to the pool state, the prices or
+/// the trade produces a different key, so stale entries are never returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct QuoteKey {
+    b_token_reserve_x: u64,
+    b_token_reserve_y: u64,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    swap_fee_bps: u64,
+    quoter_type: QuoterType,
+    b_token_amount_in: u64,
+    x2y: bool,
+    inputs: PriceInputs,
+}
+
+/// Bounded cache of `SteammPool::quote_swap_with_inputs` results, for servers that quote
+/// the same trades repeatedly within a block. Once full, the oldest entry is
+/// evicted first. Errors are not cached.
+#[derive(Debug)]
+pub struct QuoteCache {
+    capacity: usize,
+    entries: HashMap<QuoteKey, SwapQuote>,
+    // Keys in insertion order, oldest first
+    order: VecDeque<QuoteKey>,
+    hits: u64,
+    misses: u64,
+}
+
+impl QuoteCache {
+    /// Creates a cache holding at most `capacity` quotes. A capacity of zero
+    /// disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Same as `SteammPool::quote_swap_with_inputs`, returning the cached
+    /// quote if the same trade was quoted against the same pool state before.
+    pub fn quote_swap(
+        &mut self,
+        pool: &SteammPool,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<SwapQuote> {
+        let key = QuoteKey {
+            b_token_reserve_x: pool.b_token_reserve_x,
+            b_token_reserve_y: pool.b_token_reserve_y,
+            decimals_x: pool.decimals_x,
+            decimals_y: pool.decimals_y,
+            amplifier: pool.amplifier,
+            swap_fee_bps: pool.swap_fee_bps,
+            quoter_type: pool.quoter_type,
+            b_token_amount_in,
+            x2y,
+            inputs: *inputs,
+        };
+
+        if let Some(quote) = self.entries.get(&key) {
+            self.hits += 1;
+            return Ok(quote.clone());
+        }
+        self.misses += 1;
+
+        let quote = pool.quote_swap_with_inputs(b_token_amount_in, x2y, inputs)?;
+
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity
+                && let Some(oldest) = self.order.pop_front()
+            {
+                self.entries.remove(&oldest);
+            }
+            self.entries.insert(key, quote.clone());
+            self.order.push_back(key);
+        }
+
+        Ok(quote)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of quotes served from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Number of quotes computed by the pool
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::decimal::Decimal;
+
+    #[test]
+    fn test_quote_cache() -> Result<()> {
+        let inputs = PriceInputs::new(Decimal::from("3"), Decimal::from("1"))
+            .with_confidences(Decimal::from("0.0"), Decimal::from("0.0"));
+
+        let mut pool = SteammPool::new(
+            1_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            100,
+            30,
+            QuoterType::Ommv2,
+        );
+        let mut cache = QuoteCache::new(2);
+        let quote = |cache: &mut QuoteCache, pool: &SteammPool, amount_in| {
+            cache.quote_swap(pool, amount_in, true, &inputs)
+        };
+
+        let first = quote(&mut cache, &pool, 1_000_000_000)?;
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+
+        // A hit returns the identical quote
+        assert_eq!(quote(&mut cache, &pool, 1_000_000_000)?, first);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // A reserve change forces recomputation
+        pool.b_token_reserve_y -= 1_000_000_000;
+        let after_swap = quote(&mut cache, &pool, 1_000_000_000)?;
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+        assert_eq!(
+            after_swap,
+            pool.quote_swap_with_inputs(1_000_000_000, true, &inputs)?
+        );
+        assert!(after_swap.amount_out < first.amount_out);
+
+        // The oldest entry is evicted once the cache is full
+        quote(&mut cache, &pool, 2_000_000_000)?;
+        assert_eq!(cache.len(), 2);
+        pool.b_token_reserve_y += 1_000_000_000;
+        assert_eq!(quote(&mut cache, &pool, 1_000_000_000)?, first);
+        assert_eq!((cache.hits(), cache.misses()), (1, 4));
+
+        cache.clear();
+        assert!(cache.is_empty());
+
+        let mut disabled = QuoteCache::new(0);
+        quote(&mut disabled, &pool, 1_000_000_000)?;
+        quote(&mut disabled, &pool, 1_000_000_000)?;
+        assert_eq!((disabled.hits(), disabled.misses()), (0, 2));
+        assert!(disabled.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,9 +1,20 @@
-use crate::{SwapQuote, math::decimal::Decimal};
+use crate::{
+    BPS_SCALE, DecimalSwapQuote, SwapQuote,
+    amount::{BTokenAmount, BTokenRatio},
+    effective_fee_bps, get_decimal_quote,
+    math::{decimal::Decimal, safe_mul_div_up},
+    shortfall_bps, to_b_token, to_underlying,
+};
 use anyhow::Result;
 
+#[cfg(feature = "quote-cache")]
+pub mod cache;
 pub mod omm_v2_legacy;
 pub mod omm_v2_new;
+#[cfg(test)]
+pub(crate) mod test_utils;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SteammPool {
     pub b_token_reserve_x: u64,
     pub b_token_reserve_y: u64,
@@ -14,11 +25,149 @@ pub struct SteammPool {
     pub quoter_type: QuoterType,
 }
 
+/// Iteration counts of the numerical solvers run for a quote. On-chain compute
+/// cost scales with these, so they can be used to approximate gas. Solvers that
+/// a quoter does not use are reported as zero iterations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuoteDiagnostics {
+    pub get_d_iterations: usize,
+    pub get_y_iterations: usize,
+    pub newton_raphson_iterations: usize,
+    /// Whether the quoter's reserve check zeroed the amount out, because the
+    /// curve would have paid out too much of the output reserve
+    pub capped_by_reserves: bool,
+}
+
+/// Oracle prices, btoken ratios and price confidences for a quote, named so
+/// that the x and y sides cannot be swapped by position. Built with
+/// `PriceInputs::new`, which defaults the btoken ratios to one and leaves the
+/// confidences unset. The StableSwap quoter requires confidences and errors
+/// without them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PriceInputs {
+    pub price_x: Decimal,
+    pub price_y: Decimal,
+    pub b_token_ratio_x: Decimal,
+    pub b_token_ratio_y: Decimal,
+    pub confidence_x: Option<Decimal>,
+    pub confidence_y: Option<Decimal>,
+}
+
+impl PriceInputs {
+    pub fn new(price_x: Decimal, price_y: Decimal) -> Self {
+        Self {
+            price_x,
+            price_y,
+            b_token_ratio_x: Decimal::ONE,
+            b_token_ratio_y: Decimal::ONE,
+            confidence_x: None,
+            confidence_y: None,
+        }
+    }
+
+    pub fn with_b_token_ratios(
+        mut self,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Self {
+        self.b_token_ratio_x = b_token_ratio_x;
+        self.b_token_ratio_y = b_token_ratio_y;
+        self
+    }
+
+    pub fn with_confidences(mut self, confidence_x: Decimal, confidence_y: Decimal) -> Self {
+        self.confidence_x = Some(confidence_x);
+        self.confidence_y = Some(confidence_y);
+        self
+    }
+}
+
+/// Where the price of one side of a quote comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceSource {
+    Oracle(Decimal),
+    /// The price at which the pool is balanced in USD, given the oracle price
+    /// of the other side
+    PoolImplied,
+    /// The oracle price if it is present and at most `max_staleness` old, and
+    /// the pool implied price otherwise. Ages are in the oracle's time unit,
+    /// e.g. seconds since the price was published.
+    OracleOrPoolImplied {
+        price: Option<Decimal>,
+        age: u64,
+        max_staleness: u64,
+    },
+}
+
+impl PriceSource {
+    /// Returns the oracle price to use, or `None` if the pool implied price
+    /// should be used instead.
+    fn oracle_price(&self) -> Option<Decimal> {
+        match *self {
+            PriceSource::Oracle(price) => Some(price),
+            PriceSource::PoolImplied => None,
+            PriceSource::OracleOrPoolImplied {
+                price,
+                age,
+                max_staleness,
+            } => price.filter(|_| age <= max_staleness),
+        }
+    }
+}
+
+/// Result of `SteammPool::quote_swap_outcome`, telling apart the two reasons
+/// a quote can pay out nothing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuoteOutcome {
+    /// The trade pays out a nonzero amount
+    Filled(SwapQuote),
+    /// The trade is worth less than one unit of the output btoken once the
+    /// curve and fees are applied
+    RoundsToZero,
+    /// The quoter's reserve check refused the trade by returning zero: the
+    /// legacy quoter refuses to pay out the whole output reserve, StableSwap
+    /// refuses to pay out more than it
+    CappedByReserves,
+}
+
+/// Coarse state of a pool, used by routers to deprioritize degenerate pools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolHealth {
+    Ok,
+    /// The smaller side holds less than `IMBALANCED_SHARE_BPS` of D in USD
+    Imbalanced,
+    /// The smaller side holds less than `NEAR_EMPTY_SHARE_BPS` of D in USD
+    NearEmpty,
+}
+
+// Share of D held by the smaller side of the pool, in basis points
+pub const IMBALANCED_SHARE_BPS: u64 = 1_000;
+pub const NEAR_EMPTY_SHARE_BPS: u64 = 10;
+
+// Number of chunks `split_order` divides an order into
+pub const SPLIT_ORDER_STEPS: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum QuoterType {
     Ommv2Legacy,
     Ommv2,
 }
 
+impl QuoterType {
+    /// Maps a quoter tag to its variant. Accepts the variant names shared with
+    /// the TypeScript package (which calls the new quoter `Ommv2New`), the
+    /// names of the quoter modules, and the v2_1 tag of the on-chain module
+    /// that the new quoter prices. Tags are matched case-insensitively.
+    pub fn from_version_tag(tag: &str) -> Result<QuoterType> {
+        match tag.to_ascii_lowercase().as_str() {
+            "ommv2legacy" | "omm_v2_legacy" => Ok(QuoterType::Ommv2Legacy),
+            "ommv2" | "ommv2new" | "omm_v2_new" | "ommv2_1" | "omm_v2_1" => Ok(QuoterType::Ommv2),
+            _ => Err(anyhow::anyhow!("Unknown quoter tag: {:?}", tag)),
+        }
+    }
+}
+
 impl SteammPool {
     pub fn new(
         b_token_reserve_x: u64,
@@ -40,48 +189,2679 @@ impl SteammPool {
         }
     }
 
-    pub fn quote_swap(
+    /// Returns the theoretical maximum output in btokens for a swap in the
+    /// given direction, which is the full output-side reserve once converted
+    /// through its underlying amount. The legacy curve approaches this amount
+    /// asymptotically, so its quotes are strictly below it, while StableSwap
+    /// can pay it out in full.
+    pub fn max_output(
+        &self,
+        x2y: bool,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<u64> {
+        let (b_token_reserve_out, b_token_ratio_out) = if x2y {
+            (self.b_token_reserve_y, b_token_ratio_y)
+        } else {
+            (self.b_token_reserve_x, b_token_ratio_x)
+        };
+
+        let reserve_out = to_underlying(b_token_reserve_out, &b_token_ratio_out)?;
+        to_b_token(reserve_out, &b_token_ratio_out)
+    }
+
+    /// Classifies the pool by the USD value of its smaller side relative to
+    /// the StableSwap invariant D, which is the USD value of the pool when
+    /// balanced. The same classification is used for both quoter types.
+    pub fn health(
+        &self,
+        price_x: Decimal,
+        price_y: Decimal,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<PoolHealth> {
+        validate_prices(price_x, price_y)?;
+
+        // D is undefined for an empty side
+        if to_underlying(self.b_token_reserve_x, &b_token_ratio_x)? == 0
+            || to_underlying(self.b_token_reserve_y, &b_token_ratio_y)? == 0
+        {
+            return Ok(PoolHealth::NearEmpty);
+        }
+
+        let curve = omm_v2_new::CurveState::new(
+            self.b_token_reserve_x,
+            self.b_token_reserve_y,
+            price_x,
+            price_y,
+            self.decimals_x,
+            self.decimals_y,
+            self.amplifier,
+            b_token_ratio_x,
+            b_token_ratio_y,
+        )?;
+
+        let smaller_side = Decimal::min(curve.scaled_usd_reserve_x, curve.scaled_usd_reserve_y).0;
+        let share_bps = smaller_side * BPS_SCALE / curve.d;
+
+        if share_bps < NEAR_EMPTY_SHARE_BPS.into() {
+            Ok(PoolHealth::NearEmpty)
+        } else if share_bps < IMBALANCED_SHARE_BPS.into() {
+            Ok(PoolHealth::Imbalanced)
+        } else {
+            Ok(PoolHealth::Ok)
+        }
+    }
+
+    /// Returns the total USD value of both reserves, using the same USD
+    /// scaling as the StableSwap quoter.
+    pub fn tvl_usd(
+        &self,
+        price_x: Decimal,
+        price_y: Decimal,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<Decimal> {
+        validate_prices(price_x, price_y)?;
+
+        let reserve_x = to_underlying(self.b_token_reserve_x, &b_token_ratio_x)?;
+        let reserve_y = to_underlying(self.b_token_reserve_y, &b_token_ratio_y)?;
+        let usd_reserve_x = omm_v2_new::to_usd(reserve_x, price_x, self.decimals_x)?;
+        let usd_reserve_y = omm_v2_new::to_usd(reserve_y, price_y, self.decimals_y)?;
+
+        usd_reserve_x
+            .checked_add(&usd_reserve_y)
+            .ok_or_else(|| anyhow::anyhow!("TVL overflow"))
+    }
+
+    /// Returns the USD value of the x reserve divided by the USD value of the y
+    /// reserve, using the same USD scaling as the StableSwap quoter. A balanced
+    /// pool returns one. Errors if the y reserve has no USD value.
+    pub fn usd_balance_ratio(
+        &self,
+        price_x: Decimal,
+        price_y: Decimal,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<Decimal> {
+        validate_prices(price_x, price_y)?;
+
+        let reserve_x = to_underlying(self.b_token_reserve_x, &b_token_ratio_x)?;
+        let reserve_y = to_underlying(self.b_token_reserve_y, &b_token_ratio_y)?;
+        let usd_reserve_x = omm_v2_new::to_usd(reserve_x, price_x, self.decimals_x)?;
+        let usd_reserve_y = omm_v2_new::to_usd(reserve_y, price_y, self.decimals_y)?;
+
+        if usd_reserve_y.is_zero() {
+            return Err(anyhow::anyhow!("Reserve y has no USD value"));
+        }
+
+        usd_reserve_x
+            .checked_div(&usd_reserve_y)
+            .ok_or_else(|| anyhow::anyhow!("USD balance ratio overflow"))
+    }
+
+    /// Returns the direction and the smallest btoken amount in of the swap that
+    /// brings `usd_balance_ratio` to `target_ratio`, e.g. one for a 50/50 USD
+    /// split. The pool fees stay in the pool, so the ratio after the swap is
+    /// at or just past the target. Returns a zero amount if the pool is
+    /// already at the target.
+    pub fn input_to_reach_balance(
+        &self,
+        target_ratio: Decimal,
+        inputs: &PriceInputs,
+    ) -> Result<(bool, u64)> {
+        if target_ratio.is_zero() {
+            return Err(anyhow::anyhow!("Target ratio must be greater than zero"));
+        }
+
+        // Too little x in USD terms is fixed by swapping x in
+        let ratio = self.usd_balance_ratio(
+            inputs.price_x,
+            inputs.price_y,
+            inputs.b_token_ratio_x,
+            inputs.b_token_ratio_y,
+        )?;
+        let x2y = ratio < target_ratio;
+        if ratio == target_ratio {
+            return Ok((x2y, 0));
+        }
+
+        let reaches_target = |b_token_amount_in: u64| -> Result<bool> {
+            let (_, (b_token_reserve_x, b_token_reserve_y)) =
+                self.quote_swap_with_reserves(b_token_amount_in, x2y, inputs)?;
+            let pool = SteammPool {
+                b_token_reserve_x,
+                b_token_reserve_y,
+                ..*self
+            };
+            let ratio = pool.usd_balance_ratio(
+                inputs.price_x,
+                inputs.price_y,
+                inputs.b_token_ratio_x,
+                inputs.b_token_ratio_y,
+            )?;
+
+            Ok(if x2y {
+                ratio >= target_ratio
+            } else {
+                ratio <= target_ratio
+            })
+        };
+        let not_reachable = || anyhow::anyhow!("Target ratio {} not reachable", target_ratio);
+
+        // Double the amount until the target is reached, then bisect
+        let mut hi = 1u64;
+        while !reaches_target(hi).map_err(|_| not_reachable())? {
+            hi = hi.checked_mul(2).ok_or_else(not_reachable)?;
+        }
+
+        let mut lo = 1;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if reaches_target(mid)? {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+
+        Ok((x2y, lo))
+    }
+
+    /// Returns the marginal price of the pool before fees, as the btoken amount
+    /// out received per btoken in for an infinitesimal trade.
+    ///
+    /// The legacy curve always starts at the oracle price, while StableSwap
+    /// starts at the oracle price only when the pool is balanced in USD terms.
+    pub fn spot_price(
         &self,
-        b_token_amount_in: u64,
         price_x: Decimal,
         price_y: Decimal,
         x2y: bool,
         b_token_ratio_x: Decimal,
         b_token_ratio_y: Decimal,
-        price_confidence_a: Option<Decimal>,
-        price_confidence_b: Option<Decimal>,
-    ) -> Result<SwapQuote> {
-        match self.quoter_type {
-            QuoterType::Ommv2Legacy => omm_v2_legacy::quote_swap(
-                b_token_amount_in,
+    ) -> Result<Decimal> {
+        validate_prices(price_x, price_y)?;
+
+        let usd_rate = match self.quoter_type {
+            QuoterType::Ommv2Legacy => Decimal::from(1u64),
+            QuoterType::Ommv2 => omm_v2_new::spot_usd_rate(
                 self.b_token_reserve_x,
                 self.b_token_reserve_y,
-                price_x.clone(),
-                price_y.clone(),
+                price_x,
+                price_y,
                 self.decimals_x,
                 self.decimals_y,
                 self.amplifier,
                 x2y,
                 b_token_ratio_x,
                 b_token_ratio_y,
-                self.swap_fee_bps,
-            ),
-            QuoterType::Ommv2 => omm_v2_new::quote_swap(
-                b_token_amount_in,
-                self.b_token_reserve_x,
-                self.b_token_reserve_y,
-                price_x.clone(),
-                price_y.clone(),
-                self.decimals_x,
-                self.decimals_y,
-                self.amplifier,
+            )?,
+        };
+
+        let (price_in, decimals_in, b_token_ratio_in, price_out, decimals_out, b_token_ratio_out) =
+            if x2y {
+                (
+                    price_x,
+                    self.decimals_x,
+                    b_token_ratio_x,
+                    price_y,
+                    self.decimals_y,
+                    b_token_ratio_y,
+                )
+            } else {
+                (
+                    price_y,
+                    self.decimals_y,
+                    b_token_ratio_y,
+                    price_x,
+                    self.decimals_x,
+                    b_token_ratio_x,
+                )
+            };
+
+        usd_rate
+            .checked_mul(&price_in)
+            .and_then(|v| v.checked_mul(&b_token_ratio_in))
+            .and_then(|v| v.checked_mul_pow10(decimals_out))
+            .and_then(|v| v.checked_div(&price_out))
+            .and_then(|v| v.checked_div(&b_token_ratio_out))
+            .and_then(|v| v.checked_div_pow10(decimals_in))
+            .ok_or_else(|| anyhow::anyhow!("Spot price overflow"))
+    }
+
+    /// Returns the marginal price of the pool after fees, i.e. the btoken
+    /// amount out received per btoken in for an infinitesimal trade once the
+    /// swap fee is taken. This is the price to compare against other venues.
+    ///
+    /// Fees are taken from the amount out, so this is
+    /// `spot_price * (1 - fee)`. Quoted the other way around, as amount in
+    /// paid per amount out, it is `1 / spot_price / (1 - fee)`, which is
+    /// about `(1 / spot_price) * (1 + fee)` for small fees.
+    ///
+    /// Errors if the pool uses the StableSwap quoter and either confidence is
+    /// missing.
+    pub fn marginal_price_with_fee(&self, x2y: bool, inputs: &PriceInputs) -> Result<Decimal> {
+        let spot_price = self.spot_price(
+            inputs.price_x,
+            inputs.price_y,
+            x2y,
+            inputs.b_token_ratio_x,
+            inputs.b_token_ratio_y,
+        )?;
+        let fee_bps = self.fee_bps(inputs)?;
+
+        Decimal::ONE
+            .checked_sub(&Decimal::from_bps(fee_bps))
+            .and_then(|v| spot_price.checked_mul(&v))
+            .ok_or_else(|| anyhow::anyhow!("Marginal price overflow"))
+    }
+
+    /// Returns how far the execution price of the trade, before fees, is below
+    /// the spot price, in basis points rounded down.
+    pub fn price_impact_bps(
+        &self,
+        b_token_amount_in: u64,
+        price_x: Decimal,
+        price_y: Decimal,
+        x2y: bool,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<u64> {
+        let spot_price =
+            self.spot_price(price_x, price_y, x2y, b_token_ratio_x, b_token_ratio_y)?;
+        let amount_out = self.quote_swap_no_fees(
+            b_token_amount_in,
+            price_x,
+            price_y,
+            x2y,
+            b_token_ratio_x,
+            b_token_ratio_y,
+        )?;
+
+        shortfall_bps(b_token_amount_in, amount_out, &spot_price)
+            .ok_or_else(|| anyhow::anyhow!("Price impact undefined for a zero amount in"))
+    }
+
+    /// Returns the smallest btoken amount in whose price impact reaches
+    /// `target_bps`. Errors if the target cannot be reached before the quote
+    /// fails, e.g. because the amount overflows.
+    pub fn input_for_price_impact_bps(
+        &self,
+        target_bps: u64,
+        price_x: Decimal,
+        price_y: Decimal,
+        x2y: bool,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<u64> {
+        if target_bps >= BPS_SCALE {
+            return Err(anyhow::anyhow!(
+                "Target price impact must be below {} bps",
+                BPS_SCALE
+            ));
+        }
+        if target_bps == 0 {
+            return Ok(0);
+        }
+
+        let price_impact_bps = |amount_in: u64| {
+            self.price_impact_bps(
+                amount_in,
+                price_x,
+                price_y,
                 x2y,
                 b_token_ratio_x,
                 b_token_ratio_y,
-                self.swap_fee_bps,
-                price_confidence_a.unwrap(),
-                price_confidence_b.unwrap(),
-            ),
+            )
+        };
+
+        // Double the amount until the target is reached, then bisect
+        let mut hi = if x2y {
+            self.b_token_reserve_x
+        } else {
+            self.b_token_reserve_y
+        }
+        .max(1);
+        while price_impact_bps(hi)? < target_bps {
+            hi = hi
+                .checked_mul(2)
+                .ok_or_else(|| anyhow::anyhow!("Target price impact not reachable"))?;
+        }
+
+        let mut lo = 1;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if price_impact_bps(mid)? >= target_bps {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+
+        Ok(lo)
+    }
+
+    /// Quotes the smallest btoken amount in that receives at least
+    /// `b_token_amount_out` btokens net of fees. Errors if no amount in does,
+    /// e.g. because the amount out is not below the output reserve.
+    pub fn quote_swap_exact_out(
+        &self,
+        b_token_amount_out: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<SwapQuote> {
+        let reserve_out = if x2y {
+            self.b_token_reserve_y
+        } else {
+            self.b_token_reserve_x
+        };
+        if b_token_amount_out >= reserve_out {
+            return Err(anyhow::anyhow!(
+                "Amount out {} not below reserve {}",
+                b_token_amount_out,
+                reserve_out
+            ));
+        }
+
+        let quote = |amount_in: u64| self.quote_swap_with_inputs(amount_in, x2y, inputs);
+        let not_reachable = || anyhow::anyhow!("Amount out {} not reachable", b_token_amount_out);
+
+        // Double the amount until the target is reached, then bisect
+        let mut hi = b_token_amount_out.max(1);
+        while quote(hi).map_err(|_| not_reachable())?.amount_out < b_token_amount_out {
+            hi = hi.checked_mul(2).ok_or_else(not_reachable)?;
+        }
+
+        let mut lo = 0;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if quote(mid)?.amount_out >= b_token_amount_out {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+
+        quote(lo)
+    }
+
+    /// Quotes `b_token_amount_in` exact in, then the amount out exact out, and
+    /// returns whether the recovered amount in is within one unit of
+    /// `b_token_amount_in`. It falls further short when rounding maps a range
+    /// of inputs to the same output.
+    pub fn quote_roundtrip_consistency(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<bool> {
+        let exact_in = self.quote_swap_with_inputs(b_token_amount_in, x2y, inputs)?;
+        let exact_out = self.quote_swap_exact_out(exact_in.amount_out, x2y, inputs)?;
+
+        Ok(exact_out.amount_in.abs_diff(b_token_amount_in) <= 1)
+    }
+
+    /// Quotes a swap of `b_token_amount_in` btokens in the given direction.
+    ///
+    /// The pool does not know which tokens it holds, so a swap between two
+    /// sides that are actually the same token cannot be told apart from a
+    /// balanced pool of two tokens with equal prices and decimals, and is
+    /// quoted like one. Callers must reject same-token swaps themselves.
+    pub fn quote_swap(
+        &self,
+        b_token_amount_in: u64,
+        price_x: Decimal,
+        price_y: Decimal,
+        x2y: bool,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+        price_confidence_a: Option<Decimal>,
+        price_confidence_b: Option<Decimal>,
+    ) -> Result<SwapQuote> {
+        let inputs = PriceInputs {
+            price_x,
+            price_y,
+            b_token_ratio_x,
+            b_token_ratio_y,
+            confidence_x: price_confidence_a,
+            confidence_y: price_confidence_b,
+        };
+
+        self.quote_swap_with_inputs(b_token_amount_in, x2y, &inputs)
+    }
+
+    /// Same as `quote_swap`, labeling a zero amount out by its cause, as
+    /// reported by the reserve check of the quoter.
+    pub fn quote_swap_outcome(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<QuoteOutcome> {
+        let (quote, diagnostics) = self.quote_swap_instrumented(b_token_amount_in, x2y, inputs)?;
+
+        Ok(if quote.amount_out > 0 {
+            QuoteOutcome::Filled(quote)
+        } else if diagnostics.capped_by_reserves {
+            QuoteOutcome::CappedByReserves
+        } else {
+            QuoteOutcome::RoundsToZero
+        })
+    }
+
+    /// Quotes the same trade against copies of the pool with each of `amps` as
+    /// the amplifier, e.g. to compare candidate values before changing it.
+    /// The invariant is recomputed for each amplifier. Errors if any quote
+    /// fails.
+    pub fn quote_across_amplifiers(
+        &self,
+        b_token_amount_in: u64,
+        amps: &[u32],
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<Vec<SwapQuote>> {
+        amps.iter()
+            .map(|&amplifier| {
+                SteammPool { amplifier, ..*self }.quote_swap_with_inputs(
+                    b_token_amount_in,
+                    x2y,
+                    inputs,
+                )
+            })
+            .collect()
+    }
+
+    /// Same as `quote_swap`, quoting with `version` regardless of the pool's
+    /// `quoter_type`, e.g. to compare the legacy and StableSwap quoters on the
+    /// same pool.
+    pub fn quote_swap_versioned(
+        &self,
+        version: QuoterType,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<SwapQuote> {
+        SteammPool {
+            quoter_type: version,
+            ..*self
+        }
+        .quote_swap_with_inputs(b_token_amount_in, x2y, inputs)
+    }
+
+    /// Same as `quote_swap`, keeping the fees unrounded. The amount out before
+    /// fees is the same btoken amount as for `quote_swap`, and flooring
+    /// `amount_out` gives the `amount_out` of `quote_swap`.
+    pub fn quote_swap_decimal(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<DecimalSwapQuote> {
+        let quote = self.quote_swap_with_inputs(b_token_amount_in, x2y, inputs)?;
+
+        get_decimal_quote(
+            b_token_amount_in,
+            u64::try_from(quote.amount_out as u128 + quote.total_fees())?,
+            x2y,
+            self.fee_bps(inputs)?,
+        )
+    }
+
+    // Fee charged on the amount out, including the price uncertainty fee of
+    // the StableSwap quoter
+    fn fee_bps(&self, inputs: &PriceInputs) -> Result<u64> {
+        let fee_override = match self.quoter_type {
+            QuoterType::Ommv2Legacy => None,
+            QuoterType::Ommv2 => {
+                let (confidence_x, confidence_y) =
+                    require_confidences(inputs.confidence_x, inputs.confidence_y)?;
+                Some(omm_v2_new::price_uncertainty_fee_bps(
+                    inputs.price_x,
+                    inputs.price_y,
+                    confidence_x,
+                    confidence_y,
+                )?)
+            }
+        };
+
+        Ok(effective_fee_bps(self.swap_fee_bps, fee_override))
+    }
+
+    /// Same as `quote_swap`, taking typed amounts and btoken ratios so that an
+    /// underlying amount cannot be passed as the btoken amount in.
+    #[allow(clippy::too_many_arguments)]
+    pub fn quote_swap_typed(
+        &self,
+        b_token_amount_in: BTokenAmount,
+        price_x: Decimal,
+        price_y: Decimal,
+        x2y: bool,
+        b_token_ratio_x: BTokenRatio,
+        b_token_ratio_y: BTokenRatio,
+        price_confidence_a: Option<Decimal>,
+        price_confidence_b: Option<Decimal>,
+    ) -> Result<SwapQuote> {
+        self.quote_swap(
+            b_token_amount_in.0,
+            price_x,
+            price_y,
+            x2y,
+            b_token_ratio_x.0,
+            b_token_ratio_y.0,
+            price_confidence_a,
+            price_confidence_b,
+        )
+    }
+
+    /// Resolves the `(price_x, price_y)` pair to quote with. A side without an
+    /// oracle price gets the price at which the pool is balanced in USD, i.e.
+    /// at which both reserves have the same USD value. Errors if neither side
+    /// has an oracle price, or if a reserve needed for the implied price is
+    /// empty.
+    pub fn resolve_prices(
+        &self,
+        price_source_x: PriceSource,
+        price_source_y: PriceSource,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<(Decimal, Decimal)> {
+        // Price of one side at which its reserve matches the other side in USD
+        let implied_price = |price_other: Decimal, implied_x: bool| {
+            let (b_token_reserve, b_token_ratio, decimals) = if implied_x {
+                (self.b_token_reserve_x, &b_token_ratio_x, self.decimals_x)
+            } else {
+                (self.b_token_reserve_y, &b_token_ratio_y, self.decimals_y)
+            };
+            let (b_token_reserve_other, b_token_ratio_other, decimals_other) = if implied_x {
+                (self.b_token_reserve_y, &b_token_ratio_y, self.decimals_y)
+            } else {
+                (self.b_token_reserve_x, &b_token_ratio_x, self.decimals_x)
+            };
+
+            let reserve = to_underlying(b_token_reserve, b_token_ratio)?;
+            if reserve == 0 {
+                return Err(anyhow::anyhow!(
+                    "Pool implied price undefined for an empty reserve"
+                ));
+            }
+
+            let usd_reserve_other = omm_v2_new::to_usd(
+                to_underlying(b_token_reserve_other, b_token_ratio_other)?,
+                price_other,
+                decimals_other,
+            )?;
+            usd_reserve_other
+                .checked_mul_pow10(decimals)
+                .and_then(|v| v.checked_div(&Decimal::from(reserve)))
+                .ok_or_else(|| anyhow::anyhow!("Pool implied price overflow"))
+        };
+
+        match (price_source_x.oracle_price(), price_source_y.oracle_price()) {
+            (Some(price_x), Some(price_y)) => Ok((price_x, price_y)),
+            (None, Some(price_y)) => Ok((implied_price(price_y, true)?, price_y)),
+            (Some(price_x), None) => Ok((price_x, implied_price(price_x, false)?)),
+            (None, None) => Err(anyhow::anyhow!(
+                "At least one price must come from the oracle"
+            )),
+        }
+    }
+
+    /// Same as `quote_swap`, resolving the prices with `resolve_prices`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn quote_swap_with_price_sources(
+        &self,
+        b_token_amount_in: u64,
+        price_source_x: PriceSource,
+        price_source_y: PriceSource,
+        x2y: bool,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+        price_confidence_a: Option<Decimal>,
+        price_confidence_b: Option<Decimal>,
+    ) -> Result<SwapQuote> {
+        let (price_x, price_y) = self.resolve_prices(
+            price_source_x,
+            price_source_y,
+            b_token_ratio_x,
+            b_token_ratio_y,
+        )?;
+
+        self.quote_swap(
+            b_token_amount_in,
+            price_x,
+            price_y,
+            x2y,
+            b_token_ratio_x,
+            b_token_ratio_y,
+            price_confidence_a,
+            price_confidence_b,
+        )
+    }
+
+    /// Same as `quote_swap`, taking the prices, btoken ratios and confidences
+    /// as `PriceInputs`.
+    pub fn quote_swap_with_inputs(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<SwapQuote> {
+        let (quote, _) = self.quote_swap_instrumented(b_token_amount_in, x2y, inputs)?;
+
+        Ok(quote)
+    }
+
+    /// Returns the btoken amount out before fees, taking the prices and
+    /// btoken ratios as `PriceInputs`. Confidences are ignored.
+    pub fn quote_swap_no_fees_with_inputs(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<u64> {
+        self.quote_swap_no_fees(
+            b_token_amount_in,
+            inputs.price_x,
+            inputs.price_y,
+            x2y,
+            inputs.b_token_ratio_x,
+            inputs.b_token_ratio_y,
+        )
+    }
+
+    /// Same as `quote_swap`, also returning the btoken reserves `(reserve_x,
+    /// reserve_y)` after the swap. The input reserve receives the full amount
+    /// in, and the output reserve pays out the amount out and the protocol
+    /// fees, so the pool fees stay in the pool.
+    pub fn quote_swap_with_reserves(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<(SwapQuote, (u64, u64))> {
+        let quote = self.quote_swap_with_inputs(b_token_amount_in, x2y, inputs)?;
+
+        let (reserve_in, reserve_out) = if x2y {
+            (self.b_token_reserve_x, self.b_token_reserve_y)
+        } else {
+            (self.b_token_reserve_y, self.b_token_reserve_x)
+        };
+        let reserve_in = reserve_in
+            .checked_add(quote.amount_in)
+            .ok_or_else(|| anyhow::anyhow!("Reserve overflow"))?;
+        let reserve_out = quote
+            .amount_out
+            .checked_add(quote.protocol_fees)
+            .and_then(|amount_out| reserve_out.checked_sub(amount_out))
+            .ok_or_else(|| anyhow::anyhow!("Swap output exceeds reserve"))?;
+
+        let reserves = if x2y {
+            (reserve_in, reserve_out)
+        } else {
+            (reserve_out, reserve_in)
+        };
+
+        Ok((quote, reserves))
+    }
+
+    /// Same as `quote_swap`, with the input given as a USD notional. The
+    /// notional is converted into the input token at its price and then into
+    /// btokens, rounding down at each step so the input never exceeds the
+    /// notional.
+    pub fn quote_swap_usd(
+        &self,
+        usd_notional: Decimal,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<SwapQuote> {
+        validate_prices(inputs.price_x, inputs.price_y)?;
+
+        let (price_in, decimals_in, b_token_ratio_in) = if x2y {
+            (inputs.price_x, self.decimals_x, inputs.b_token_ratio_x)
+        } else {
+            (inputs.price_y, self.decimals_y, inputs.b_token_ratio_y)
+        };
+
+        let amount_in = usd_notional
+            .checked_div(&price_in)
+            .ok_or_else(|| anyhow::anyhow!("USD conversion failed"))?
+            .to_token_amount(decimals_in)
+            .ok_or_else(|| anyhow::anyhow!("USD conversion exceeds u64"))?;
+
+        self.quote_swap_with_inputs(to_b_token(amount_in, &b_token_ratio_in)?, x2y, inputs)
+    }
+
+    /// Quotes `b_token_amount_in_x` from x to y and `b_token_amount_in_y` from
+    /// y to x against the current pool state, returning `(x2y, y2x)` quotes.
+    /// The new quoter computes the D invariant once for both directions.
+    pub fn quote_both_directions(
+        &self,
+        b_token_amount_in_x: u64,
+        b_token_amount_in_y: u64,
+        inputs: &PriceInputs,
+    ) -> Result<(SwapQuote, SwapQuote)> {
+        match self.quoter_type {
+            QuoterType::Ommv2Legacy => Ok((
+                self.quote_swap_with_inputs(b_token_amount_in_x, true, inputs)?,
+                self.quote_swap_with_inputs(b_token_amount_in_y, false, inputs)?,
+            )),
+            QuoterType::Ommv2 => {
+                let (confidence_x, confidence_y) =
+                    require_confidences(inputs.confidence_x, inputs.confidence_y)?;
+                omm_v2_new::quote_both_directions(
+                    b_token_amount_in_x,
+                    b_token_amount_in_y,
+                    self.b_token_reserve_x,
+                    self.b_token_reserve_y,
+                    inputs.price_x,
+                    inputs.price_y,
+                    self.decimals_x,
+                    self.decimals_y,
+                    self.amplifier,
+                    inputs.b_token_ratio_x,
+                    inputs.b_token_ratio_y,
+                    self.swap_fee_bps,
+                    confidence_x,
+                    confidence_y,
+                )
+            }
         }
     }
+
+    /// Same as `quote_swap`, returning the net amount out in the underlying
+    /// token instead of btokens.
+    pub fn quote_swap_underlying_out(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<u64> {
+        let quote = self.quote_swap_with_inputs(b_token_amount_in, x2y, inputs)?;
+
+        to_underlying(
+            quote.amount_out,
+            if x2y {
+                &inputs.b_token_ratio_y
+            } else {
+                &inputs.b_token_ratio_x
+            },
+        )
+    }
+
+    /// Same as `quote_swap`, for tokens that charge a fee on transfer. The
+    /// pool only receives `b_token_amount_in` net of `transfer_fee_bps_in`, and
+    /// the trader only receives the output net of `transfer_fee_bps_out`.
+    /// Passing zero for both fees is equivalent to `quote_swap`.
+    ///
+    /// The returned quote reports the gross amount sent by the trader as
+    /// `amount_in` and the net amount received as `amount_out`.
+    pub fn quote_swap_with_transfer_fees(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+        transfer_fee_bps_in: u64,
+        transfer_fee_bps_out: u64,
+    ) -> Result<SwapQuote> {
+        let quote = self.quote_swap_with_inputs(
+            deduct_transfer_fee(b_token_amount_in, transfer_fee_bps_in)?,
+            x2y,
+            inputs,
+        )?;
+
+        Ok(SwapQuote {
+            amount_in: b_token_amount_in,
+            amount_out: deduct_transfer_fee(quote.amount_out, transfer_fee_bps_out)?,
+            ..quote
+        })
+    }
+
+    fn quote_swap_no_fees(
+        &self,
+        b_token_amount_in: u64,
+        price_x: Decimal,
+        price_y: Decimal,
+        x2y: bool,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<u64> {
+        let quote_swap_no_fees = match self.quoter_type {
+            QuoterType::Ommv2Legacy => omm_v2_legacy::quote_swap_no_fees,
+            QuoterType::Ommv2 => omm_v2_new::quote_swap_no_fees,
+        };
+
+        quote_swap_no_fees(
+            b_token_amount_in,
+            self.b_token_reserve_x,
+            self.b_token_reserve_y,
+            price_x,
+            price_y,
+            self.decimals_x,
+            self.decimals_y,
+            self.amplifier,
+            x2y,
+            b_token_ratio_x,
+            b_token_ratio_y,
+        )
+    }
+
+    /// Same as `quote_swap`, also returning the solver iteration counts.
+    pub fn quote_swap_instrumented(
+        &self,
+        b_token_amount_in: u64,
+        x2y: bool,
+        inputs: &PriceInputs,
+    ) -> Result<(SwapQuote, QuoteDiagnostics)> {
+        match self.quoter_type {
+            QuoterType::Ommv2Legacy => omm_v2_legacy::quote_swap_instrumented(
+                b_token_amount_in,
+                self.b_token_reserve_x,
+                self.b_token_reserve_y,
+                inputs.price_x,
+                inputs.price_y,
+                self.decimals_x,
+                self.decimals_y,
+                self.amplifier,
+                x2y,
+                inputs.b_token_ratio_x,
+                inputs.b_token_ratio_y,
+                self.swap_fee_bps,
+            ),
+            QuoterType::Ommv2 => {
+                let (confidence_x, confidence_y) =
+                    require_confidences(inputs.confidence_x, inputs.confidence_y)?;
+                omm_v2_new::quote_swap_instrumented(
+                    b_token_amount_in,
+                    self.b_token_reserve_x,
+                    self.b_token_reserve_y,
+                    inputs.price_x,
+                    inputs.price_y,
+                    self.decimals_x,
+                    self.decimals_y,
+                    self.amplifier,
+                    x2y,
+                    inputs.b_token_ratio_x,
+                    inputs.b_token_ratio_y,
+                    self.swap_fee_bps,
+                    confidence_x,
+                    confidence_y,
+                )
+            }
+        }
+    }
+}
+
+/// Quotes the same trade against several pools for one pair and returns the
+/// index of the pool with the largest amount out, along with its quote. Pools
+/// that fail to quote, e.g. StableSwap pools when `inputs` has no confidences,
+/// or would return nothing are skipped, and ties go to the earliest pool.
+/// Errors if no pool can fill the trade.
+pub fn best_quote(
+    pools: &[SteammPool],
+    b_token_amount_in: u64,
+    x2y: bool,
+    inputs: &PriceInputs,
+) -> Result<(usize, SwapQuote)> {
+    let mut best: Option<(usize, SwapQuote)> = None;
+
+    for (index, pool) in pools.iter().enumerate() {
+        let Ok(quote) = pool.quote_swap_with_inputs(b_token_amount_in, x2y, inputs) else {
+            continue;
+        };
+
+        if quote.amount_out > best.as_ref().map_or(0, |(_, best)| best.amount_out) {
+            best = Some((index, quote));
+        }
+    }
+
+    best.ok_or_else(|| anyhow::anyhow!("No pool can fill a swap of {}", b_token_amount_in))
+}
+
+/// Splits `b_token_amount_in` across several pools for one pair to maximize
+/// the total amount out, returning `(pool index, amount in)` for each pool
+/// that receives part of the order.
+///
+/// The order is divided into `SPLIT_ORDER_STEPS` chunks, each allocated to the
+/// pool whose output grows the most by taking it. Since every pool's output is
+/// concave in its input, this greedy allocation follows the marginal prices of
+/// the pools down until they meet. Pools that fail to quote, e.g. StableSwap
+/// pools when `inputs` has no confidences, are skipped.
+pub fn split_order(
+    pools: &[SteammPool],
+    b_token_amount_in: u64,
+    x2y: bool,
+    inputs: &PriceInputs,
+) -> Result<Vec<(usize, u64)>> {
+    let quote = |pool: &SteammPool, amount_in: u64| -> Result<u64> {
+        if amount_in == 0 {
+            return Ok(0);
+        }
+
+        Ok(pool
+            .quote_swap_with_inputs(amount_in, x2y, inputs)?
+            .amount_out)
+    };
+
+    let mut amounts_in = vec![0u64; pools.len()];
+    let mut amounts_out = vec![0u64; pools.len()];
+    let chunk = b_token_amount_in / SPLIT_ORDER_STEPS;
+    let mut remaining = b_token_amount_in;
+
+    while remaining > 0 {
+        // The last chunk also takes the rounding remainder
+        let step = if remaining - chunk < chunk || chunk == 0 {
+            remaining
+        } else {
+            chunk
+        };
+
+        let mut best: Option<(usize, u64, u64)> = None;
+        for (index, pool) in pools.iter().enumerate() {
+            let Ok(amount_out) = quote(pool, amounts_in[index] + step) else {
+                continue;
+            };
+            let gain = amount_out.saturating_sub(amounts_out[index]);
+            if best.is_none_or(|(_, best_gain, _)| gain > best_gain) {
+                best = Some((index, gain, amount_out));
+            }
+        }
+
+        let (index, _, amount_out) = best
+            .ok_or_else(|| anyhow::anyhow!("No pool can fill a swap of {}", b_token_amount_in))?;
+        amounts_in[index] += step;
+        amounts_out[index] = amount_out;
+        remaining -= step;
+    }
+
+    Ok(amounts_in
+        .into_iter()
+        .enumerate()
+        .filter(|(_, amount_in)| *amount_in > 0)
+        .collect())
+}
+
+/// Returns the amount left after a transfer fee of `transfer_fee_bps`. The fee
+/// is rounded up, in favour of the token.
+fn deduct_transfer_fee(amount: u64, transfer_fee_bps: u64) -> Result<u64> {
+    if transfer_fee_bps > BPS_SCALE {
+        return Err(anyhow::anyhow!(
+            "Transfer fee exceeds 100%: {} bps",
+            transfer_fee_bps
+        ));
+    }
+
+    Ok(amount - safe_mul_div_up(amount, transfer_fee_bps, BPS_SCALE)?)
+}
+
+/// Checks that both oracle prices are nonzero. A stale or missing oracle can
+/// report a zero price, which the quoters cannot convert to or from USD.
+pub(crate) fn validate_prices(price_x: Decimal, price_y: Decimal) -> Result<()> {
+    if price_x.is_zero() {
+        return Err(anyhow::anyhow!("Price x must be greater than zero"));
+    }
+    if price_y.is_zero() {
+        return Err(anyhow::anyhow!("Price y must be greater than zero"));
+    }
+
+    Ok(())
+}
+
+/// Returns both price confidences, which the StableSwap quoter needs for its
+/// price uncertainty fee.
+pub(crate) fn require_confidences(
+    price_confidence_a: Option<Decimal>,
+    price_confidence_b: Option<Decimal>,
+) -> Result<(Decimal, Decimal)> {
+    price_confidence_a
+        .zip(price_confidence_b)
+        .ok_or_else(|| anyhow::anyhow!("missing price confidence for Ommv2"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_swap_instrumented() -> Result<()> {
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 10, 30, quoter_type);
+            let args = (
+                10_000_000_000,
+                Decimal::from("3"),
+                Decimal::from("1"),
+                true,
+                Decimal::from("1.0"),
+                Decimal::from("1.0"),
+                Some(Decimal::from("0.001")),
+                Some(Decimal::from("0.0001")),
+            );
+
+            let quote = pool.quote_swap(
+                args.0, args.1, args.2, args.3, args.4, args.5, args.6, args.7,
+            )?;
+            let inputs = PriceInputs::new(args.1, args.2)
+                .with_b_token_ratios(args.4, args.5)
+                .with_confidences(args.6.unwrap(), args.7.unwrap());
+            let (instrumented_quote, diagnostics) =
+                pool.quote_swap_instrumented(args.0, args.3, &inputs)?;
+            assert_eq!(quote.amount_out, instrumented_quote.amount_out);
+
+            match quoter_type {
+                QuoterType::Ommv2Legacy => {
+                    assert!(diagnostics.newton_raphson_iterations > 0);
+                    assert_eq!(diagnostics.get_d_iterations, 0);
+                    assert_eq!(diagnostics.get_y_iterations, 0);
+                }
+                QuoterType::Ommv2 => {
+                    assert_eq!(diagnostics.newton_raphson_iterations, 0);
+                    assert!(diagnostics.get_d_iterations > 0);
+                    assert!(diagnostics.get_y_iterations > 0);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_output_bounds_quotes() -> Result<()> {
+        let b_token_ratio_x = Decimal::from("1.1");
+        let b_token_ratio_y = Decimal::from("1.03");
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            for amplifier in [1, 10, 100, 1000] {
+                let pool = SteammPool::new(
+                    1_000_000_000_000,
+                    3_000_000_000,
+                    9,
+                    6,
+                    amplifier,
+                    30,
+                    quoter_type,
+                );
+
+                for x2y in [true, false] {
+                    let max_output = pool.max_output(x2y, b_token_ratio_x, b_token_ratio_y)?;
+                    let reserve_out = if x2y {
+                        pool.b_token_reserve_y
+                    } else {
+                        pool.b_token_reserve_x
+                    };
+                    assert!(max_output <= reserve_out);
+                    assert!(max_output + 1 >= reserve_out);
+
+                    for amount_in in [
+                        1_000,
+                        1_000_000,
+                        1_000_000_000,
+                        1_000_000_000_000,
+                        u64::MAX / 4,
+                    ] {
+                        let quote = pool.quote_swap(
+                            amount_in,
+                            Decimal::from("3"),
+                            Decimal::from("1"),
+                            x2y,
+                            b_token_ratio_x,
+                            b_token_ratio_y,
+                            Some(Decimal::from(0u64)),
+                            Some(Decimal::from(0u64)),
+                        );
+                        if let Ok(quote) = quote {
+                            assert!(quote.amount_out < max_output);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_both_directions() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (b_token_ratio_x, b_token_ratio_y) = (Decimal::from("1.1"), Decimal::from("1.03"));
+        let confidence = (Some(Decimal::from("0.001")), Some(Decimal::from("0.0001")));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            for amplifier in [1, 10, 100, 1000] {
+                let pool = SteammPool::new(
+                    1_000_000_000_000,
+                    3_000_000_000,
+                    9,
+                    6,
+                    amplifier,
+                    30,
+                    quoter_type,
+                );
+                let inputs = PriceInputs::new(price_x, price_y)
+                    .with_b_token_ratios(b_token_ratio_x, b_token_ratio_y)
+                    .with_confidences(confidence.0.unwrap(), confidence.1.unwrap());
+                let (x2y, y2x) = pool.quote_both_directions(10_000_000_000, 30_000_000, &inputs)?;
+
+                let expected_x2y = pool.quote_swap(
+                    10_000_000_000,
+                    price_x,
+                    price_y,
+                    true,
+                    b_token_ratio_x,
+                    b_token_ratio_y,
+                    confidence.0,
+                    confidence.1,
+                )?;
+                let expected_y2x = pool.quote_swap(
+                    30_000_000,
+                    price_x,
+                    price_y,
+                    false,
+                    b_token_ratio_x,
+                    b_token_ratio_y,
+                    confidence.0,
+                    confidence.1,
+                )?;
+
+                for (quote, expected, a2b) in
+                    [(x2y, expected_x2y, true), (y2x, expected_y2x, false)]
+                {
+                    assert_eq!(quote.a2b, a2b);
+                    assert_eq!(quote.amount_in, expected.amount_in);
+                    assert_eq!(quote.amount_out, expected.amount_out);
+                    assert_eq!(quote.protocol_fees, expected.protocol_fees);
+                    assert_eq!(quote.pool_fees, expected.pool_fees);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_rejects_zero_price() {
+        let zero = Decimal::from(0u64);
+        let price = Decimal::from("3");
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 10, 30, quoter_type);
+
+            for x2y in [true, false] {
+                for (price_x, price_y, side) in [(zero, price, "x"), (price, zero, "y")] {
+                    let err = pool
+                        .quote_swap(
+                            10_000_000,
+                            price_x,
+                            price_y,
+                            x2y,
+                            Decimal::from("1.0"),
+                            Decimal::from("1.0"),
+                            Some(Decimal::from("0.001")),
+                            Some(Decimal::from("0.001")),
+                        )
+                        .unwrap_err();
+                    assert_eq!(
+                        err.to_string(),
+                        format!("Price {} must be greater than zero", side)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_health() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+
+        // (reserve x, reserve y, expected) for a SUI/USDC pool
+        let cases = [
+            // 1_000 SUI and 3_000 USDC, balanced
+            (1_000_000_000_000, 3_000_000_000, PoolHealth::Ok),
+            // 50:50 in USD but not in units
+            (10_000_000_000, 30_000_000, PoolHealth::Ok),
+            // 99:1 in USD
+            (1_000_000_000_000, 30_303_030, PoolHealth::Imbalanced),
+            (10_101_010_100, 3_000_000_000, PoolHealth::Imbalanced),
+            // 99.99:0.01 in USD
+            (1_000_000_000_000, 300_030, PoolHealth::NearEmpty),
+            (1_000_000_000_000, 0, PoolHealth::NearEmpty),
+            (0, 0, PoolHealth::NearEmpty),
+        ];
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            for amplifier in [1, 10, 100, 1000] {
+                for (reserve_x, reserve_y, expected) in cases {
+                    let pool =
+                        SteammPool::new(reserve_x, reserve_y, 9, 6, amplifier, 30, quoter_type);
+                    assert_eq!(pool.health(price_x, price_y, ratio, ratio)?, expected);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_with_transfer_fees() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+        let confidence = Some(Decimal::from("0.0001"));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 10, 30, quoter_type);
+            let quote = |amount_in: u64| {
+                pool.quote_swap(
+                    amount_in, price_x, price_y, true, ratio, ratio, confidence, confidence,
+                )
+            };
+            let inputs = PriceInputs::new(price_x, price_y)
+                .with_confidences(confidence.unwrap(), confidence.unwrap());
+            let quote_with_transfer_fees = |fee_in: u64, fee_out: u64| {
+                pool.quote_swap_with_transfer_fees(10_000_000_000, true, &inputs, fee_in, fee_out)
+            };
+
+            // No transfer fees is the plain quote
+            let plain = quote(10_000_000_000)?;
+            let no_fees = quote_with_transfer_fees(0, 0)?;
+            assert_eq!(no_fees.amount_in, plain.amount_in);
+            assert_eq!(no_fees.amount_out, plain.amount_out);
+            assert_eq!(no_fees.pool_fees, plain.pool_fees);
+
+            // 30bps on the way in: only 9.97 SUI reach the pool
+            let net_in = quote(9_970_000_000)?;
+            let fee_in = quote_with_transfer_fees(30, 0)?;
+            assert_eq!(fee_in.amount_in, 10_000_000_000);
+            assert_eq!(fee_in.amount_out, net_in.amount_out);
+            assert_eq!(fee_in.protocol_fees, net_in.protocol_fees);
+            assert_eq!(fee_in.pool_fees, net_in.pool_fees);
+
+            // 30bps on the way out, rounded in favour of the token
+            let fee_out = quote_with_transfer_fees(0, 30)?;
+            let transfer_fee = (plain.amount_out * 30).div_ceil(10_000);
+            assert_eq!(fee_out.amount_out, plain.amount_out - transfer_fee);
+
+            let fee_both = quote_with_transfer_fees(30, 30)?;
+            assert!(fee_both.amount_out < fee_in.amount_out);
+            assert!(fee_both.amount_out < fee_out.amount_out);
+
+            assert!(quote_with_transfer_fees(10_001, 0).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spot_price() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (b_token_ratio_x, b_token_ratio_y) = (Decimal::from("1.1"), Decimal::from("1.1"));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            // Balanced pool: the spot price is the oracle price, in btokens
+            let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 10, 30, quoter_type);
+            let spot_x2y =
+                pool.spot_price(price_x, price_y, true, b_token_ratio_x, b_token_ratio_y)?;
+            let spot_y2x =
+                pool.spot_price(price_x, price_y, false, b_token_ratio_x, b_token_ratio_y)?;
+            // 1 bSUI = 1.1 SUI = 3.3 USDC = 3 bUSDC
+            assert!(spot_x2y.within_bps(&Decimal::from("0.003"), 1));
+            assert!(spot_y2x.within_bps(&Decimal::from("333.333333333333333333"), 1));
+
+            // Tiny trades execute at the spot price
+            for (x2y, spot) in [(true, spot_x2y), (false, spot_y2x)] {
+                let amount_in = if x2y { 1_000_000_000 } else { 1_000_000 };
+                let amount_out = pool.quote_swap_no_fees(
+                    amount_in,
+                    price_x,
+                    price_y,
+                    x2y,
+                    b_token_ratio_x,
+                    b_token_ratio_y,
+                )?;
+                let execution_price = Decimal::from(amount_out)
+                    .checked_div(&Decimal::from(amount_in))
+                    .unwrap();
+                assert!(execution_price.within_bps(&spot, 1));
+                assert!(execution_price <= spot);
+            }
+        }
+
+        // StableSwap prices the scarce side higher on an imbalanced pool
+        let pool = SteammPool::new(
+            1_000_000_000_000,
+            1_000_000_000,
+            9,
+            6,
+            10,
+            30,
+            QuoterType::Ommv2,
+        );
+        let spot_x2y = pool.spot_price(
+            price_x,
+            price_y,
+            true,
+            Decimal::from(1u64),
+            Decimal::from(1u64),
+        )?;
+        assert!(spot_x2y < Decimal::from("0.003"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_marginal_price_with_fee() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (b_token_ratio_x, b_token_ratio_y) = (Decimal::from("1.1"), Decimal::from("1.1"));
+        let confidence = Some(Decimal::from("0.0"));
+        let inputs = PriceInputs::new(price_x, price_y)
+            .with_b_token_ratios(b_token_ratio_x, b_token_ratio_y)
+            .with_confidences(Decimal::from("0.0"), Decimal::from("0.0"));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 10, 30, quoter_type);
+            for x2y in [true, false] {
+                let spot =
+                    pool.spot_price(price_x, price_y, x2y, b_token_ratio_x, b_token_ratio_y)?;
+                let marginal = pool.marginal_price_with_fee(x2y, &inputs)?;
+                assert_eq!(marginal, spot.checked_mul(&Decimal::from("0.997")).unwrap());
+
+                // Tiny trades execute at the marginal price, fees included
+                let amount_in = if x2y { 1_000_000_000 } else { 1_000_000 };
+                let quote = pool.quote_swap(
+                    amount_in,
+                    price_x,
+                    price_y,
+                    x2y,
+                    b_token_ratio_x,
+                    b_token_ratio_y,
+                    confidence,
+                    confidence,
+                )?;
+                let execution_price = Decimal::from(quote.amount_out)
+                    .checked_div(&Decimal::from(amount_in))
+                    .unwrap();
+                assert!(execution_price.within_bps(&marginal, 1));
+                assert!(execution_price <= marginal);
+            }
+        }
+
+        // Missing confidences are an error for StableSwap only
+        let pool = SteammPool::new(
+            1_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            10,
+            30,
+            QuoterType::Ommv2,
+        );
+        let missing_x = PriceInputs {
+            confidence_x: None,
+            ..inputs
+        };
+        let err = pool.marginal_price_with_fee(true, &missing_x).unwrap_err();
+        assert_eq!(err.to_string(), "missing price confidence for Ommv2");
+
+        let legacy = SteammPool {
+            quoter_type: QuoterType::Ommv2Legacy,
+            ..pool
+        };
+        assert_eq!(
+            legacy.marginal_price_with_fee(
+                true,
+                &PriceInputs::new(price_x, price_y)
+                    .with_b_token_ratios(b_token_ratio_x, b_token_ratio_y)
+            )?,
+            legacy
+                .spot_price(price_x, price_y, true, b_token_ratio_x, b_token_ratio_y)?
+                .checked_mul(&Decimal::from("0.997"))
+                .unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_for_price_impact_bps() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            for amplifier in [1, 10, 100] {
+                let pool = SteammPool::new(
+                    1_000_000_000_000,
+                    3_000_000_000,
+                    9,
+                    6,
+                    amplifier,
+                    30,
+                    quoter_type,
+                );
+
+                for x2y in [true, false] {
+                    for target_bps in [1, 10, 50, 100, 1_000, 5_000] {
+                        let amount_in = pool.input_for_price_impact_bps(
+                            target_bps, price_x, price_y, x2y, ratio, ratio,
+                        )?;
+                        let impact =
+                            pool.price_impact_bps(amount_in, price_x, price_y, x2y, ratio, ratio)?;
+                        let impact_before = pool.price_impact_bps(
+                            amount_in - 1,
+                            price_x,
+                            price_y,
+                            x2y,
+                            ratio,
+                            ratio,
+                        )?;
+
+                        assert!(impact >= target_bps && impact <= target_bps + 1);
+                        assert!(impact_before < target_bps);
+                    }
+                }
+
+                assert_eq!(
+                    pool.input_for_price_impact_bps(0, price_x, price_y, true, ratio, ratio)?,
+                    0
+                );
+                assert!(
+                    pool.input_for_price_impact_bps(10_000, price_x, price_y, true, ratio, ratio)
+                        .is_err()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_underlying_out() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (b_token_ratio_x, b_token_ratio_y) = (Decimal::from("1.1"), Decimal::from("1.03"));
+        let confidence = Some(Decimal::from("0.0001"));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 10, 30, quoter_type);
+
+            for (x2y, amount_in, b_token_ratio_out) in [
+                (true, 10_000_000_000, b_token_ratio_y),
+                (false, 30_000_000, b_token_ratio_x),
+            ] {
+                let quote = pool.quote_swap(
+                    amount_in,
+                    price_x,
+                    price_y,
+                    x2y,
+                    b_token_ratio_x,
+                    b_token_ratio_y,
+                    confidence,
+                    confidence,
+                )?;
+                let inputs = PriceInputs::new(price_x, price_y)
+                    .with_b_token_ratios(b_token_ratio_x, b_token_ratio_y)
+                    .with_confidences(confidence.unwrap(), confidence.unwrap());
+                let underlying_out = pool.quote_swap_underlying_out(amount_in, x2y, &inputs)?;
+
+                let expected = Decimal::from(quote.amount_out)
+                    .checked_mul(&b_token_ratio_out)
+                    .unwrap();
+                assert!(underlying_out > quote.amount_out);
+                assert!(Decimal::from(underlying_out) <= expected);
+                assert!(Decimal::from(underlying_out + 1) > expected);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_slippage_bps() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            for swap_fee_bps in [0, 30, 100] {
+                let pool = SteammPool::new(
+                    1_000_000_000_000,
+                    3_000_000_000,
+                    9,
+                    6,
+                    10,
+                    swap_fee_bps,
+                    quoter_type,
+                );
+                let spot_price = pool.spot_price(price_x, price_y, true, ratio, ratio)?;
+
+                for amount_in in [1_000_000_000, 10_000_000_000, 100_000_000_000] {
+                    let quote = pool.quote_swap(
+                        amount_in,
+                        price_x,
+                        price_y,
+                        true,
+                        ratio,
+                        ratio,
+                        Some(Decimal::ZERO),
+                        Some(Decimal::ZERO),
+                    )?;
+                    let price_impact =
+                        pool.price_impact_bps(amount_in, price_x, price_y, true, ratio, ratio)?;
+                    let total_slippage = quote.total_slippage_bps(spot_price);
+
+                    // Fees apply on top of the impact, give or take rounding
+                    assert!(total_slippage >= price_impact);
+                    assert!(total_slippage + 1 >= price_impact + swap_fee_bps);
+                    assert!(total_slippage <= price_impact + swap_fee_bps + 1);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quoter_type_from_version_tag() -> Result<()> {
+        for tag in ["Ommv2Legacy", "omm_v2_legacy", "OMMV2LEGACY"] {
+            assert_eq!(QuoterType::from_version_tag(tag)?, QuoterType::Ommv2Legacy);
+        }
+        for tag in ["Ommv2", "Ommv2New", "omm_v2_new", "omm_v2_1", "OmmV2_1"] {
+            assert_eq!(QuoterType::from_version_tag(tag)?, QuoterType::Ommv2);
+        }
+
+        for tag in ["", "omm", "omm_v2_2", "Ommv2 "] {
+            let err = QuoterType::from_version_tag(tag).unwrap_err();
+            assert_eq!(err.to_string(), format!("Unknown quoter tag: {:?}", tag));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_best_quote() -> Result<()> {
+        let pools = [
+            SteammPool::new(
+                1_000_000_000_000,
+                3_000_000_000,
+                9,
+                6,
+                1,
+                30,
+                QuoterType::Ommv2,
+            ),
+            // Empty output side, so this pool cannot quote
+            SteammPool::new(1_000_000_000_000, 0, 9, 6, 1000, 30, QuoterType::Ommv2),
+            SteammPool::new(
+                10_000_000_000_000,
+                30_000_000_000,
+                9,
+                6,
+                100,
+                30,
+                QuoterType::Ommv2,
+            ),
+            SteammPool::new(
+                1_000_000_000_000,
+                3_000_000_000,
+                9,
+                6,
+                100,
+                30,
+                QuoterType::Ommv2,
+            ),
+        ];
+        let inputs = PriceInputs::new(Decimal::from("3"), Decimal::from("1"))
+            .with_confidences(Decimal::from("0.001"), Decimal::from("0.0001"));
+        let quote = |pools: &[SteammPool], amount_in| best_quote(pools, amount_in, true, &inputs);
+
+        // The deepest pool wins
+        let (index, best) = quote(&pools, 100_000_000_000)?;
+        assert_eq!(index, 2);
+        for (i, pool) in pools.iter().enumerate() {
+            if let Ok(other) = pool.quote_swap(
+                100_000_000_000,
+                Decimal::from("3"),
+                Decimal::from("1"),
+                true,
+                Decimal::from("1.0"),
+                Decimal::from("1.0"),
+                Some(Decimal::from("0.001")),
+                Some(Decimal::from("0.0001")),
+            ) && i != index
+            {
+                assert!(other.amount_out < best.amount_out);
+            }
+        }
+
+        // Ties go to the earliest pool
+        let twins = [1, 2].map(|_| {
+            SteammPool::new(
+                1_000_000_000_000,
+                3_000_000_000,
+                9,
+                6,
+                100,
+                30,
+                QuoterType::Ommv2,
+            )
+        });
+        let (index, _) = quote(&twins, 100_000_000_000)?;
+        assert_eq!(index, 0);
+
+        // Dust rounds to zero everywhere
+        let err = quote(&pools, 1).unwrap_err();
+        assert_eq!(err.to_string(), "No pool can fill a swap of 1");
+        assert!(quote(&pools[1..2], 100_000_000_000).is_err());
+        assert!(quote(&[], 100_000_000_000).is_err());
+
+        // StableSwap pools cannot quote without confidences and are skipped
+        let mixed = [
+            SteammPool { ..pools[2] },
+            SteammPool {
+                quoter_type: QuoterType::Ommv2Legacy,
+                ..pools[0]
+            },
+        ];
+        let inputs = PriceInputs::new(Decimal::from("3"), Decimal::from("1"));
+        let (index, best) = best_quote(&mixed, 100_000_000_000, true, &inputs)?;
+        assert_eq!(index, 1);
+        assert_eq!(
+            best,
+            mixed[1].quote_swap_with_inputs(100_000_000_000, true, &inputs)?
+        );
+        assert!(best_quote(&pools, 100_000_000_000, true, &inputs).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_order() -> Result<()> {
+        let pools = [
+            SteammPool::new(
+                5_000_000_000_000,
+                15_000_000_000,
+                9,
+                6,
+                1,
+                30,
+                QuoterType::Ommv2,
+            ),
+            // Empty output side, so this pool cannot quote
+            SteammPool::new(5_000_000_000_000, 0, 9, 6, 1, 30, QuoterType::Ommv2),
+            SteammPool::new(
+                10_000_000_000_000,
+                30_000_000_000,
+                9,
+                6,
+                1,
+                30,
+                QuoterType::Ommv2,
+            ),
+        ];
+        let prices = (Decimal::from("3"), Decimal::from("1"));
+        let ratios = (Decimal::from("1.0"), Decimal::from("1.0"));
+        let confidences = (Some(Decimal::from("0.001")), Some(Decimal::from("0.0001")));
+        let amount_out = |pool: &SteammPool, amount_in| {
+            pool.quote_swap(
+                amount_in,
+                prices.0,
+                prices.1,
+                true,
+                ratios.0,
+                ratios.1,
+                confidences.0,
+                confidences.1,
+            )
+            .unwrap()
+            .amount_out
+        };
+
+        let inputs = PriceInputs::new(prices.0, prices.1)
+            .with_b_token_ratios(ratios.0, ratios.1)
+            .with_confidences(confidences.0.unwrap(), confidences.1.unwrap());
+
+        let amount_in = 3_000_000_000_000; // 3_000 SUI
+        let split = split_order(&pools, amount_in, true, &inputs)?;
+
+        // Both healthy pools are used, roughly in proportion to their depth
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].0, 0);
+        assert_eq!(split[1].0, 2);
+        assert_eq!(
+            split.iter().map(|(_, amount)| amount).sum::<u64>(),
+            amount_in
+        );
+        assert!(split[1].1 > split[0].1);
+
+        let (best_index, best) = best_quote(&pools, amount_in, true, &inputs)?;
+        assert_eq!(best_index, 2);
+
+        let split_out: u64 = split
+            .iter()
+            .map(|(index, amount)| amount_out(&pools[*index], *amount))
+            .sum();
+        assert!(split_out > best.amount_out);
+
+        // Nothing to route
+        let split = split_order(&pools, 0, true, &inputs)?;
+        assert!(split.is_empty());
+
+        // StableSwap pools cannot quote without confidences and are skipped
+        let mixed = [
+            SteammPool { ..pools[2] },
+            SteammPool {
+                quoter_type: QuoterType::Ommv2Legacy,
+                ..pools[0]
+            },
+        ];
+        let inputs = PriceInputs::new(prices.0, prices.1);
+        assert_eq!(
+            split_order(&mixed, amount_in, true, &inputs)?,
+            vec![(1, amount_in)]
+        );
+        let err = split_order(&pools, amount_in, true, &inputs).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("No pool can fill a swap of {}", amount_in)
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_quote_emits_tracing_span() -> Result<()> {
+        use std::sync::{Arc, Mutex};
+        use tracing::{
+            Event, Metadata, Subscriber,
+            span::{Attributes, Id, Record},
+        };
+
+        /// Records the names of the spans and events it sees.
+        #[derive(Clone, Default)]
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name().to_string());
+                Id::from_u64(names.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut names = self.0.lock().unwrap();
+                names.push(event.metadata().target().to_string());
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        for (quoter_type, span, target) in [
+            (
+                QuoterType::Ommv2Legacy,
+                "omm_v2_legacy::quote",
+                "steamm_ox::omm::omm_v2_legacy",
+            ),
+            (
+                QuoterType::Ommv2,
+                "omm_v2_new::quote",
+                "steamm_ox::omm::omm_v2_new",
+            ),
+        ] {
+            let recorder = Recorder::default();
+            let pool = SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 10, 30, quoter_type);
+            tracing::subscriber::with_default(recorder.clone(), || {
+                pool.quote_swap(
+                    10_000_000_000,
+                    Decimal::from("3"),
+                    Decimal::from("1"),
+                    true,
+                    Decimal::from("1.0"),
+                    Decimal::from("1.0"),
+                    Some(Decimal::from("0.001")),
+                    Some(Decimal::from("0.0001")),
+                )
+            })?;
+
+            let names = recorder.0.lock().unwrap();
+            assert_eq!(names[..], [span.to_string(), target.to_string()]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_outcome() -> Result<()> {
+        let (price_btc, price_usdc) = (Decimal::from("100000"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+        let confidence = Some(Decimal::from("0.0"));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            // 1 BTC (8 decimals) and 100_000 USDC (6 decimals)
+            let pool = SteammPool::new(100_000_000, 100_000_000_000, 8, 6, 100, 30, quoter_type);
+            let inputs = PriceInputs::new(price_btc, price_usdc)
+                .with_confidences(Decimal::from("0.0"), Decimal::from("0.0"));
+            let outcome = |amount_in, x2y| pool.quote_swap_outcome(amount_in, x2y, &inputs);
+
+            // 1 unit of USDC is worth 0.001 units of BTC
+            assert_eq!(outcome(1, false)?, QuoteOutcome::RoundsToZero);
+            // 999 units are worth just under one unit of BTC
+            assert_eq!(outcome(999, false)?, QuoteOutcome::RoundsToZero);
+            assert_eq!(outcome(0, false)?, QuoteOutcome::RoundsToZero);
+
+            let QuoteOutcome::Filled(quote) = outcome(1_000_000, false)? else {
+                panic!("1 USDC should buy some BTC");
+            };
+            assert_eq!(
+                quote,
+                pool.quote_swap(
+                    1_000_000, price_btc, price_usdc, false, ratio, ratio, confidence, confidence,
+                )?
+            );
+            // Even a trade worth far more than the reserve pays out something
+            assert!(matches!(
+                outcome(10_000_000_000_000, false)?,
+                QuoteOutcome::Filled(_)
+            ));
+            assert!(matches!(outcome(1, true)?, QuoteOutcome::Filled(_)));
+        }
+
+        // On a tiny pool, a StableSwap trade worth far more than the reserve
+        // still passes the reserve check, which only refuses more than the
+        // whole reserve
+        let pool = SteammPool::new(10, 10, 0, 0, 100, 0, QuoterType::Ommv2);
+        let inputs = PriceInputs::new(Decimal::from("1"), Decimal::from("1.01"))
+            .with_confidences(Decimal::from("0.0"), Decimal::from("0.0"));
+        let outcome = |amount_in| pool.quote_swap_outcome(amount_in, true, &inputs);
+        assert_eq!(outcome(0)?, QuoteOutcome::RoundsToZero);
+        let QuoteOutcome::Filled(quote) = outcome(1_000_000_000_000)? else {
+            panic!("StableSwap pays out the whole reserve");
+        };
+        assert_eq!(quote.amount_out, 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_versioned() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+        let confidence = Some(Decimal::from("0.0"));
+        let legacy = SteammPool::new(
+            1_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            100,
+            30,
+            QuoterType::Ommv2Legacy,
+        );
+        let stable = SteammPool {
+            quoter_type: QuoterType::Ommv2,
+            ..legacy
+        };
+
+        for (amount_in, x2y) in [(10_000_000_000, true), (300_000_000, false)] {
+            let quote = |pool: &SteammPool| {
+                pool.quote_swap(
+                    amount_in, price_x, price_y, x2y, ratio, ratio, confidence, confidence,
+                )
+            };
+            let (legacy_quote, stable_quote) = (quote(&legacy)?, quote(&stable)?);
+
+            // The pool's own quoter type makes no difference
+            for pool in [&legacy, &stable] {
+                let inputs = PriceInputs::new(price_x, price_y)
+                    .with_confidences(Decimal::from("0.0"), Decimal::from("0.0"));
+                let versioned =
+                    |version| pool.quote_swap_versioned(version, amount_in, x2y, &inputs);
+                assert_eq!(versioned(QuoterType::Ommv2Legacy)?, legacy_quote);
+                assert_eq!(versioned(QuoterType::Ommv2)?, stable_quote);
+            }
+
+            assert_ne!(legacy_quote.amount_out, stable_quote.amount_out);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_across_amplifiers() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+        let confidence = Some(Decimal::from("0.0"));
+        let amps = [1, 10, 100, 1_000, 8_000];
+        let inputs = PriceInputs::new(price_x, price_y)
+            .with_confidences(Decimal::from("0.0"), Decimal::from("0.0"));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            // Balanced: 1_000 SUI and 3_000 USDC
+            let pool =
+                SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 100, 30, quoter_type);
+
+            for (amount_in, x2y) in [(10_000_000_000, true), (30_000_000, false)] {
+                let quotes = pool.quote_across_amplifiers(amount_in, &amps, x2y, &inputs)?;
+                assert_eq!(quotes.len(), amps.len());
+
+                for (quote, amplifier) in quotes.iter().zip(amps) {
+                    let pool = SteammPool { amplifier, ..pool };
+                    assert_eq!(
+                        *quote,
+                        pool.quote_swap(
+                            amount_in, price_x, price_y, x2y, ratio, ratio, confidence, confidence,
+                        )?
+                    );
+                }
+
+                // Near balance a flatter curve pays out more
+                assert!(
+                    quotes
+                        .windows(2)
+                        .all(|pair| pair[0].amount_out <= pair[1].amount_out)
+                );
+                assert!(quotes[0].amount_out < quotes[amps.len() - 1].amount_out);
+            }
+
+            assert!(
+                pool.quote_across_amplifiers(1_000, &[], true, &inputs)?
+                    .is_empty()
+            );
+            assert!(
+                pool.quote_across_amplifiers(1_000, &[100, 0], true, &inputs)
+                    .is_err()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_decimal() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (ratio_x, ratio_y) = (Decimal::from("1.02"), Decimal::from("1.01"));
+
+        for (quoter_type, confidence) in [
+            (QuoterType::Ommv2Legacy, None),
+            (QuoterType::Ommv2, Some(Decimal::from("0"))),
+            // 0.006 on a $3 price raises the fee to 20 bps, above the pool fee
+            (QuoterType::Ommv2, Some(Decimal::from("0.006"))),
+        ] {
+            for swap_fee_bps in [1, 5, 30] {
+                let pool = SteammPool::new(
+                    1_000_000_000_000,
+                    3_000_000_000,
+                    9,
+                    6,
+                    100,
+                    swap_fee_bps,
+                    quoter_type,
+                );
+                let mut total = Decimal::ZERO;
+                let mut floored_total = 0;
+
+                for amount_in in [1, 777, 1_000_000, 123_456_789, 10_000_000_000] {
+                    let quote = pool.quote_swap(
+                        amount_in, price_x, price_y, true, ratio_x, ratio_y, confidence, confidence,
+                    )?;
+                    let inputs = PriceInputs {
+                        price_x,
+                        price_y,
+                        b_token_ratio_x: ratio_x,
+                        b_token_ratio_y: ratio_y,
+                        confidence_x: confidence,
+                        confidence_y: confidence,
+                    };
+                    let decimal_quote = pool.quote_swap_decimal(amount_in, true, &inputs)?;
+
+                    assert_eq!(decimal_quote.amount_in, quote.amount_in);
+                    assert_eq!(decimal_quote.a2b, quote.a2b);
+                    assert_eq!(
+                        decimal_quote.amount_out.checked_floor(),
+                        Some(quote.amount_out)
+                    );
+                    assert_eq!(
+                        decimal_quote.total_fees().checked_ceil(),
+                        Some(quote.total_fees())
+                    );
+
+                    total = total.checked_add(&decimal_quote.amount_out).unwrap();
+                    floored_total += quote.amount_out;
+                }
+
+                // Flooring once loses less than flooring every quote
+                assert!(total.checked_floor::<u64>().unwrap() >= floored_total);
+            }
+        }
+
+        // The StableSwap fee needs both confidences
+        let pool = SteammPool::new(
+            1_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            100,
+            30,
+            QuoterType::Ommv2,
+        );
+        let confidence = Some(Decimal::from("0"));
+        for (confidence_x, confidence_y) in [(None, None), (confidence, None), (None, confidence)] {
+            let inputs = PriceInputs {
+                confidence_x,
+                confidence_y,
+                ..PriceInputs::new(price_x, price_y).with_b_token_ratios(ratio_x, ratio_y)
+            };
+            let err = pool.fee_bps(&inputs).unwrap_err();
+            assert_eq!(err.to_string(), "missing price confidence for Ommv2");
+            assert!(
+                pool.quote_swap_decimal(1_000_000_000, true, &inputs)
+                    .is_err()
+            );
+        }
+        let legacy = SteammPool {
+            quoter_type: QuoterType::Ommv2Legacy,
+            ..pool
+        };
+        assert_eq!(legacy.fee_bps(&PriceInputs::new(price_x, price_y))?, 30);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tvl_usd() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+
+        // 1_000 SUI at $3 and 3_000 USDC at $1
+        let pool = SteammPool::new(
+            1_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            10,
+            30,
+            QuoterType::Ommv2,
+        );
+        assert_eq!(
+            pool.tvl_usd(price_x, price_y, ratio, ratio)?,
+            Decimal::from(6_000u64)
+        );
+
+        // The btoken ratio is applied before pricing: 1_500 SUI and 3_300 USDC
+        assert_eq!(
+            pool.tvl_usd(price_x, price_y, Decimal::from("1.5"), Decimal::from("1.1"))?,
+            Decimal::from(7_800u64)
+        );
+
+        // Fractional USD values are kept
+        let pool = SteammPool::new(1, 1, 9, 6, 10, 30, QuoterType::Ommv2);
+        assert_eq!(
+            pool.tvl_usd(price_x, price_y, ratio, ratio)?,
+            Decimal::from("0.000001003")
+        );
+
+        let empty = SteammPool::new(0, 0, 9, 6, 10, 30, QuoterType::Ommv2);
+        assert!(empty.tvl_usd(price_x, price_y, ratio, ratio)?.is_zero());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_usd_balance_ratio() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+
+        // 1_000 SUI and 3_000 USDC, balanced
+        let pool = SteammPool::new(
+            1_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            10,
+            30,
+            QuoterType::Ommv2,
+        );
+        assert_eq!(
+            pool.usd_balance_ratio(price_x, price_y, ratio, ratio)?,
+            Decimal::from(1u64)
+        );
+
+        // $9_000 of SUI against $3_000 of USDC
+        let pool = SteammPool::new(
+            3_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            10,
+            30,
+            QuoterType::Ommv2,
+        );
+        assert_eq!(
+            pool.usd_balance_ratio(price_x, price_y, ratio, ratio)?,
+            Decimal::from(3u64)
+        );
+
+        // The btoken ratio is applied before pricing
+        let pool = SteammPool::new(
+            1_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            10,
+            30,
+            QuoterType::Ommv2,
+        );
+        assert_eq!(
+            pool.usd_balance_ratio(price_x, price_y, Decimal::from("3.0"), ratio)?,
+            Decimal::from(3u64)
+        );
+
+        let pool = SteammPool::new(1_000_000_000_000, 0, 9, 6, 10, 30, QuoterType::Ommv2);
+        assert_eq!(
+            pool.usd_balance_ratio(price_x, price_y, ratio, ratio)
+                .unwrap_err()
+                .to_string(),
+            "Reserve y has no USD value"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_usd() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (ratio_x, ratio_y) = (Decimal::from("1.25"), Decimal::from("1.0"));
+        let confidence = Some(Decimal::from("0.0"));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool =
+                SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 100, 30, quoter_type);
+            let quote = |b_token_amount_in, x2y| {
+                pool.quote_swap(
+                    b_token_amount_in,
+                    price_x,
+                    price_y,
+                    x2y,
+                    ratio_x,
+                    ratio_y,
+                    confidence,
+                    confidence,
+                )
+            };
+            let inputs = PriceInputs {
+                price_x,
+                price_y,
+                b_token_ratio_x: ratio_x,
+                b_token_ratio_y: ratio_y,
+                confidence_x: confidence,
+                confidence_y: confidence,
+            };
+            let quote_usd = |usd_notional: &str, x2y| {
+                pool.quote_swap_usd(Decimal::from(usd_notional), x2y, &inputs)
+            };
+
+            // $30 is 10 SUI, or 8 bSUI at a ratio of 1.25
+            assert_eq!(quote_usd("30", true)?, quote(8_000_000_000, true)?);
+            // $30 is 30 USDC
+            assert_eq!(quote_usd("30", false)?, quote(30_000_000, false)?);
+
+            // A notional worth a fraction of a unit rounds down
+            assert_eq!(
+                quote_usd("0.0000025", false)?,
+                quote_usd("0.000002", false)?
+            );
+            assert_eq!(quote_usd("0", true)?.amount_in, 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip_loses_only_fees() -> Result<()> {
+        let price = Decimal::from("1");
+        let ratio = Decimal::from("1.0");
+        let confidence = Some(Decimal::from("0.0"));
+        let swap_fee_bps = 30;
+
+        // Only the StableSwap curve is symmetric in its reserves. The legacy
+        // curve prices off the output reserve alone, so swapping back after a
+        // trade does not retrace it.
+        for amplifier in [1, 10, 100, 1000] {
+            for amount_in in [1, 10, 1_000, 1_000_000, 1_000_000_000, 100_000_000_000] {
+                let mut pool = SteammPool::new(
+                    1_000_000_000_000,
+                    1_000_000_000_000,
+                    9,
+                    9,
+                    amplifier,
+                    swap_fee_bps,
+                    QuoterType::Ommv2,
+                );
+                let quote = |pool: &SteammPool, amount_in, x2y| {
+                    pool.quote_swap(
+                        amount_in, price, price, x2y, ratio, ratio, confidence, confidence,
+                    )
+                };
+
+                let inputs = PriceInputs::new(price, price)
+                    .with_b_token_ratios(ratio, ratio)
+                    .with_confidences(confidence.unwrap(), confidence.unwrap());
+                let (there, (reserve_x, reserve_y)) =
+                    pool.quote_swap_with_reserves(amount_in, true, &inputs)?;
+                pool.b_token_reserve_x = reserve_x;
+                pool.b_token_reserve_y = reserve_y;
+                let back = quote(&pool, there.amount_out, false)?;
+
+                // Each leg rounds its output down and its fees up, in favor of
+                // the pool, so the loss covers both fees plus a few units
+                let loss = amount_in - back.amount_out;
+                assert!(loss as u128 >= there.total_fees() + back.total_fees());
+                assert!(
+                    loss <= 2 * amount_in * swap_fee_bps / BPS_SCALE + 4,
+                    "A = {}, amount in = {}, loss = {}",
+                    amplifier,
+                    amount_in,
+                    loss
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_with_reserves() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (ratio_x, ratio_y) = (Decimal::from("1.25"), Decimal::from("1.0"));
+        let confidence = Some(Decimal::from("0.001"));
+        let (reserve_x, reserve_y) = (1_000_000_000_000, 3_000_000_000);
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool = SteammPool::new(reserve_x, reserve_y, 9, 6, 100, 30, quoter_type);
+
+            for (x2y, amount_in) in [(true, 10_000_000_000), (false, 30_000_000), (true, 0)] {
+                let inputs = PriceInputs::new(price_x, price_y)
+                    .with_b_token_ratios(ratio_x, ratio_y)
+                    .with_confidences(confidence.unwrap(), confidence.unwrap());
+                let (quote, (new_reserve_x, new_reserve_y)) =
+                    pool.quote_swap_with_reserves(amount_in, x2y, &inputs)?;
+                assert_eq!(
+                    quote,
+                    pool.quote_swap(
+                        amount_in, price_x, price_y, x2y, ratio_x, ratio_y, confidence, confidence,
+                    )?
+                );
+
+                // The pool fees stay in the pool
+                let (delta_in, delta_out) = if x2y {
+                    (new_reserve_x - reserve_x, reserve_y - new_reserve_y)
+                } else {
+                    (new_reserve_y - reserve_y, reserve_x - new_reserve_x)
+                };
+                assert_eq!(delta_in, amount_in);
+                assert_eq!(delta_out, quote.amount_out + quote.protocol_fees);
+                assert_eq!(amount_in == 0, quote.amount_out == 0);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_with_reserves_overflow() -> Result<()> {
+        let price = Decimal::from("1");
+        let ratio = Decimal::from("1.0");
+        let confidence = Some(Decimal::from("0.0"));
+        let reserve = u64::MAX - 1_000;
+        let inputs = PriceInputs::new(price, price)
+            .with_b_token_ratios(ratio, ratio)
+            .with_confidences(confidence.unwrap(), confidence.unwrap());
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            // The input reserve is near u64::MAX, so any sizable input
+            // overflows it rather than wrapping around
+            let pool = SteammPool::new(reserve, reserve, 9, 9, 100, 30, quoter_type);
+            let quote = |amount_in| pool.quote_swap_with_reserves(amount_in, true, &inputs);
+
+            let (_, (new_reserve_x, _)) = quote(1_000)?;
+            assert_eq!(new_reserve_x, u64::MAX);
+
+            let err = quote(1_001).unwrap_err();
+            assert!(err.to_string().contains("Reserve overflow"), "{err}");
+        }
+
+        // On an imbalanced pool this large, StableSwap's invariant does not
+        // fit in U256, which is an error rather than a panic
+        let pool = SteammPool::new(reserve, 1_000_000_000, 9, 9, 100, 30, QuoterType::Ommv2);
+        for x2y in [true, false] {
+            assert!(pool.quote_swap_with_reserves(1_000, x2y, &inputs).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_identical_sides_quote_like_a_balanced_pool() -> Result<()> {
+        let price = Decimal::from("1.5");
+        let ratio = Decimal::from("1.1");
+        let confidence = Some(Decimal::from("0.0"));
+        let reserve = 1_000_000_000_000;
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            for amplifier in [1, 100, 10_000] {
+                let pool = SteammPool::new(reserve, reserve, 9, 9, amplifier, 30, quoter_type);
+
+                for amount_in in [1_000, 1_000_000_000, 100_000_000_000] {
+                    let quote = |x2y| {
+                        pool.quote_swap(
+                            amount_in, price, price, x2y, ratio, ratio, confidence, confidence,
+                        )
+                    };
+                    let x2y = quote(true)?;
+
+                    // Both directions agree, and never pay out more than the
+                    // input minus fees
+                    assert_eq!(x2y.amount_out, quote(false)?.amount_out);
+                    assert!(x2y.amount_out as u128 + x2y.total_fees() <= amount_in as u128);
+
+                    // Small trades on a flat curve are the input minus fees,
+                    // within a basis point of slippage and rounding
+                    if amplifier == 10_000 && amount_in <= 1_000_000_000 {
+                        assert!(
+                            (x2y.amount_out + amount_in / BPS_SCALE + 2) as u128 + x2y.total_fees()
+                                >= amount_in as u128
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_with_inputs() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (ratio_x, ratio_y) = (Decimal::from("1.25"), Decimal::from("1.1"));
+        let (confidence_x, confidence_y) = (Decimal::from("0.01"), Decimal::from("0.001"));
+
+        let inputs = PriceInputs::new(price_x, price_y);
+        assert_eq!(inputs.b_token_ratio_x, Decimal::ONE);
+        assert_eq!(inputs.b_token_ratio_y, Decimal::ONE);
+        assert_eq!((inputs.confidence_x, inputs.confidence_y), (None, None));
+
+        let inputs = inputs
+            .with_b_token_ratios(ratio_x, ratio_y)
+            .with_confidences(confidence_x, confidence_y);
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool =
+                SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 100, 30, quoter_type);
+
+            for (x2y, amount_in) in [(true, 10_000_000_000), (false, 30_000_000)] {
+                assert_eq!(
+                    pool.quote_swap_with_inputs(amount_in, x2y, &inputs)?,
+                    pool.quote_swap(
+                        amount_in,
+                        price_x,
+                        price_y,
+                        x2y,
+                        ratio_x,
+                        ratio_y,
+                        Some(confidence_x),
+                        Some(confidence_y),
+                    )?
+                );
+                assert_eq!(
+                    pool.quote_swap_no_fees_with_inputs(amount_in, x2y, &inputs)?,
+                    pool.quote_swap_no_fees(amount_in, price_x, price_y, x2y, ratio_x, ratio_y)?
+                );
+            }
+        }
+
+        // Without confidences only the legacy quoter can quote
+        let inputs = PriceInputs::new(price_x, price_y);
+        let legacy = SteammPool::new(
+            1_000_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            100,
+            30,
+            QuoterType::Ommv2Legacy,
+        );
+        assert!(
+            legacy
+                .quote_swap_with_inputs(1_000_000_000, true, &inputs)
+                .is_ok()
+        );
+
+        let stable = SteammPool {
+            quoter_type: QuoterType::Ommv2,
+            ..legacy
+        };
+        let err = stable
+            .quote_swap_with_inputs(1_000_000_000, true, &inputs)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "missing price confidence for Ommv2");
+        let inputs = PriceInputs {
+            confidence_x: Some(confidence_x),
+            ..inputs
+        };
+        assert!(
+            stable
+                .quote_swap_with_inputs(1_000_000_000, true, &inputs)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_exact_out() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (ratio_x, ratio_y) = (Decimal::from("1.25"), Decimal::from("1.1"));
+        let confidence = Some(Decimal::from("0.001"));
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool =
+                SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 100, 30, quoter_type);
+            let quote = |amount_in, x2y| {
+                pool.quote_swap(
+                    amount_in, price_x, price_y, x2y, ratio_x, ratio_y, confidence, confidence,
+                )
+            };
+            let inputs = PriceInputs::new(price_x, price_y)
+                .with_b_token_ratios(ratio_x, ratio_y)
+                .with_confidences(confidence.unwrap(), confidence.unwrap());
+            let exact_out = |amount_out, x2y| pool.quote_swap_exact_out(amount_out, x2y, &inputs);
+            let consistent =
+                |amount_in, x2y| pool.quote_roundtrip_consistency(amount_in, x2y, &inputs);
+
+            for x2y in [true, false] {
+                for amount_out in [0, 1, 1_000, 1_000_000, 100_000_000] {
+                    // The smallest amount in that reaches the amount out
+                    let quote_out = exact_out(amount_out, x2y)?;
+                    assert!(quote_out.amount_out >= amount_out);
+                    assert_eq!(quote_out, quote(quote_out.amount_in, x2y)?);
+                    if quote_out.amount_in > 0 {
+                        assert!(quote(quote_out.amount_in - 1, x2y)?.amount_out < amount_out);
+                    }
+                }
+
+                let reserve_out = if x2y {
+                    pool.b_token_reserve_y
+                } else {
+                    pool.b_token_reserve_x
+                };
+                assert!(exact_out(reserve_out, x2y).is_err());
+            }
+
+            // A unit of bUSDC out is worth hundreds of units of bSUI in, so
+            // exact out recovers the amount in only when swapping into bSUI
+            for amount_in in [1, 1_000, 1_000_000, 1_000_000_000] {
+                assert!(consistent(amount_in, false)?);
+            }
+            for amount_in in [1_000, 1_000_000, 1_000_000_000] {
+                assert!(!consistent(amount_in, true)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_with_price_sources() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let (ratio_x, ratio_y) = (Decimal::from("1.25"), Decimal::from("1.0"));
+        let confidence = Some(Decimal::from("0.0"));
+
+        // 800 bSUI is 1_000 SUI, balanced against 3_000 USDC at a price of 3
+        let pool = SteammPool::new(
+            800_000_000_000,
+            3_000_000_000,
+            9,
+            6,
+            100,
+            30,
+            QuoterType::Ommv2,
+        );
+        let resolve =
+            |source_x, source_y| pool.resolve_prices(source_x, source_y, ratio_x, ratio_y);
+
+        let oracle = (PriceSource::Oracle(price_x), PriceSource::Oracle(price_y));
+        assert_eq!(resolve(oracle.0, oracle.1)?, (price_x, price_y));
+        assert_eq!(
+            resolve(PriceSource::PoolImplied, oracle.1)?,
+            (price_x, price_y)
+        );
+        assert_eq!(
+            resolve(oracle.0, PriceSource::PoolImplied)?,
+            (price_x, price_y)
+        );
+
+        // Fresh oracle prices are used, stale or missing ones fall back
+        let fallback = |price, age| PriceSource::OracleOrPoolImplied {
+            price,
+            age,
+            max_staleness: 60,
+        };
+        let oracle_x = Decimal::from("3.3");
+        assert_eq!(resolve(fallback(Some(oracle_x), 60), oracle.1)?.0, oracle_x);
+        assert_eq!(resolve(fallback(Some(oracle_x), 61), oracle.1)?.0, price_x);
+        assert_eq!(resolve(fallback(None, 0), oracle.1)?.0, price_x);
+
+        // On an imbalanced pool the implied price is off the oracle price
+        let imbalanced = SteammPool::new(
+            800_000_000_000,
+            6_000_000_000,
+            9,
+            6,
+            100,
+            30,
+            QuoterType::Ommv2,
+        );
+        assert_eq!(
+            imbalanced.resolve_prices(PriceSource::PoolImplied, oracle.1, ratio_x, ratio_y)?,
+            (Decimal::from("6"), price_y)
+        );
+        assert_eq!(
+            imbalanced.resolve_prices(oracle.0, PriceSource::PoolImplied, ratio_x, ratio_y)?,
+            (price_x, Decimal::from("0.5"))
+        );
+
+        // The quote matches the one with the resolved prices
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            let pool = SteammPool::new(800_000_000_000, 6_000_000_000, 9, 6, 100, 30, quoter_type);
+            assert_eq!(
+                pool.quote_swap_with_price_sources(
+                    1_000_000_000,
+                    fallback(None, 0),
+                    oracle.1,
+                    true,
+                    ratio_x,
+                    ratio_y,
+                    confidence,
+                    confidence,
+                )?,
+                pool.quote_swap(
+                    1_000_000_000,
+                    Decimal::from("6"),
+                    price_y,
+                    true,
+                    ratio_x,
+                    ratio_y,
+                    confidence,
+                    confidence,
+                )?
+            );
+        }
+
+        assert_eq!(
+            resolve(PriceSource::PoolImplied, PriceSource::PoolImplied)
+                .unwrap_err()
+                .to_string(),
+            "At least one price must come from the oracle"
+        );
+        let empty = SteammPool::new(0, 3_000_000_000, 9, 6, 100, 30, QuoterType::Ommv2);
+        assert_eq!(
+            empty
+                .resolve_prices(PriceSource::PoolImplied, oracle.1, ratio_x, ratio_y)
+                .unwrap_err()
+                .to_string(),
+            "Pool implied price undefined for an empty reserve"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_to_reach_balance() -> Result<()> {
+        let (price_x, price_y) = (Decimal::from("3"), Decimal::from("1"));
+        let ratio = Decimal::from("1.0");
+        let confidence = Decimal::from("0.0");
+        let inputs = PriceInputs::new(price_x, price_y)
+            .with_b_token_ratios(ratio, ratio)
+            .with_confidences(confidence, confidence);
+
+        for quoter_type in [QuoterType::Ommv2Legacy, QuoterType::Ommv2] {
+            // (reserve x, reserve y, target ratio, expected direction)
+            for (reserve_x, reserve_y, target_ratio, expected_x2y) in [
+                // $6_000 of SUI against $3_000 of USDC
+                (2_000_000_000_000, 3_000_000_000, "1", false),
+                // $3_000 of SUI against $6_000 of USDC
+                (1_000_000_000_000, 6_000_000_000, "1", true),
+                // Balanced, targeting 60/40
+                (1_000_000_000_000, 3_000_000_000, "1.5", true),
+            ] {
+                let pool = SteammPool::new(reserve_x, reserve_y, 9, 6, 10, 30, quoter_type);
+                let target_ratio = Decimal::from(target_ratio);
+
+                let (x2y, amount_in) = pool.input_to_reach_balance(target_ratio, &inputs)?;
+                assert_eq!(x2y, expected_x2y);
+
+                let ratio_after = |amount_in| -> Result<Decimal> {
+                    let (_, (reserve_x, reserve_y)) =
+                        pool.quote_swap_with_reserves(amount_in, x2y, &inputs)?;
+                    SteammPool::new(reserve_x, reserve_y, 9, 6, 10, 30, quoter_type)
+                        .usd_balance_ratio(price_x, price_y, ratio, ratio)
+                };
+
+                // The swap lands on the target, and one unit less falls short
+                assert!(ratio_after(amount_in)?.within_bps(&target_ratio, 1));
+                if x2y {
+                    assert!(ratio_after(amount_in)? >= target_ratio);
+                    assert!(ratio_after(amount_in - 1)? < target_ratio);
+                } else {
+                    assert!(ratio_after(amount_in)? <= target_ratio);
+                    assert!(ratio_after(amount_in - 1)? > target_ratio);
+                }
+            }
+
+            let balanced =
+                SteammPool::new(1_000_000_000_000, 3_000_000_000, 9, 6, 10, 30, quoter_type);
+            assert_eq!(balanced.input_to_reach_balance(Decimal::ONE, &inputs)?.1, 0);
+        }
+
+        Ok(())
+    }
 }
@@ -1,6 +1,12 @@
 use crate::{
     SwapQuote, get_quote,
-    math::{decimal::Decimal, decimal_to_fixedpoint64, fixed_point::FixedPoint64},
+    math::{
+        decimal::Decimal,
+        decimal_to_fixedpoint64,
+        fixed_point::FixedPoint64,
+        scaling::{SCALE_10, scale10_to_fixedpoint64},
+    },
+    omm::{QuoteDiagnostics, validate_prices},
     to_b_token, to_underlying,
 };
 use anyhow::Result;
@@ -86,12 +92,51 @@ pub fn quote_swap(
     b_token_ratio_y: Decimal,
     swap_fee_bps: u64,
 ) -> Result<SwapQuote> {
-    let amount_out_btoken = quote_swap_no_fees(
+    let (quote, _) = quote_swap_instrumented(
         b_token_amount_in,
         b_token_reserve_x,
         b_token_reserve_y,
-        price_x.clone(),
-        price_y.clone(),
+        price_x,
+        price_y,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        x2y,
+        b_token_ratio_x,
+        b_token_ratio_y,
+        swap_fee_bps,
+    )?;
+
+    Ok(quote)
+}
+
+/// Same as `quote_swap`, also returning the iteration count of the solver.
+#[allow(clippy::too_many_arguments)]
+pub fn quote_swap_instrumented(
+    // Amount in (btoken token - e.g. bSUI or bUSDC)
+    b_token_amount_in: u64,
+    // Reserve X (btoken token - e.g. bSUI)
+    b_token_reserve_x: u64,
+    // Reserve Y (btoken token - e.g. bUSDC)
+    b_token_reserve_y: u64,
+    // Price X (underlying price - e.g. 3 SUI)
+    price_x: Decimal,
+    // Price Y (underlying price - e.g. 1 USDC)
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    x2y: bool,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+    swap_fee_bps: u64,
+) -> Result<(SwapQuote, QuoteDiagnostics)> {
+    let (amount_out_btoken, diagnostics) = quote_swap_no_fees_instrumented(
+        b_token_amount_in,
+        b_token_reserve_x,
+        b_token_reserve_y,
+        price_x,
+        price_y,
         decimals_x,
         decimals_y,
         amplifier,
@@ -100,13 +145,15 @@ pub fn quote_swap(
         b_token_ratio_y,
     )?;
 
-    Ok(get_quote(
+    let quote = get_quote(
         b_token_amount_in,
         amount_out_btoken,
         x2y,
         swap_fee_bps,
         None,
-    ))
+    )?;
+
+    Ok((quote, diagnostics))
 }
 
 pub fn quote_swap_no_fees(
@@ -127,12 +174,62 @@ pub fn quote_swap_no_fees(
     b_token_ratio_x: Decimal,
     b_token_ratio_y: Decimal,
 ) -> Result<u64> {
-    let reserve_x = to_underlying(b_token_reserve_x, &b_token_ratio_x);
-    let reserve_y = to_underlying(b_token_reserve_y, &b_token_ratio_y);
+    let (amount_out, _) = quote_swap_no_fees_instrumented(
+        b_token_amount_in,
+        b_token_reserve_x,
+        b_token_reserve_y,
+        price_x,
+        price_y,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        x2y,
+        b_token_ratio_x,
+        b_token_ratio_y,
+    )?;
+
+    Ok(amount_out)
+}
+
+/// Same as `quote_swap_no_fees`, also returning the iteration count of
+/// `newton_raphson`.
+#[allow(clippy::too_many_arguments)]
+pub fn quote_swap_no_fees_instrumented(
+    // Amount in (btoken token - e.g. bSUI or bUSDC)
+    b_token_amount_in: u64,
+    // Reserve X (btoken token - e.g. bSUI)
+    b_token_reserve_x: u64,
+    // Reserve Y (btoken token - e.g. bUSDC)
+    b_token_reserve_y: u64,
+    // Price X (underlying price - e.g. 3 SUI)
+    price_x: Decimal,
+    // Price Y (underlying price - e.g. 1 USDC)
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    x2y: bool,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+) -> Result<(u64, QuoteDiagnostics)> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!(
+        "omm_v2_legacy::quote",
+        b_token_amount_in,
+        b_token_reserve_x,
+        b_token_reserve_y,
+        x2y,
+    )
+    .entered();
+
+    validate_prices(price_x, price_y)?;
+
+    let reserve_x = to_underlying(b_token_reserve_x, &b_token_ratio_x)?;
+    let reserve_y = to_underlying(b_token_reserve_y, &b_token_ratio_y)?;
 
-    let (_, amount_out_b_token) = if x2y {
-        let amount_in = to_underlying(b_token_amount_in, &b_token_ratio_x);
-        let out = quote_swap_inner(
+    let (inner, amount_out_b_token) = if x2y {
+        let amount_in = to_underlying(b_token_amount_in, &b_token_ratio_x)?;
+        let inner = quote_swap_inner_instrumented(
             amount_in as u128,
             reserve_x as u128,
             reserve_y as u128,
@@ -143,11 +240,11 @@ pub fn quote_swap_no_fees(
             amplifier,
             x2y,
         )?;
-        let b_token = to_b_token(out as u64, &b_token_ratio_y);
-        (out, b_token)
+        let b_token = to_b_token(amount_out_to_u64(inner.delta_out)?, &b_token_ratio_y)?;
+        (inner, b_token)
     } else {
-        let amount_in = to_underlying(b_token_amount_in, &b_token_ratio_y);
-        let out = quote_swap_inner(
+        let amount_in = to_underlying(b_token_amount_in, &b_token_ratio_y)?;
+        let inner = quote_swap_inner_instrumented(
             amount_in as u128,
             reserve_x as u128,
             reserve_y as u128,
@@ -158,19 +255,35 @@ pub fn quote_swap_no_fees(
             amplifier,
             x2y,
         )?;
-        let b_token = to_b_token(out as u64, &b_token_ratio_x);
-        (out, b_token)
+        let b_token = to_b_token(amount_out_to_u64(inner.delta_out)?, &b_token_ratio_x)?;
+        (inner, b_token)
     };
 
-    if x2y && amount_out_b_token >= b_token_reserve_y {
-        Ok(0)
-    } else if !x2y && amount_out_b_token >= b_token_reserve_x {
-        Ok(0)
+    let capped_by_reserves = inner.capped_by_reserves
+        || if x2y {
+            amount_out_b_token >= b_token_reserve_y
+        } else {
+            amount_out_b_token >= b_token_reserve_x
+        };
+    let diagnostics = QuoteDiagnostics {
+        newton_raphson_iterations: inner.iterations,
+        capped_by_reserves,
+        ..Default::default()
+    };
+
+    if capped_by_reserves {
+        Ok((0, diagnostics))
     } else {
-        Ok(amount_out_b_token)
+        Ok((amount_out_b_token, diagnostics))
     }
 }
 
+/// The inner quote is capped below the output reserve, which is itself a u64,
+/// so this only fails if that invariant is broken.
+fn amount_out_to_u64(amount_out: u128) -> Result<u64> {
+    u64::try_from(amount_out).map_err(|_| anyhow::anyhow!("Amount out {} exceeds u64", amount_out))
+}
+
 pub fn quote_swap_inner(
     // Amount in (underlying token - e.g. SUI or USDC)
     amount_in: u128,
@@ -185,6 +298,88 @@ pub fn quote_swap_inner(
     amplifier: u32,
     x2y: bool,
 ) -> Result<u128> {
+    let inner = quote_swap_inner_instrumented(
+        amount_in, reserve_x, reserve_y, price_x, price_y, decimals_x, decimals_y, amplifier, x2y,
+    )?;
+
+    Ok(inner.delta_out)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn quote_swap_inner_instrumented(
+    // Amount in (underlying token - e.g. SUI or USDC)
+    amount_in: u128,
+    // Reserve X (underlying token - e.g. SUI)
+    reserve_x: u128,
+    // Reserve Y (underlying token - e.g. USDC)
+    reserve_y: u128,
+    price_x: Decimal,
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    x2y: bool,
+) -> Result<InnerQuote> {
+    let inner = quote_swap_inner_detailed(
+        amount_in, reserve_x, reserve_y, price_x, price_y, decimals_x, decimals_y, amplifier, x2y,
+    )?;
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        k = %inner.k,
+        z = %inner.z,
+        newton_raphson_iterations = inner.iterations,
+        converged = inner.converged,
+        amount_out = inner.delta_out,
+        "quoted"
+    );
+
+    if !inner.converged {
+        return Err(NewtonRaphsonError {
+            last: inner.z,
+            iterations: inner.iterations,
+        }
+        .into());
+    }
+
+    Ok(inner)
+}
+
+/// Intermediate values of the legacy curve for a single quote, useful to
+/// diagnose a mispriced quote.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InnerQuote {
+    /// Amount out in the underlying token, zero if it would drain the reserve
+    pub delta_out: u128,
+    /// Solved fraction of the output reserve paid out
+    pub z: FixedPoint64,
+    /// Trade size in output terms, relative to the output reserve
+    pub k: FixedPoint64,
+    pub iterations: usize,
+    /// Whether Newton-Raphson reached its tolerance before the iteration cap
+    pub converged: bool,
+    /// Whether `delta_out` was zeroed because it would drain the reserve
+    pub capped_by_reserves: bool,
+}
+
+/// Same as `quote_swap_inner`, returning the intermediate values of the curve.
+/// Unlike `quote_swap_inner`, a solve that did not converge is reported through
+/// `converged` rather than as an error.
+#[allow(clippy::too_many_arguments)]
+pub fn quote_swap_inner_detailed(
+    // Amount in (underlying token - e.g. SUI or USDC)
+    amount_in: u128,
+    // Reserve X (underlying token - e.g. SUI)
+    reserve_x: u128,
+    // Reserve Y (underlying token - e.g. USDC)
+    reserve_y: u128,
+    price_x: Decimal,
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    x2y: bool,
+) -> Result<InnerQuote> {
     let r_x = FixedPoint64::from(reserve_x)?;
     let r_y = FixedPoint64::from(reserve_y)?;
     let p_x = decimal_to_fixedpoint64(price_x)?;
@@ -192,7 +387,9 @@ pub fn quote_swap_inner(
     let amp = FixedPoint64::from(amplifier as u128)?;
     let delta_in = FixedPoint64::from(amount_in)?;
 
-    let dec_pow = if decimals_x >= decimals_y {
+    let dec_pow = if decimals_x == decimals_y {
+        FixedPoint64::ONE
+    } else if decimals_x > decimals_y {
         FixedPoint64::from(10)?.pow(decimals_x - decimals_y)?
     } else {
         FixedPoint64::one()?.div(&FixedPoint64::from(10)?.pow(decimals_y - decimals_x)?)?
@@ -204,10 +401,11 @@ pub fn quote_swap_inner(
         FixedPoint64::multiply_divide(&mut vec![delta_in, dec_pow, p_y], &mut vec![r_x, p_x])?
     };
 
-    let max_bound = FixedPoint64::from_rational(9_999_999_999, 10_000_000_000)?;
+    let max_bound = scale10_to_fixedpoint64(SCALE_10 - 1)?;
     let initial_z = if max_bound.lt(&k) { max_bound } else { k };
 
-    let z = newton_raphson(&k, &amp, &initial_z)?;
+    let (z, iterations, converged) =
+        newton_raphson_inner(&k, &amp, &initial_z, NewtonRaphsonMode::EarlyExit)?;
 
     let delta_out = if x2y {
         z.mul(&r_y)?.to_u128_down()
@@ -215,25 +413,114 @@ pub fn quote_swap_inner(
         z.mul(&r_x)?.to_u128_down()
     };
 
-    if x2y && delta_out >= reserve_y {
-        Ok(0)
-    } else if !x2y && delta_out >= reserve_x {
-        Ok(0)
+    let capped_by_reserves = if x2y {
+        delta_out >= reserve_y
     } else {
-        Ok(delta_out)
+        delta_out >= reserve_x
+    };
+    let delta_out = if capped_by_reserves { 0 } else { delta_out };
+
+    Ok(InnerQuote {
+        delta_out,
+        z,
+        k,
+        iterations,
+        converged,
+        capped_by_reserves,
+    })
+}
+
+/// Controls when `newton_raphson` stops iterating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NewtonRaphsonMode {
+    /// Stops as soon as the residual or the step size falls below the
+    /// tolerance, running at most 20 iterations.
+    EarlyExit,
+    /// Runs exactly the given number of iterations regardless of convergence,
+    /// so the result does not depend on the path taken to the tolerance.
+    FixedIterations(u32),
+}
+
+/// Returned, wrapped in `anyhow::Error`, when `newton_raphson` in
+/// `NewtonRaphsonMode::EarlyExit` reaches its iteration cap without meeting
+/// the tolerance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NewtonRaphsonError {
+    /// The last iterate computed
+    pub last: FixedPoint64,
+    pub iterations: usize,
+}
+
+impl std::fmt::Display for NewtonRaphsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "newton_raphson did not converge after {} iterations: last = {}",
+            self.iterations, self.last
+        )
     }
 }
 
-fn newton_raphson(
+impl std::error::Error for NewtonRaphsonError {}
+
+/// Solves for `z` using the default `NewtonRaphsonMode::EarlyExit`.
+pub fn newton_raphson(
+    k: &FixedPoint64,
+    a: &FixedPoint64,
+    initial_z: &FixedPoint64,
+) -> Result<FixedPoint64> {
+    newton_raphson_with_mode(k, a, initial_z, NewtonRaphsonMode::EarlyExit)
+}
+
+pub fn newton_raphson_with_mode(
     k: &FixedPoint64,
     a: &FixedPoint64,
     initial_z: &FixedPoint64,
+    mode: NewtonRaphsonMode,
 ) -> Result<FixedPoint64> {
-    let one = FixedPoint64::one()?;
+    let (z, _) = newton_raphson_with_iterations(k, a, initial_z, mode)?;
+    Ok(z)
+}
+
+/// Same as `newton_raphson_with_mode`, also returning the number of
+/// iterations run.
+///
+/// In `NewtonRaphsonMode::EarlyExit`, errors with a `NewtonRaphsonError` if the
+/// tolerance is not met within the iteration cap. `FixedIterations` always
+/// returns the last iterate.
+pub fn newton_raphson_with_iterations(
+    k: &FixedPoint64,
+    a: &FixedPoint64,
+    initial_z: &FixedPoint64,
+    mode: NewtonRaphsonMode,
+) -> Result<(FixedPoint64, usize)> {
+    let (z, iterations, converged) = newton_raphson_inner(k, a, initial_z, mode)?;
+    if mode == NewtonRaphsonMode::EarlyExit && !converged {
+        return Err(NewtonRaphsonError {
+            last: z,
+            iterations,
+        }
+        .into());
+    }
+    Ok((z, iterations))
+}
+
+/// Returns `(z, iterations, converged)`, where `converged` is true if the
+/// residual or the step size fell below the tolerance on the last iteration.
+fn newton_raphson_inner(
+    k: &FixedPoint64,
+    a: &FixedPoint64,
+    initial_z: &FixedPoint64,
+    mode: NewtonRaphsonMode,
+) -> Result<(FixedPoint64, usize, bool)> {
+    let one = FixedPoint64::ONE;
     let min_z = FixedPoint64::from_rational(1, 100_000)?; // 1e-5
     let max_z = FixedPoint64::from_rational(999_999_999_999_999_999, 1_000_000_000_000_000_000)?; // 0.999999999999999999
     let tol = FixedPoint64::from_rational(1, 100_000_000_000_000)?; // 1e-14
-    let max_iter = 20;
+    let (max_iter, early_exit) = match mode {
+        NewtonRaphsonMode::EarlyExit => (20, true),
+        NewtonRaphsonMode::FixedIterations(n) => (n, false),
+    };
 
     let mut z = if initial_z.gte(&one) {
         max_z
@@ -241,17 +528,21 @@ fn newton_raphson(
         *initial_z
     };
     let mut i = 0;
+    let mut iterations = 0;
+    let mut converged = false;
 
     while i < max_iter {
+        iterations += 1;
         let (fx_val, fx_positive) = compute_f(&z, a, k)?;
 
-        if fx_val.lt(&tol) {
+        converged = fx_val.lt(&tol);
+        if early_exit && converged {
             break;
         }
 
         let fp = compute_f_prime(&z, a)?;
 
-        if fp.lt(&FixedPoint64::from_rational(1, 10_000_000_000)?) {
+        if fp.lt(&scale10_to_fixedpoint64(1)?) {
             return Err(anyhow::anyhow!("Derivative near zero (error code 1001)"));
         }
 
@@ -268,7 +559,7 @@ fn newton_raphson(
             z.add(&step)?
         };
 
-        let new_z = if new_z.lte(&FixedPoint64::zero()?) || new_z.gte(&one) {
+        let new_z = if new_z.lte(&FixedPoint64::ZERO) || new_z.gte(&one) {
             let damped_step = fx_div_fp.mul(&FixedPoint64::from_rational(1, 2)?)?;
             let temp_z = if fx_positive {
                 z.sub(&damped_step)?
@@ -291,7 +582,8 @@ fn newton_raphson(
         } else {
             z.sub(&new_z)?
         };
-        if step_size.lt(&tol) {
+        converged = step_size.lt(&tol);
+        if early_exit && converged {
             break;
         }
 
@@ -299,11 +591,11 @@ fn newton_raphson(
         i += 1;
     }
 
-    Ok(z)
+    Ok((z, iterations, converged))
 }
 
 fn compute_f(z: &FixedPoint64, a: &FixedPoint64, k: &FixedPoint64) -> Result<(FixedPoint64, bool)> {
-    let one = FixedPoint64::one()?;
+    let one = FixedPoint64::ONE;
     let ln2_64 =
         FixedPoint64::from_raw_value(12_786_308_645_202_655_660)?.mul(&FixedPoint64::from(64)?)?;
 
@@ -330,7 +622,7 @@ fn compute_f(z: &FixedPoint64, a: &FixedPoint64, k: &FixedPoint64) -> Result<(Fi
 }
 
 fn compute_f_prime(z: &FixedPoint64, a: &FixedPoint64) -> Result<FixedPoint64> {
-    let one = FixedPoint64::one()?;
+    let one = FixedPoint64::ONE;
     let one_div_a = one.div(a)?;
     let term3 = one.div(&a.mul(&one.sub(z)?)?)?;
     one.sub(&one_div_a)?.add(&term3)
@@ -339,6 +631,7 @@ fn compute_f_prime(z: &FixedPoint64, a: &FixedPoint64) -> Result<FixedPoint64> {
 #[cfg(test)]
 mod tests {
     use crate::math::u256::U256;
+    use crate::omm::test_utils::Rng;
 
     use super::*;
 
@@ -838,4 +1131,321 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_quote_swap_iteration_counts() -> Result<()> {
+        for amplifier in [1, 10, 100, 1000, 8000] {
+            for x2y in [true, false] {
+                let (_, diagnostics) = quote_swap_no_fees_instrumented(
+                    if x2y { 100_000_000_000 } else { 300_000_000 },
+                    1_000_000_000_000,
+                    3_000_000_000,
+                    Decimal::from("3"),
+                    Decimal::from("1"),
+                    9,
+                    6,
+                    amplifier,
+                    x2y,
+                    Decimal::from("1.0"),
+                    Decimal::from("1.0"),
+                )?;
+
+                assert!(diagnostics.newton_raphson_iterations > 0);
+                assert!(diagnostics.newton_raphson_iterations <= 20);
+                assert_eq!(diagnostics.get_d_iterations, 0);
+                assert_eq!(diagnostics.get_y_iterations, 0);
+            }
+        }
+
+        let k = FixedPoint64::from_rational(1, 2)?;
+        let a = FixedPoint64::from(100)?;
+        let (_, iterations) =
+            newton_raphson_with_iterations(&k, &a, &k, NewtonRaphsonMode::FixedIterations(7))?;
+        assert_eq!(iterations, 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_newton_raphson_fixed_iterations() -> Result<()> {
+        // Reference vectors from the steamm Move contracts: test_quote_swap of
+        // the legacy omm_v2 quoter, on a 1_000 SUI / 1_000 USDC pool at $3
+        // and $1 with an amplifier of 1. Solving for z with exactly 20
+        // iterations reproduces the Move amount out:
+        // (amount in, x2y, Move amount out)
+        let (reserve_x, reserve_y) = (1_000_000_000_000, 1_000_000_000);
+        let (p_x, p_y) = (FixedPoint64::from(3)?, FixedPoint64::one()?);
+        let dec_pow = FixedPoint64::from(10)?.pow(3)?;
+        let move_cases = [
+            (10_000_000, false, 3_327_783_945),
+            (100_000_000, false, 32_783_899_517),
+            (10_000_000_000, true, 29_554_466),
+            (100_000_000_000, true, 259_181_779),
+        ];
+        for (amount_in, x2y, expected) in move_cases {
+            let (r_x, r_y) = (
+                FixedPoint64::from(reserve_x)?,
+                FixedPoint64::from(reserve_y)?,
+            );
+            let delta_in = FixedPoint64::from(amount_in)?;
+            let k = if x2y {
+                FixedPoint64::multiply_divide(
+                    &mut vec![delta_in, p_x],
+                    &mut vec![r_y, p_y, dec_pow],
+                )?
+            } else {
+                FixedPoint64::multiply_divide(
+                    &mut vec![delta_in, dec_pow, p_y],
+                    &mut vec![r_x, p_x],
+                )?
+            };
+            let z = newton_raphson_with_mode(
+                &k,
+                &FixedPoint64::one()?,
+                &k,
+                NewtonRaphsonMode::FixedIterations(20),
+            )?;
+            let amount_out = z.mul(if x2y { &r_y } else { &r_x })?.to_u128_down();
+            assert_eq!(amount_out, expected);
+        }
+
+        // Regression snapshot of this crate's own fixed-iteration output, not
+        // Move reference values:
+        // (k, amplifier, z after exactly 20 iterations as a raw 64.64 value)
+        let cases = [
+            (
+                FixedPoint64::from_rational(1, 100)?,
+                1,
+                183_548_170_319_958_995,
+            ),
+            (
+                FixedPoint64::from_rational(1, 10)?,
+                10,
+                1_834_894_348_698_510_814,
+            ),
+            (
+                FixedPoint64::from_rational(1, 2)?,
+                100,
+                9_188_094_104_203_728_126,
+            ),
+            (
+                FixedPoint64::from_rational(9, 10)?,
+                1000,
+                16_576_425_568_831_562_080,
+            ),
+            (
+                FixedPoint64::from_rational(3, 2)?,
+                2,
+                16_232_613_867_486_487_015,
+            ),
+        ];
+        let tol = FixedPoint64::from_rational(1, 100_000_000_000_000)?;
+        let max_bound = FixedPoint64::from_rational(9_999_999_999, 10_000_000_000)?;
+
+        for (k, amp, expected) in cases {
+            let a = FixedPoint64::from(amp)?;
+            let initial_z = FixedPoint64::min(k, max_bound);
+
+            let fixed = newton_raphson_with_mode(
+                &k,
+                &a,
+                &initial_z,
+                NewtonRaphsonMode::FixedIterations(20),
+            )?;
+            assert_eq!(fixed.get_value(), expected);
+
+            // The early exit result stops on a different iterate but stays
+            // within the solver tolerance
+            let early = newton_raphson(&k, &a, &initial_z)?;
+            assert_ne!(early, fixed);
+            let diff = if early.gt(&fixed) {
+                early.sub(&fixed)?
+            } else {
+                fixed.sub(&early)?
+            };
+            assert!(diff.lt(&tol));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote_swap_inner_detailed() -> Result<()> {
+        // Sell 10 SUI into a 1_000 SUI / 3_000 USDC pool
+        let (amount_in, reserve_x, reserve_y) = (10_000_000_000, 1_000_000_000_000, 3_000_000_000);
+        let args = (Decimal::from("3"), Decimal::from("1"), 9, 6, 10, true);
+
+        let inner = quote_swap_inner_detailed(
+            amount_in, reserve_x, reserve_y, args.0, args.1, args.2, args.3, args.4, args.5,
+        )?;
+        let delta_out = quote_swap_inner(
+            amount_in, reserve_x, reserve_y, args.0, args.1, args.2, args.3, args.4, args.5,
+        )?;
+
+        assert_eq!(inner.delta_out, delta_out);
+        assert!(inner.converged);
+        assert!(inner.iterations > 0);
+
+        // k is the oracle value of the input relative to the output reserve:
+        // 10 SUI * $3 / 3_000 USDC = 1%
+        assert_eq!(inner.k, FixedPoint64::from_rational(1, 100)?);
+
+        // The curve pays out slightly less than k
+        assert!(!inner.z.is_zero());
+        assert!(inner.z.lt(&inner.k));
+        assert!(inner.z.lt(&FixedPoint64::one()?));
+        assert_eq!(
+            inner.delta_out,
+            inner.z.mul(&FixedPoint64::from(reserve_y)?)?.to_u128_down()
+        );
+
+        Ok(())
+    }
+
+    /// Randomized check that `newton_raphson` stays inside `(0, 1)` and solves
+    /// the curve to within its tolerance for any `k` in `(0, 1)` and realistic
+    /// amplifiers, starting from the initial guess used by the quoter.
+    #[test]
+    fn test_newton_raphson_stability() -> Result<()> {
+        let max_bound = FixedPoint64::from_rational(9_999_999_999, 10_000_000_000)?;
+        let tol = FixedPoint64::from_rational(1, 100_000_000_000_000)?;
+
+        let check = |k_raw: u128, a: u128| -> Result<()> {
+            let k = FixedPoint64::from_raw_value(k_raw)?;
+            let a = FixedPoint64::from(a)?;
+            let initial_z = if max_bound.lt(&k) { max_bound } else { k };
+
+            let (z, _, converged) =
+                newton_raphson_inner(&k, &a, &initial_z, NewtonRaphsonMode::EarlyExit)?;
+            assert!(converged, "k = {}, a = {}", k, a);
+            assert!(z.gt(&FixedPoint64::ZERO) && z.lt(&FixedPoint64::ONE));
+            let (residual, _) = compute_f(&z, &a, &k)?;
+            assert!(
+                residual.lt(&tol),
+                "k = {}, a = {}, residual = {}",
+                k,
+                a,
+                residual
+            );
+
+            Ok(())
+        };
+
+        // Boundary values: the smallest and largest k below one, the initial
+        // guess cap, and the extremes of the amplifier range
+        let max_bound_raw = max_bound.get_value();
+        for k_raw in [
+            1,
+            2,
+            1 << 32,
+            1 << 63,
+            max_bound_raw,
+            max_bound_raw + 1,
+            u64::MAX as u128,
+        ] {
+            for a in [1, 2, 10, 100, 1_000, 8_000, 10_000] {
+                check(k_raw, a)?;
+            }
+        }
+
+        // k is drawn log-uniformly so that tiny trades are covered
+        let mut rng = Rng(0x1234_5678_9abc_def0);
+        for _ in 0..5_000 {
+            let k_raw = (rng.next() >> (rng.next() % 64)).max(1) as u128;
+            let a = rng.range(1, 10_000) as u128;
+            check(k_raw, a)?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_equal_decimals_fast_path() -> Result<()> {
+        // The skipped `10^0` is exactly one, so the fast path cannot change
+        // any quote
+        assert_eq!(FixedPoint64::from(10)?.pow(0)?, FixedPoint64::ONE);
+
+        for decimals in [0, 6, 9, 18] {
+            let out = quote_swap_inner(
+                10_000_000,
+                1_000_000_000_000,
+                1_000_000_000_000,
+                Decimal::from("1"),
+                Decimal::from("1"),
+                decimals,
+                decimals,
+                100,
+                true,
+            )?;
+            // Equal decimals cancel out, so the quote is the same for all
+            assert_eq!(out, 9_999_999);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_reserves_do_not_truncate() -> Result<()> {
+        let price = Decimal::from("1");
+        let quote = |amount_in, reserve| {
+            quote_swap_inner(amount_in, reserve, reserve, price, price, 9, 9, 100, true)
+        };
+
+        // The largest reserves a pool can hold quote below the output reserve
+        let reserve = u64::MAX as u128;
+        for amount_in in [1_000_000_000, reserve / 2, reserve] {
+            let out = quote(amount_in, reserve)?;
+            assert!(out < reserve);
+            assert_eq!(amount_out_to_u64(out)? as u128, out);
+        }
+
+        // Beyond that the fixed point conversion rejects the reserve instead of
+        // truncating it
+        assert!(quote(1_000_000_000, reserve + 1).is_err());
+
+        assert_eq!(amount_out_to_u64(reserve)?, u64::MAX);
+        assert_eq!(
+            amount_out_to_u64(reserve + 1).unwrap_err().to_string(),
+            "Amount out 18446744073709551616 exceeds u64"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_newton_raphson_non_convergence() -> Result<()> {
+        // With k = 0 the root is z = 0, below the `min_z` clamp, so from most
+        // starting points the iterates bounce off the clamp until the cap
+        let k = FixedPoint64::ZERO;
+        let a = FixedPoint64::one()?;
+        let initial_z = FixedPoint64::from_rational(1, 4)?;
+
+        let (_, iterations, converged) =
+            newton_raphson_inner(&k, &a, &initial_z, NewtonRaphsonMode::EarlyExit)?;
+        assert!(!converged);
+        assert_eq!(iterations, 20);
+
+        let err = newton_raphson(&k, &a, &initial_z).unwrap_err();
+        let err = err.downcast_ref::<NewtonRaphsonError>().unwrap();
+        assert_eq!(err.iterations, 20);
+        assert!(err.last.lt(&initial_z));
+        assert!(
+            newton_raphson_with_iterations(&k, &a, &initial_z, NewtonRaphsonMode::EarlyExit)
+                .is_err()
+        );
+
+        // A fixed iteration count ignores the tolerance by design
+        let (_, iterations) = newton_raphson_with_iterations(
+            &k,
+            &a,
+            &initial_z,
+            NewtonRaphsonMode::FixedIterations(20),
+        )?;
+        assert_eq!(iterations, 20);
+
+        // The quoter starts from z = k, where k = 0 is an immediate root
+        assert_eq!(newton_raphson(&k, &a, &k)?, FixedPoint64::ZERO);
+
+        Ok(())
+    }
 }
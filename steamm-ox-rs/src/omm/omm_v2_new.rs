@@ -1,12 +1,59 @@
 use crate::{
     BPS_SCALE, SwapQuote, get_quote,
     math::{decimal::Decimal, u256::U256},
+    omm::{QuoteDiagnostics, validate_prices},
     to_b_token, to_underlying,
 };
 use anyhow::Result;
 
 const A_PRECISION: u128 = 100;
 const LIMIT: usize = 255;
+const COINS: CoinCount = CoinCount::TWO;
+
+/// Number of coins in a StableSwap pool, with the factors of the invariant
+/// that depend on it. The quoters only price two coin pools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoinCount(u32);
+
+impl CoinCount {
+    pub const TWO: CoinCount = CoinCount(2);
+    /// Largest coin count whose `n^n` fits in a u128.
+    pub const MAX: CoinCount = CoinCount(26);
+
+    /// Returns `None` for fewer than two coins, or more than `MAX`.
+    pub const fn new(n: u32) -> Option<Self> {
+        if n < 2 || n > Self::MAX.0 {
+            None
+        } else {
+            Some(Self(n))
+        }
+    }
+
+    pub const fn n(self) -> u128 {
+        self.0 as u128
+    }
+
+    pub const fn n_pow_n(self) -> u128 {
+        (self.0 as u128).pow(self.0)
+    }
+
+    /// Scales an amplifier following the Curve convention, where the stored
+    /// amplifier is `A * n^(n-1) * A_PRECISION`.
+    pub fn scaled_amp(self, amplifier: u32) -> U256 {
+        U256::from(amplifier) * U256::from(self.n_pow_n() / self.n()) * U256::from(A_PRECISION)
+    }
+
+    /// Returns `ann = amp * n`, i.e. `A * n^n * A_PRECISION`, for an amplifier
+    /// scaled by `scaled_amp`.
+    pub fn ann(self, amp: U256) -> U256 {
+        amp * U256::from(self.n())
+    }
+
+    /// Same as `ann`, returning `None` on overflow.
+    pub fn checked_ann(self, amp: u128) -> Option<u128> {
+        amp.checked_mul(self.n())
+    }
+}
 
 // === Swap Functions ===
 
@@ -31,30 +78,159 @@ pub fn quote_swap(
     price_confidence_a: Decimal,
     price_confidence_b: Decimal,
 ) -> Result<SwapQuote> {
-    let amount_out_btoken = quote_swap_no_fees(
+    let (quote, _) = quote_swap_instrumented(
         b_token_amount_in,
         b_token_reserve_x,
         b_token_reserve_y,
-        price_x.clone(),
-        price_y.clone(),
+        price_x,
+        price_y,
         decimals_x,
         decimals_y,
         amplifier,
         x2y,
         b_token_ratio_x,
         b_token_ratio_y,
+        swap_fee_bps,
+        price_confidence_a,
+        price_confidence_b,
     )?;
 
-    let price_uncertainty_ratio_a = price_uncertainty_ratio(price_x, price_confidence_a)?;
-    let price_uncertainty_ratio_b = price_uncertainty_ratio(price_y, price_confidence_b)?;
+    Ok(quote)
+}
+
+/// Same as `quote_swap`, also returning the iteration counts of the solvers.
+#[allow(clippy::too_many_arguments)]
+pub fn quote_swap_instrumented(
+    // Amount in (btoken token - e.g. bSUI or bUSDC)
+    b_token_amount_in: u64,
+    // Reserve X (btoken token - e.g. bSUI)
+    b_token_reserve_x: u64,
+    // Reserve Y (btoken token - e.g. bUSDC)
+    b_token_reserve_y: u64,
+    // Price X (underlying price - e.g. 3 SUI)
+    price_x: Decimal,
+    // Price Y (underlying price - e.g. 1 USDC)
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    x2y: bool,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+    swap_fee_bps: u64,
+    price_confidence_a: Decimal,
+    price_confidence_b: Decimal,
+) -> Result<(SwapQuote, QuoteDiagnostics)> {
+    quote_swap_with_fee_mode(
+        b_token_amount_in,
+        b_token_reserve_x,
+        b_token_reserve_y,
+        price_x,
+        price_y,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        x2y,
+        b_token_ratio_x,
+        b_token_ratio_y,
+        swap_fee_bps,
+        price_confidence_a,
+        price_confidence_b,
+        FeeMode::default(),
+    )
+}
+
+/// Controls which risks raise the swap fee above `swap_fee_bps`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FeeMode {
+    /// The fee is at least the relative price confidence of either oracle.
+    #[default]
+    PriceUncertainty,
+    /// The fee is also at least the drift of the pool's marginal price from
+    /// the oracle price, in basis points, capped at 100%. A skewed pool
+    /// quotes away from the oracle, which makes it riskier to trade against.
+    PriceUncertaintyAndImbalance,
+}
+
+/// Same as `quote_swap_instrumented`, raising the fee according to `fee_mode`.
+#[allow(clippy::too_many_arguments)]
+pub fn quote_swap_with_fee_mode(
+    // Amount in (btoken token - e.g. bSUI or bUSDC)
+    b_token_amount_in: u64,
+    // Reserve X (btoken token - e.g. bSUI)
+    b_token_reserve_x: u64,
+    // Reserve Y (btoken token - e.g. bUSDC)
+    b_token_reserve_y: u64,
+    // Price X (underlying price - e.g. 3 SUI)
+    price_x: Decimal,
+    // Price Y (underlying price - e.g. 1 USDC)
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    x2y: bool,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+    swap_fee_bps: u64,
+    price_confidence_a: Decimal,
+    price_confidence_b: Decimal,
+    fee_mode: FeeMode,
+) -> Result<(SwapQuote, QuoteDiagnostics)> {
+    let curve = CurveState::new(
+        b_token_reserve_x,
+        b_token_reserve_y,
+        price_x,
+        price_y,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        b_token_ratio_x,
+        b_token_ratio_y,
+    )?;
+    let (amount_out_btoken, diagnostics) = curve.quote(b_token_amount_in, x2y)?;
+
+    let mut fee_override =
+        price_uncertainty_fee_bps(price_x, price_y, price_confidence_a, price_confidence_b)?;
 
-    Ok(get_quote(
+    if fee_mode == FeeMode::PriceUncertaintyAndImbalance {
+        fee_override = fee_override.max(curve.imbalance_bps(x2y)?);
+    }
+
+    let quote = get_quote(
         b_token_amount_in,
         amount_out_btoken,
         x2y,
         swap_fee_bps,
-        Some(price_uncertainty_ratio_a.max(price_uncertainty_ratio_b)),
-    ))
+        Some(fee_override),
+    )?;
+
+    Ok((quote, diagnostics))
+}
+
+/// Returns the fee override, in basis points, for the larger relative price
+/// confidence of the two oracles. This is the fee override of `quote_swap` in
+/// the default `FeeMode`.
+pub fn price_uncertainty_fee_bps(
+    price_x: Decimal,
+    price_y: Decimal,
+    price_confidence_a: Decimal,
+    price_confidence_b: Decimal,
+) -> Result<u64> {
+    let price_uncertainty_ratio_a = price_uncertainty_ratio(price_x, price_confidence_a)?;
+    let price_uncertainty_ratio_b = price_uncertainty_ratio(price_y, price_confidence_b)?;
+    Ok(price_uncertainty_ratio_a.max(price_uncertainty_ratio_b))
+}
+
+/// Converts a confidence given as `pct_bps` basis points of `price` into the
+/// absolute price confidence the quoter expects, i.e. `price * pct_bps /
+/// 10000`. The result is rounded up so that its price uncertainty ratio, and
+/// hence the fee override, is exactly `pct_bps` rather than one basis point
+/// less.
+pub fn confidence_from_percent(price: &Decimal, pct_bps: u64) -> Result<Decimal> {
+    price
+        .checked_mul_int(pct_bps)
+        .and_then(|v| v.checked_div_ceil(&Decimal::from(BPS_SCALE)))
+        .ok_or_else(|| anyhow::anyhow!("Price confidence overflow"))
 }
 
 fn price_uncertainty_ratio(price: Decimal, price_confidence: Decimal) -> Result<u64> {
@@ -85,171 +261,837 @@ pub fn quote_swap_no_fees(
     b_token_ratio_x: Decimal,
     b_token_ratio_y: Decimal,
 ) -> Result<u64> {
-    let amount_in = to_underlying(
+    let (amount_out, _) = quote_swap_no_fees_instrumented(
         b_token_amount_in,
-        if x2y {
-            &b_token_ratio_x
-        } else {
-            &b_token_ratio_y
-        },
-    );
+        b_token_reserve_x,
+        b_token_reserve_y,
+        price_x,
+        price_y,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        x2y,
+        b_token_ratio_x,
+        b_token_ratio_y,
+    )?;
+
+    Ok(amount_out)
+}
+
+/// Same as `quote_swap_no_fees`, also returning the iteration counts of
+/// `get_d` and `get_y`.
+pub fn quote_swap_no_fees_instrumented(
+    // Amount in (btoken token - e.g. bSUI or bUSDC)
+    b_token_amount_in: u64,
+    // Reserve X (btoken token - e.g. bSUI)
+    b_token_reserve_x: u64,
+    // Reserve Y (btoken token - e.g. bUSDC)
+    b_token_reserve_y: u64,
+    // Price X (underlying price - e.g. 3 SUI)
+    price_x: Decimal,
+    // Price Y (underlying price - e.g. 1 USDC)
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    x2y: bool,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+) -> Result<(u64, QuoteDiagnostics)> {
+    CurveState::new(
+        b_token_reserve_x,
+        b_token_reserve_y,
+        price_x,
+        price_y,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        b_token_ratio_x,
+        b_token_ratio_y,
+    )?
+    .quote(b_token_amount_in, x2y)
+}
+
+/// Quotes a swap of `b_token_amount_in_x` from x to y and a swap of
+/// `b_token_amount_in_y` from y to x against the same pool state. Both
+/// directions share the D invariant, so it is only computed once.
+#[allow(clippy::too_many_arguments)]
+pub fn quote_both_directions(
+    // Amount in for the x to y swap (btoken token - e.g. bSUI)
+    b_token_amount_in_x: u64,
+    // Amount in for the y to x swap (btoken token - e.g. bUSDC)
+    b_token_amount_in_y: u64,
+    b_token_reserve_x: u64,
+    b_token_reserve_y: u64,
+    price_x: Decimal,
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+    swap_fee_bps: u64,
+    price_confidence_a: Decimal,
+    price_confidence_b: Decimal,
+) -> Result<(SwapQuote, SwapQuote)> {
+    let curve = CurveState::new(
+        b_token_reserve_x,
+        b_token_reserve_y,
+        price_x,
+        price_y,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        b_token_ratio_x,
+        b_token_ratio_y,
+    )?;
+    let (amount_out_y, _) = curve.quote(b_token_amount_in_x, true)?;
+    let (amount_out_x, _) = curve.quote(b_token_amount_in_y, false)?;
+
+    let price_uncertainty_ratio_a = price_uncertainty_ratio(price_x, price_confidence_a)?;
+    let price_uncertainty_ratio_b = price_uncertainty_ratio(price_y, price_confidence_b)?;
+    let swap_fee_override = Some(price_uncertainty_ratio_a.max(price_uncertainty_ratio_b));
+
+    Ok((
+        get_quote(
+            b_token_amount_in_x,
+            amount_out_y,
+            true,
+            swap_fee_bps,
+            swap_fee_override,
+        )?,
+        get_quote(
+            b_token_amount_in_y,
+            amount_out_x,
+            false,
+            swap_fee_bps,
+            swap_fee_override,
+        )?,
+    ))
+}
+
+/// Returns the marginal rate of the curve in USD terms, i.e. the USD value
+/// received per USD of an infinitesimal trade. This is 1 for a balanced pool
+/// and moves away from 1 as the pool becomes imbalanced.
+#[allow(clippy::too_many_arguments)]
+pub fn spot_usd_rate(
+    b_token_reserve_x: u64,
+    b_token_reserve_y: u64,
+    price_x: Decimal,
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    amplifier: u32,
+    x2y: bool,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+) -> Result<Decimal> {
+    CurveState::new(
+        b_token_reserve_x,
+        b_token_reserve_y,
+        price_x,
+        price_y,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        b_token_ratio_x,
+        b_token_ratio_y,
+    )?
+    .spot_usd_rate(x2y)
+}
+
+/// Quotes a swap on a plain StableSwap pool without an oracle, in underlying
+/// token units and without fees. Reserves are valued one to one once adjusted
+/// for decimals, like `pool_implied_price`, which matches an on-chain
+/// StableSwap pool of the same tokens.
+pub fn quote_stable_underlying(
+    // Amount in (underlying token - e.g. USDC or USDT)
+    amount_in: u64,
+    // Reserve X (underlying token - e.g. USDC)
+    reserve_x: u64,
+    // Reserve Y (underlying token - e.g. USDT)
+    reserve_y: u64,
+    amplifier: u32,
+    decimals_x: u32,
+    decimals_y: u32,
+    x2y: bool,
+) -> Result<u64> {
+    let (amount_out, _) = CurveState::new(
+        reserve_x,
+        reserve_y,
+        Decimal::ONE,
+        Decimal::ONE,
+        decimals_x,
+        decimals_y,
+        amplifier,
+        Decimal::ONE,
+        Decimal::ONE,
+    )?
+    .quote(amount_in, x2y)?;
 
-    let reserve_x = to_underlying(b_token_reserve_x, &b_token_ratio_x);
-    let reserve_y = to_underlying(b_token_reserve_y, &b_token_ratio_y);
+    Ok(amount_out)
+}
 
-    // We avoid using Decimal and use u256 instead to increase the overflow limit
-    // Reserves are in USD value and scaled by 10^10
-    let scaled_usd_reserve_x = to_usd(reserve_x, price_x, decimals_x);
-    let scaled_usd_reserve_y = to_usd(reserve_y, price_y, decimals_y);
+/// Returns the exchange rate implied by the curve alone, without an oracle:
+/// the amount of the output token, in whole units, received per whole unit of
+/// the input token for an infinitesimal trade. Reserves are underlying amounts
+/// and are valued one to one once adjusted for decimals, so a balanced pool
+/// implies a price of 1.
+///
+/// The marginal rate is estimated by perturbing the input reserve by a
+/// billionth and solving for the output reserve with `get_y`. Comparing it to
+/// `price_x / price_y` shows how far the oracle is from the pool balance.
+pub fn pool_implied_price(
+    reserve_x: u64,
+    reserve_y: u64,
+    amplifier: u32,
+    decimals_x: u32,
+    decimals_y: u32,
+    x2y: bool,
+) -> Result<Decimal> {
+    let one = Decimal::from(1u64);
+    let scaled_reserve_x = to_usd(reserve_x, one, decimals_x)?.0;
+    let scaled_reserve_y = to_usd(reserve_y, one, decimals_y)?.0;
+    if scaled_reserve_x.is_zero() || scaled_reserve_y.is_zero() {
+        return Err(anyhow::anyhow!(
+            "Implied price undefined for an empty reserve"
+        ));
+    }
 
-    // We follow the Curve convention where the amplifier is actually defined as
-    // A * n^(n-1) * A_PRECISION => A * 2^1 * A_PRECISION
-    let scaled_amp = U256::from(amplifier * 2) * U256::from(A_PRECISION);
-    let d = get_d(scaled_usd_reserve_x.0, scaled_usd_reserve_y.0, scaled_amp);
+    let scaled_amp = COINS.scaled_amp(amplifier);
+    let d = get_d(scaled_reserve_x, scaled_reserve_y, scaled_amp)?;
 
-    // let scaled_amount_in = U256::from(amount_in) * U256::from(SCALE);
+    let (reserve_in, reserve_out) = if x2y {
+        (scaled_reserve_x, scaled_reserve_y)
+    } else {
+        (scaled_reserve_y, scaled_reserve_x)
+    };
+    let delta_in = (reserve_in / U256::from(1_000_000_000u64)).max(U256::one());
+    let reserve_out_after_trade = get_y(reserve_in + delta_in, scaled_amp, d)?;
+    let delta_out = reserve_out.saturating_sub(reserve_out_after_trade);
 
-    let amount_out_btoken = if x2y {
-        let scaled_usd_amount_in = to_usd(amount_in, price_x, decimals_x);
+    Decimal::from_scaled_u256(delta_out)
+        .checked_div(&Decimal::from_scaled_u256(delta_in))
+        .ok_or_else(|| anyhow::anyhow!("Implied price overflow"))
+}
 
-        let scaled_usd_reserve_out_after_trade = get_y(
-            scaled_usd_reserve_x.0 + scaled_usd_amount_in.0,
-            scaled_amp,
-            d,
-        );
+/// Pool state shared by every quote against the same reserves and prices,
+/// including the D invariant.
+pub(crate) struct CurveState {
+    b_token_reserve_x: u64,
+    b_token_reserve_y: u64,
+    reserve_x: u64,
+    reserve_y: u64,
+    price_x: Decimal,
+    price_y: Decimal,
+    decimals_x: u32,
+    decimals_y: u32,
+    b_token_ratio_x: Decimal,
+    b_token_ratio_y: Decimal,
+    // Reserves are in USD value
+    pub(crate) scaled_usd_reserve_x: Decimal,
+    pub(crate) scaled_usd_reserve_y: Decimal,
+    scaled_amp: U256,
+    pub(crate) d: U256,
+    get_d_iterations: usize,
+}
 
-        let reserve_out_after_trade = from_usd(
-            Decimal::from_scaled_u256(scaled_usd_reserve_out_after_trade),
+impl CurveState {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        b_token_reserve_x: u64,
+        b_token_reserve_y: u64,
+        price_x: Decimal,
+        price_y: Decimal,
+        decimals_x: u32,
+        decimals_y: u32,
+        amplifier: u32,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<Self> {
+        validate_prices(price_x, price_y)?;
+
+        // Equal integer prices on tokens with the same decimals cancel out in the
+        // USD conversion, so the curve skips the price multiplication. The
+        // decimals still apply, to keep the reserves at the same USD scale.
+        let (price_x, price_y) =
+            if decimals_x == decimals_y && is_equal_integer_price(price_x, price_y) {
+                (Decimal::from(1u64), Decimal::from(1u64))
+            } else {
+                (price_x, price_y)
+            };
+
+        Self::with_prices(
+            b_token_reserve_x,
+            b_token_reserve_y,
+            price_x,
             price_y,
+            decimals_x,
             decimals_y,
-        );
+            amplifier,
+            b_token_ratio_x,
+            b_token_ratio_y,
+        )
+    }
 
-        let amount_out_underlying = reserve_y - reserve_out_after_trade;
-        let amount_out_btoken = to_b_token(amount_out_underlying, &b_token_ratio_y);
+    // Same as `new`, always converting the reserves at the given prices. Takes
+    // the same arguments as `new`.
+    #[allow(clippy::too_many_arguments)]
+    fn with_prices(
+        b_token_reserve_x: u64,
+        b_token_reserve_y: u64,
+        price_x: Decimal,
+        price_y: Decimal,
+        decimals_x: u32,
+        decimals_y: u32,
+        amplifier: u32,
+        b_token_ratio_x: Decimal,
+        b_token_ratio_y: Decimal,
+    ) -> Result<Self> {
+        let reserve_x = to_underlying(b_token_reserve_x, &b_token_ratio_x)?;
+        let reserve_y = to_underlying(b_token_reserve_y, &b_token_ratio_y)?;
 
-        if amount_out_btoken > b_token_reserve_y {
-            return Ok(0);
+        // We avoid using Decimal and use u256 instead to increase the overflow limit
+        let scaled_usd_reserve_x = to_usd(reserve_x, price_x, decimals_x)?;
+        let scaled_usd_reserve_y = to_usd(reserve_y, price_y, decimals_y)?;
+
+        // The invariant divides by both reserves and by `Ann - 1`, so an empty
+        // side or a zero amplifier has no curve to quote on
+        if scaled_usd_reserve_x.0.is_zero() || scaled_usd_reserve_y.0.is_zero() {
+            return Err(anyhow::anyhow!("Pool reserves must be greater than zero"));
         }
-        amount_out_btoken
-    } else {
-        let scaled_usd_amount_in = to_usd(amount_in, price_y, decimals_y);
+        if amplifier == 0 {
+            return Err(anyhow::anyhow!("Amplifier must be greater than zero"));
+        }
+
+        // We follow the Curve convention where the amplifier is actually defined as
+        // A * n^(n-1) * A_PRECISION => A * 2^1 * A_PRECISION
+        let scaled_amp = COINS.scaled_amp(amplifier);
+        let (d, get_d_iterations) =
+            get_d_with_iterations(scaled_usd_reserve_x.0, scaled_usd_reserve_y.0, scaled_amp)?;
 
-        let scaled_usd_reserve_out_after_trade = get_y(
-            scaled_usd_reserve_y.0 + scaled_usd_amount_in.0,
+        Ok(Self {
+            b_token_reserve_x,
+            b_token_reserve_y,
+            reserve_x,
+            reserve_y,
+            price_x,
+            price_y,
+            decimals_x,
+            decimals_y,
+            b_token_ratio_x,
+            b_token_ratio_y,
+            scaled_usd_reserve_x,
+            scaled_usd_reserve_y,
             scaled_amp,
             d,
+            get_d_iterations,
+        })
+    }
+
+    /// Returns `-dy/dx` on the invariant `4xy * (Ann * (x + y) - (Ann - 1) * D) = D^3`
+    /// where x is the input side and y the output side, both in USD:
+    ///
+    /// `y * (Ann * (2x + y) - (Ann - 1) * D) / (x * (Ann * (x + 2y) - (Ann - 1) * D))`
+    fn spot_usd_rate(&self, x2y: bool) -> Result<Decimal> {
+        let (x, y) = if x2y {
+            (self.scaled_usd_reserve_x, self.scaled_usd_reserve_y)
+        } else {
+            (self.scaled_usd_reserve_y, self.scaled_usd_reserve_x)
+        };
+        let overflow = || anyhow::anyhow!("Spot price overflow");
+
+        // Ann = A * n^n, see the scaling of the amplifier in `new`
+        let ann = Decimal::from_scaled_u256(
+            COINS.ann(self.scaled_amp) * Decimal::wad() / U256::from(A_PRECISION),
         );
+        let d_term = ann
+            .checked_sub(&Decimal::from(1u64))
+            .and_then(|ann_minus_one| ann_minus_one.checked_mul(&Decimal::from_scaled_u256(self.d)))
+            .ok_or_else(overflow)?;
+        let partial = |a: Decimal, b: Decimal| -> Result<Decimal> {
+            a.checked_add(&a)
+                .and_then(|two_a| two_a.checked_add(&b))
+                .and_then(|sum| ann.checked_mul(&sum))
+                .and_then(|sum| sum.checked_sub(&d_term))
+                .and_then(|sum| b.checked_mul(&sum))
+                .ok_or_else(|| anyhow::anyhow!("Pool too imbalanced for spot price"))
+        };
+
+        partial(x, y)?
+            .checked_div(&partial(y, x)?)
+            .ok_or_else(overflow)
+    }
+
+    /// Returns how far the pool's marginal price is from the oracle price, in bps.
+    fn imbalance_bps(&self, x2y: bool) -> Result<u64> {
+        let rate = self.spot_usd_rate(x2y)?;
+        let drift = if rate >= Decimal::ONE {
+            rate.saturating_sub(&Decimal::ONE)
+        } else {
+            Decimal::ONE.saturating_sub(&rate)
+        };
+
+        Ok(drift.to_bps().min(BPS_SCALE))
+    }
+
+    /// Returns the btoken amount out, before fees, for the given btoken amount in.
+    fn quote(&self, b_token_amount_in: u64, x2y: bool) -> Result<(u64, QuoteDiagnostics)> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "omm_v2_new::quote",
+            b_token_amount_in,
+            x2y,
+            d = %self.d,
+            scaled_usd_reserve_x = %self.scaled_usd_reserve_x,
+            scaled_usd_reserve_y = %self.scaled_usd_reserve_y,
+            get_d_iterations = self.get_d_iterations,
+        )
+        .entered();
+
+        let (
+            b_token_ratio_in,
+            price_in,
+            decimals_in,
+            scaled_usd_reserve_in,
+            b_token_reserve_out,
+            reserve_out,
+            b_token_ratio_out,
+            price_out,
+            decimals_out,
+        ) = if x2y {
+            (
+                &self.b_token_ratio_x,
+                self.price_x,
+                self.decimals_x,
+                self.scaled_usd_reserve_x,
+                self.b_token_reserve_y,
+                self.reserve_y,
+                &self.b_token_ratio_y,
+                self.price_y,
+                self.decimals_y,
+            )
+        } else {
+            (
+                &self.b_token_ratio_y,
+                self.price_y,
+                self.decimals_y,
+                self.scaled_usd_reserve_y,
+                self.b_token_reserve_x,
+                self.reserve_x,
+                &self.b_token_ratio_x,
+                self.price_x,
+                self.decimals_x,
+            )
+        };
+
+        let amount_in = to_underlying(b_token_amount_in, b_token_ratio_in)?;
+        let scaled_usd_amount_in = to_usd(amount_in, price_in, decimals_in)?;
+
+        let scaled_usd_reserve_in_after_trade = scaled_usd_reserve_in
+            .0
+            .checked_add(scaled_usd_amount_in.0)
+            .ok_or_else(|| anyhow::anyhow!("Reserve overflow"))?;
+        let (scaled_usd_reserve_out_after_trade, get_y_iterations) =
+            get_y_with_iterations(scaled_usd_reserve_in_after_trade, self.scaled_amp, self.d)?;
+        let diagnostics = QuoteDiagnostics {
+            get_d_iterations: self.get_d_iterations,
+            get_y_iterations,
+            ..Default::default()
+        };
 
         let reserve_out_after_trade = from_usd(
             Decimal::from_scaled_u256(scaled_usd_reserve_out_after_trade),
-            price_x,
-            decimals_x,
+            price_out,
+            decimals_out,
+        )?;
+
+        let amount_out_underlying = reserve_out
+            .checked_sub(reserve_out_after_trade)
+            .ok_or_else(|| anyhow::anyhow!("Reserve after trade exceeds reserve"))?;
+        let amount_out_btoken = to_b_token(amount_out_underlying, b_token_ratio_out)?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            scaled_usd_amount_in = %scaled_usd_amount_in,
+            scaled_usd_reserve_out_after_trade = %scaled_usd_reserve_out_after_trade,
+            get_y_iterations,
+            amount_out = amount_out_btoken,
+            "quoted"
         );
 
-        let amount_out_underlying = reserve_x - reserve_out_after_trade;
-        let amount_out_btoken = to_b_token(amount_out_underlying, &b_token_ratio_x);
+        if amount_out_btoken > b_token_reserve_out {
+            return Ok((
+                0,
+                QuoteDiagnostics {
+                    capped_by_reserves: true,
+                    ..diagnostics
+                },
+            ));
+        }
+
+        Ok((amount_out_btoken, diagnostics))
+    }
+}
+
+/// Converts a unit amount into a USD amount using split price.
+pub fn to_usd(amount: u64, price: Decimal, decimals: u32) -> Result<Decimal> {
+    Decimal::from(amount)
+        .checked_mul(&price)
+        .ok_or_else(|| anyhow::anyhow!("USD conversion overflow"))?
+        .checked_div_pow10(decimals)
+        .ok_or_else(|| anyhow::anyhow!("USD conversion failed"))
+}
+
+/// Returns true if both prices are the same nonzero integer.
+fn is_equal_integer_price(price_x: Decimal, price_y: Decimal) -> bool {
+    price_x == price_y && !price_x.is_zero() && (price_x.0 % Decimal::wad()).is_zero()
+}
+
+/// Converts a USD amount into a unit amount using split price.
+/// Errors if the unit amount does not fit in a u64.
+///
+/// `from_usd(to_usd(amount))` never exceeds `amount` and falls short by at
+/// most `1 + 10^decimals / (price * 10^18)` units. The loss comes from
+/// `to_usd` truncating to 18 decimal places of USD, so it is only noticeable
+/// for cheap tokens with many decimals: at most 1 unit whenever the price is
+/// at least `10^(decimals - 18)`, but up to 10^6 units for an 18 decimal
+/// token priced at 0.000001.
+pub fn from_usd(usd_amount: Decimal, price: Decimal, decimals: u32) -> Result<u64> {
+    usd_amount
+        .checked_div(&price)
+        .ok_or_else(|| anyhow::anyhow!("USD conversion failed"))?
+        .checked_mul_pow10(decimals)
+        .ok_or_else(|| anyhow::anyhow!("USD conversion overflow"))?
+        .checked_ceil()
+        .ok_or_else(|| anyhow::anyhow!("USD conversion exceeds u64"))
+}
+
+/// Newton's method ran out of iterations before two consecutive iterates came
+/// within one unit of each other. Returned, wrapped in an `anyhow::Error`, by
+/// `get_d` and `get_y`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConvergenceError {
+    /// `"get_d"` or `"get_y"`
+    pub solver: &'static str,
+    /// The last iterate computed
+    pub last: U256,
+    /// Distance between the last two iterates
+    pub delta: U256,
+    pub iterations: usize,
+}
+
+impl std::fmt::Display for ConvergenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} did not converge after {} iterations: last = {}, delta = {}",
+            self.solver, self.iterations, self.last, self.delta
+        )
+    }
+}
+
+impl std::error::Error for ConvergenceError {}
+
+/// Calculates the D invariant for a 2-coin pool using integer math.
+/// Returns D as U256, or an error if a reserve is zero or the iteration does
+/// not converge, in which case the error is a `ConvergenceError`.
+///
+/// Inputs that fit in a u64 are first tried on the u128 fast path, which
+/// produces the same result as the U256 path whenever no intermediate value
+/// overflows. Larger inputs, or any overflow, fall back to U256 math.
+///
+/// Newton's method starts from `reserve_a + reserve_b`, an upper bound on D,
+/// and decreases monotonically from there. Starting from the lower bound
+/// `2 * sqrt(reserve_a * reserve_b)` instead overshoots on the first step. That
+/// takes more iterations on average, including on imbalanced pools, and stops
+/// one unit away from the current result on about 2% of them.
+pub fn get_d(reserve_a: U256, reserve_b: U256, amp: U256) -> Result<U256> {
+    Ok(get_d_with_iterations(reserve_a, reserve_b, amp)?.0)
+}
+
+/// Same as `get_d`, also returning the number of iterations run.
+pub fn get_d_with_iterations(reserve_a: U256, reserve_b: U256, amp: U256) -> Result<(U256, usize)> {
+    if let (Ok(reserve_a), Ok(reserve_b), Ok(amp)) = (
+        u64::try_from(reserve_a),
+        u64::try_from(reserve_b),
+        u64::try_from(amp),
+    ) && let Some((d, iterations)) =
+        get_d_u128_with_iterations(reserve_a as u128, reserve_b as u128, amp as u128)
+    {
+        return Ok((U256::from(d), iterations));
+    }
+
+    get_d_u256_with_iterations(reserve_a, reserve_b, amp, LIMIT)
+}
+
+/// Computes `get_d` using U256 math only.
+pub fn get_d_u256(reserve_a: U256, reserve_b: U256, amp: U256) -> Result<U256> {
+    Ok(get_d_u256_with_iterations(reserve_a, reserve_b, amp, LIMIT)?.0)
+}
+
+fn get_d_u256_with_iterations(
+    reserve_a: U256,
+    reserve_b: U256,
+    amp: U256,
+    max_iterations: usize,
+) -> Result<(U256, usize)> {
+    if reserve_a.is_zero() || reserve_b.is_zero() {
+        return Err(anyhow::anyhow!("get_d called with a zero reserve"));
+    }
+
+    // Reserves near the top of the U256 range overflow the intermediate
+    // products, which is reported as an error instead of wrapping or panicking
+    let overflow = || anyhow::anyhow!("get_d overflow");
+    let a_precision = U256::from(A_PRECISION);
+
+    let sum = reserve_a.checked_add(reserve_b).ok_or_else(overflow)?;
+    // Same as `COINS.ann(amp)`, checked
+    let ann = amp
+        .checked_mul(U256::from(COINS.n()))
+        .ok_or_else(overflow)?;
+    let ann_sum = ann
+        .checked_mul(sum)
+        .map(|v| v / a_precision)
+        .ok_or_else(overflow)?;
+    let ann_minus_one = ann.checked_sub(a_precision).ok_or_else(overflow)?;
+
+    let mut d = sum;
+    let mut d_prev = d;
+    let mut limit = max_iterations;
+
+    while limit > 0 {
+        let d_p = d
+            .checked_mul(d)
+            .map(|v| v / reserve_a)
+            .and_then(|v| v.checked_mul(d))
+            .map(|v| v / reserve_b / U256::from(COINS.n_pow_n()))
+            .ok_or_else(overflow)?;
 
-        if amount_out_btoken > b_token_reserve_x {
-            return Ok(0);
+        d_prev = d;
+
+        let numerator = d_p
+            .checked_mul(U256::from(COINS.n()))
+            .and_then(|v| v.checked_add(ann_sum))
+            .and_then(|v| v.checked_mul(d))
+            .ok_or_else(overflow)?;
+        let denominator = ann_minus_one
+            .checked_mul(d)
+            .map(|v| v / a_precision)
+            .zip(U256::from(COINS.n() + 1).checked_mul(d_p))
+            .and_then(|(a, b)| a.checked_add(b))
+            .ok_or_else(overflow)?;
+
+        d = numerator.checked_div(denominator).ok_or_else(overflow)?;
+
+        if d > d_prev {
+            if d - d_prev <= U256::one() {
+                return Ok((d, max_iterations - limit + 1));
+            }
+        } else {
+            if d_prev - d <= U256::one() {
+                return Ok((d, max_iterations - limit + 1));
+            }
         }
-        amount_out_btoken
-    };
 
-    Ok(amount_out_btoken)
-}
+        limit -= 1;
+    }
 
-/// Converts a unit amount into a USD amount using split price.
-pub fn to_usd(amount: u64, price: Decimal, decimals: u32) -> Decimal {
-    Decimal::from(amount)
-        .checked_mul(&price)
-        .unwrap()
-        .checked_div(&Decimal::from(10_u64.pow(decimals)))
-        .unwrap()
+    Err(ConvergenceError {
+        solver: "get_d",
+        last: d,
+        delta: d.abs_diff(d_prev),
+        iterations: max_iterations,
+    }
+    .into())
 }
 
-/// Converts a USD amount into a unit amount using split price.
-pub fn from_usd(usd_amount: Decimal, price: Decimal, decimals: u32) -> u64 {
-    usd_amount
-        .checked_div(&price)
-        .unwrap()
-        .checked_mul(&Decimal::from(10_u64.pow(decimals)))
-        .unwrap()
-        .checked_ceil()
-        .unwrap()
+/// Computes `get_d` using u128 math. Returns `None` if an intermediate value
+/// overflows or if the iteration does not converge.
+pub fn get_d_u128(reserve_a: u128, reserve_b: u128, amp: u128) -> Option<u128> {
+    get_d_u128_with_iterations(reserve_a, reserve_b, amp).map(|(d, _)| d)
 }
 
-/// Calculates the D invariant for a 2-coin pool using integer math.
-/// Returns D as U256 or panics if it does not converge.
-pub fn get_d(reserve_a: U256, reserve_b: U256, amp: U256) -> U256 {
-    let sum = reserve_a + reserve_b;
-    let ann = amp * U256::from(2u8); // n = 2 coins
+fn get_d_u128_with_iterations(
+    reserve_a: u128,
+    reserve_b: u128,
+    amp: u128,
+) -> Option<(u128, usize)> {
+    let sum = reserve_a.checked_add(reserve_b)?;
+    let ann = COINS.checked_ann(amp)?;
 
     let mut d = sum;
     let mut limit = LIMIT;
 
     while limit > 0 {
         let mut d_p = d;
-        d_p = d_p * d / reserve_a;
-        d_p = d_p * d / reserve_b;
-        d_p = d_p / U256::from(4u8);
+        d_p = d_p.checked_mul(d)?.checked_div(reserve_a)?;
+        d_p = d_p.checked_mul(d)?.checked_div(reserve_b)?;
+        d_p /= COINS.n_pow_n();
 
         let d_prev = d;
 
-        let numerator = ((ann * sum / U256::from(A_PRECISION)) + d_p * U256::from(2u8)) * d;
-        let denominator = ((ann - U256::from(A_PRECISION)) * d / U256::from(A_PRECISION))
-            + (U256::from(3u8) * d_p);
+        let numerator = (ann.checked_mul(sum)? / A_PRECISION)
+            .checked_add(d_p.checked_mul(COINS.n())?)?
+            .checked_mul(d)?;
+        let denominator = (ann.checked_sub(A_PRECISION)?.checked_mul(d)? / A_PRECISION)
+            .checked_add(d_p.checked_mul(COINS.n() + 1)?)?;
 
-        d = numerator / denominator;
+        d = numerator.checked_div(denominator)?;
 
-        if d > d_prev {
-            if d - d_prev <= U256::one() {
-                return d;
-            }
-        } else {
-            if d_prev - d <= U256::one() {
-                return d;
-            }
+        if d.abs_diff(d_prev) <= 1 {
+            return Some((d, LIMIT - limit + 1));
         }
 
         limit -= 1;
     }
 
-    panic!("get_d did not converge");
+    None
 }
 
 /// Calculates the output reserve after a swap using the StableSwap invariant.
-/// Returns the new reserve as U256 or panics if it does not converge.
-pub fn get_y(reserve_in: U256, amp: U256, d: U256) -> U256 {
-    let ann = amp * U256::from(2u8);
+/// Returns the new reserve as U256, or an error if the input reserve is zero
+/// or the iteration does not converge.
+///
+/// Like `get_d`, inputs that fit in a u64 are first tried on the u128 fast
+/// path before falling back to U256 math.
+pub fn get_y(reserve_in: U256, amp: U256, d: U256) -> Result<U256> {
+    Ok(get_y_with_iterations(reserve_in, amp, d)?.0)
+}
+
+/// Same as `get_y`, also returning the number of iterations run.
+pub fn get_y_with_iterations(reserve_in: U256, amp: U256, d: U256) -> Result<(U256, usize)> {
+    if let (Ok(reserve_in), Ok(amp), Ok(d)) = (
+        u64::try_from(reserve_in),
+        u64::try_from(amp),
+        u64::try_from(d),
+    ) && let Some((y, iterations)) =
+        get_y_u128_with_iterations(reserve_in as u128, amp as u128, d as u128)
+    {
+        return Ok((U256::from(y), iterations));
+    }
+
+    get_y_u256_with_iterations(reserve_in, amp, d, LIMIT)
+}
+
+/// Computes `get_y` using U256 math only.
+pub fn get_y_u256(reserve_in: U256, amp: U256, d: U256) -> Result<U256> {
+    Ok(get_y_u256_with_iterations(reserve_in, amp, d, LIMIT)?.0)
+}
+
+fn get_y_u256_with_iterations(
+    reserve_in: U256,
+    amp: U256,
+    d: U256,
+    max_iterations: usize,
+) -> Result<(U256, usize)> {
+    if reserve_in.is_zero() {
+        return Err(anyhow::anyhow!("get_y called with a zero input reserve"));
+    }
+
+    // Same as in `get_d_u256_with_iterations`, overflow is an error
+    let overflow = || anyhow::anyhow!("get_y overflow");
+    let n = U256::from(COINS.n());
+    let a_precision = U256::from(A_PRECISION);
+
+    // Same as `COINS.ann(amp)`, checked
+    let ann = amp.checked_mul(n).ok_or_else(overflow)?;
 
     let sum = reserve_in;
-    let mut c = d * d / (U256::from(2u8) * reserve_in);
-    c = c * d * U256::from(A_PRECISION) / (ann * U256::from(2u8));
+    let c = d
+        .checked_mul(d)
+        .zip(n.checked_mul(reserve_in))
+        .and_then(|(v, n_reserve_in)| v.checked_div(n_reserve_in))
+        .and_then(|v| v.checked_mul(d))
+        .and_then(|v| v.checked_mul(a_precision))
+        .zip(ann.checked_mul(n))
+        .and_then(|(v, ann_n)| v.checked_div(ann_n))
+        .ok_or_else(overflow)?;
 
-    let b = sum + d * U256::from(A_PRECISION) / ann;
-    let mut y_prev;
+    let b = d
+        .checked_mul(a_precision)
+        .and_then(|v| v.checked_div(ann))
+        .and_then(|v| v.checked_add(sum))
+        .ok_or_else(overflow)?;
+    let mut y_prev = d;
     let mut y = d;
 
-    let mut limit = LIMIT;
+    let mut limit = max_iterations;
 
     while limit > 0 {
         y_prev = y;
-        y = (y * y + c) / (U256::from(2u8) * y + b - d);
+        let numerator = y
+            .checked_mul(y)
+            .and_then(|v| v.checked_add(c))
+            .ok_or_else(overflow)?;
+        let denominator = y
+            .checked_mul(U256::from(2u8))
+            .and_then(|v| v.checked_add(b))
+            .and_then(|v| v.checked_sub(d))
+            .ok_or_else(overflow)?;
+        y = numerator.checked_div(denominator).ok_or_else(overflow)?;
 
         if y > y_prev {
             if y - y_prev <= U256::one() {
-                return y;
+                return Ok((y, max_iterations - limit + 1));
             }
         } else {
             if y_prev - y <= U256::one() {
-                return y;
+                return Ok((y, max_iterations - limit + 1));
             }
         }
 
         limit -= 1;
     }
 
-    panic!("get_y did not converge");
+    Err(ConvergenceError {
+        solver: "get_y",
+        last: y,
+        delta: y.abs_diff(y_prev),
+        iterations: max_iterations,
+    }
+    .into())
+}
+
+/// Computes `get_y` using u128 math. Returns `None` if an intermediate value
+/// overflows or if the iteration does not converge.
+pub fn get_y_u128(reserve_in: u128, amp: u128, d: u128) -> Option<u128> {
+    get_y_u128_with_iterations(reserve_in, amp, d).map(|(y, _)| y)
+}
+
+fn get_y_u128_with_iterations(reserve_in: u128, amp: u128, d: u128) -> Option<(u128, usize)> {
+    let ann = COINS.checked_ann(amp)?;
+
+    let sum = reserve_in;
+    let mut c = d
+        .checked_mul(d)?
+        .checked_div(reserve_in.checked_mul(COINS.n())?)?;
+    c = c
+        .checked_mul(d)?
+        .checked_mul(A_PRECISION)?
+        .checked_div(ann.checked_mul(COINS.n())?)?;
+
+    let b = sum.checked_add(d.checked_mul(A_PRECISION)?.checked_div(ann)?)?;
+    let mut y_prev;
+    let mut y = d;
+
+    let mut limit = LIMIT;
+
+    while limit > 0 {
+        y_prev = y;
+        y = y
+            .checked_mul(y)?
+            .checked_add(c)?
+            .checked_div(y.checked_mul(2)?.checked_add(b)?.checked_sub(d)?)?;
+
+        if y.abs_diff(y_prev) <= 1 {
+            return Some((y, LIMIT - limit + 1));
+        }
+
+        limit -= 1;
+    }
+
+    None
 }
 
 #[cfg(test)]
@@ -362,23 +1204,102 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_quote_swap_errors_when_amounts_exceed_u64() {
+        // Underlying reserve exceeds u64::MAX once the btoken ratio is applied
+        let result = quote_swap_no_fees(
+            10_000_000,
+            1_000_000_000_000,
+            u64::MAX / 2,
+            Decimal::from("3"),
+            Decimal::from("1"),
+            9,
+            6,
+            1,
+            true,
+            Decimal::from("1.0"),
+            Decimal::from("3.0"),
+        );
+        assert!(result.is_err());
+
+        // Underlying amount in exceeds u64::MAX once the btoken ratio is applied
+        let result = quote_swap_no_fees(
+            u64::MAX - 1,
+            1_000_000_000_000,
+            1_000_000_000,
+            Decimal::from("3"),
+            Decimal::from("1"),
+            9,
+            6,
+            1,
+            true,
+            Decimal::from("1.5"),
+            Decimal::from("1.0"),
+        );
+        assert!(result.is_err());
+
+        // A USD amount worth more than u64::MAX units of a cheap token
+        let result = from_usd(Decimal::from(u64::MAX), Decimal::from("0.5"), 0);
+        assert!(result.is_err());
+        assert_eq!(
+            from_usd(Decimal::from(u64::MAX / 2), Decimal::from("0.5"), 0).unwrap(),
+            u64::MAX - 1
+        );
+    }
+
+    #[test]
+    fn test_quote_swap_iteration_counts() -> Result<()> {
+        for amplifier in [1, 10, 100, 1000, 8000] {
+            for x2y in [true, false] {
+                let (_, diagnostics) = quote_swap_no_fees_instrumented(
+                    if x2y { 100_000_000_000 } else { 300_000_000 },
+                    1_000_000_000_000,
+                    3_000_000_000,
+                    Decimal::from("3"),
+                    Decimal::from("1"),
+                    9,
+                    6,
+                    amplifier,
+                    x2y,
+                    Decimal::from("1.0"),
+                    Decimal::from("1.0"),
+                )?;
+
+                assert!(diagnostics.get_d_iterations > 0 && diagnostics.get_d_iterations <= LIMIT);
+                assert!(diagnostics.get_y_iterations > 0 && diagnostics.get_y_iterations <= LIMIT);
+                assert_eq!(diagnostics.newton_raphson_iterations, 0);
+            }
+        }
+
+        // A balanced pool starts at D = sum and converges right away
+        let (d, iterations) =
+            get_d_with_iterations(u256(1_000_000), u256(1_000_000), u256(20_000)).unwrap();
+        assert_eq!(d, u256(2_000_000));
+        assert_eq!(iterations, 1);
+
+        Ok(())
+    }
+
     fn assert_get_d_u64(reserve_a: u64, reserve_b: u64, amp: u64, expected: u64) {
         assert_eq!(
-            get_d(u256(reserve_a), u256(reserve_b), u256(amp)),
+            get_d(u256(reserve_a), u256(reserve_b), u256(amp)).unwrap(),
             u256(expected)
         );
     }
     fn assert_get_d(reserve_a: U256, reserve_b: U256, amp: U256, expected: U256) {
-        assert_eq!(get_d(reserve_a, reserve_b, amp,), expected);
+        assert_eq!(get_d(reserve_a, reserve_b, amp,).unwrap(), expected);
     }
 
     fn assert_get_y_u64(reserve_in: u64, amp: u64, d: u64, expected: u64) {
-        assert_eq!(get_y(u256(reserve_in), u256(amp), u256(d)), u256(expected));
+        assert_eq!(
+            get_y(u256(reserve_in), u256(amp), u256(d)).unwrap(),
+            u256(expected)
+        );
     }
 
     fn assert_get_y_scaled(reserve_in: U256, amp: U256, d: U256, expected: U256) {
         let upscale = U256::from(10u64).pow(U256::from(10u64));
-        let result = get_y(reserve_in * upscale, amp, d * upscale) / upscale;
+        let result = get_y(reserve_in * upscale, amp, d * upscale).unwrap() / upscale;
         let diff = if result > expected {
             result - expected
         } else {
@@ -397,6 +1318,42 @@ mod tests {
         U256::from(val)
     }
 
+    #[test]
+    fn test_coin_count() {
+        // The constants the two coin invariant was written with
+        let two = CoinCount::TWO;
+        assert_eq!(CoinCount::new(2), Some(two));
+        assert_eq!(two.n(), 2);
+        assert_eq!(two.n_pow_n(), 4);
+        assert_eq!(two.scaled_amp(100), U256::from(100u64 * 2 * 100));
+        assert_eq!(two.ann(U256::from(20_000u64)), U256::from(40_000u64));
+        assert_eq!(two.checked_ann(20_000), Some(40_000));
+        assert_eq!(two.checked_ann(u128::MAX), None);
+
+        let three = CoinCount::new(3).unwrap();
+        assert_eq!(three.n_pow_n(), 27);
+        assert_eq!(three.scaled_amp(100), U256::from(100u64 * 9 * 100));
+        assert_eq!(
+            three.ann(three.scaled_amp(100)),
+            U256::from(100u64 * 27 * 100)
+        );
+
+        assert_eq!(CoinCount::new(1), None);
+        assert_eq!(CoinCount::new(0), None);
+
+        // 26^26 is the largest n^n that fits in a u128
+        let max = CoinCount::new(26).unwrap();
+        assert_eq!(max, CoinCount::MAX);
+        assert_eq!(max.n_pow_n(), 26u128.pow(26));
+        assert_eq!(
+            max.scaled_amp(u32::MAX),
+            U256::from(u32::MAX) * U256::from(26u128.pow(25)) * U256::from(A_PRECISION)
+        );
+        assert_eq!(27u128.checked_pow(27), None);
+        assert_eq!(CoinCount::new(27), None);
+        assert_eq!(CoinCount::new(u32::MAX), None);
+    }
+
     #[test]
     fn test_get_d() {
         assert_get_d_u64(1_000_000, 1_000_000, 20_000, 2_000_000);
@@ -462,6 +1419,70 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_d_fast_path_matches_u256() {
+        let vectors = [
+            (1_000_000u64, 1_000_000u64, 20_000u64),
+            (646_604_101_554_903, 430_825_829_860_939, 10_000),
+            (208_391_493_399_283, 381_737_267_304_454, 6_000),
+            (357_533_698_368_810, 292_279_113_116_023, 200_000),
+            (640_219_149_077_469, 749_346_581_809_482, 6_000),
+            (796_587_650_933_232, 263_696_548_289_376, 20_000),
+            (645_814_702_742_123, 941_346_843_035_970, 6_000),
+            (36_731_011_531_180, 112_244_514_819_796, 6_000),
+            (638_355_455_638_005, 144_419_816_425_350, 20_000),
+            (747_070_395_683_716, 583_370_126_767_355, 200_000),
+            (222_152_880_197_132, 503_754_962_483_370, 10_000),
+            (30_000_000_000_000, 10_000_000_000_000, 200),
+        ];
+
+        for (reserve_a, reserve_b, amp) in vectors {
+            let slow = get_d_u256(u256(reserve_a), u256(reserve_b), u256(amp)).unwrap();
+            let fast = get_d_u128(reserve_a as u128, reserve_b as u128, amp as u128);
+            assert_eq!(fast.map(U256::from), Some(slow));
+            assert_eq!(
+                get_d(u256(reserve_a), u256(reserve_b), u256(amp)).unwrap(),
+                slow
+            );
+        }
+
+        // Values that overflow u128 intermediates fall back to U256 math
+        let reserve = u64::MAX as u128;
+        assert_eq!(get_d_u128(reserve, reserve, 20_000), None);
+        assert_eq!(
+            get_d(u256(u64::MAX), u256(u64::MAX), u256(20_000)).unwrap(),
+            get_d_u256(u256(u64::MAX), u256(u64::MAX), u256(20_000)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_y_fast_path_matches_u256() {
+        let vectors = [
+            (1_010_000u64, 20_000u64, 2_000_000u64),
+            (1_045_311_940_606_135, 10_000, 1_077_207_198_258_876),
+            (628_789_391_533_719, 6_000, 589_673_027_554_751),
+            (664_497_701_537_459, 200_000, 649_811_157_409_887),
+            (1_241_196_069_415_337, 6_000, 1_389_495_058_454_884),
+            (1_207_464_631_415_294, 20_000, 1_059_395_029_204_629),
+            (1_326_030_781_815_325, 6_000, 1_586_694_700_461_120),
+            (596_549_235_149_733, 6_000, 148_556_820_223_757),
+            (1_412_549_409_240_877, 20_000, 781_493_318_669_443),
+            (966_973_926_501_573, 200_000, 1_330_435_412_150_341),
+            (468_614_952_287_735, 10_000, 725_272_897_710_721),
+            (10_100_000_000_000, 200, 38_041_326_932_308),
+            (30_051_565_391_310, 200, 38_041_326_932_308),
+        ];
+
+        for (reserve_in, amp, d) in vectors {
+            let slow = get_y_u256(u256(reserve_in), u256(amp), u256(d)).unwrap();
+            let fast = get_y_u128(reserve_in as u128, amp as u128, d as u128);
+            assert_eq!(fast.map(U256::from), Some(slow));
+            assert_eq!(get_y(u256(reserve_in), u256(amp), u256(d)).unwrap(), slow);
+        }
+
+        assert_eq!(get_y_u128(1, 200, u64::MAX as u128), None);
+    }
+
     #[test]
     fn test_get_d_scaled() {
         // Tests that scaling the reserves leads to the linear scaling of the D value
@@ -478,7 +1499,9 @@ mod tests {
                 u256(646_604_101_554_903) * upscale,
                 u256(430_825_829_860_939) * upscale,
                 u256(10_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(1_077_207_198_258_876)
         );
         assert_eq!(
@@ -486,7 +1509,9 @@ mod tests {
                 u256(208_391_493_399_283) * upscale,
                 u256(381_737_267_304_454) * upscale,
                 u256(6_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(589_673_027_554_751)
         );
         assert_eq!(
@@ -494,7 +1519,9 @@ mod tests {
                 u256(357_533_698_368_810) * upscale,
                 u256(292_279_113_116_023) * upscale,
                 u256(200_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(649_811_157_409_887)
         );
         assert_eq!(
@@ -502,7 +1529,9 @@ mod tests {
                 u256(640_219_149_077_469) * upscale,
                 u256(749_346_581_809_482) * upscale,
                 u256(6_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(1_389_495_058_454_884)
         );
         assert_eq!(
@@ -510,7 +1539,9 @@ mod tests {
                 u256(796_587_650_933_232) * upscale,
                 u256(263_696_548_289_376) * upscale,
                 u256(20_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(1_059_395_029_204_629)
         );
         assert_eq!(
@@ -518,7 +1549,9 @@ mod tests {
                 u256(645_814_702_742_123) * upscale,
                 u256(941_346_843_035_970) * upscale,
                 u256(6_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(1_586_694_700_461_120)
         );
         assert_eq!(
@@ -526,7 +1559,9 @@ mod tests {
                 u256(36_731_011_531_180) * upscale,
                 u256(112_244_514_819_796) * upscale,
                 u256(6_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(148_556_820_223_757)
         );
         assert_eq!(
@@ -534,7 +1569,9 @@ mod tests {
                 u256(638_355_455_638_005) * upscale,
                 u256(144_419_816_425_350) * upscale,
                 u256(20_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(781_493_318_669_443)
         );
         assert_eq!(
@@ -542,7 +1579,9 @@ mod tests {
                 u256(747_070_395_683_716) * upscale,
                 u256(583_370_126_767_355) * upscale,
                 u256(200_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(1_330_435_412_150_341)
         );
         assert_eq!(
@@ -550,7 +1589,9 @@ mod tests {
                 u256(222_152_880_197_132) * upscale,
                 u256(503_754_962_483_370) * upscale,
                 u256(10_000)
-            ) / upscale,
+            )
+            .unwrap()
+                / upscale,
             u256(725_272_897_710_721)
         );
 
@@ -748,8 +1789,547 @@ mod tests {
         let out = to_underlying(
             quote.amount_out,
             &Decimal(U256::from(1021227215424886858u64)),
-        );
+        )?;
         println!("out: {}", out);
         Ok(())
     }
+
+    #[test]
+    fn test_quote_swap_equal_integer_prices() -> Result<()> {
+        // USDC/USDT style pool, balanced in USD terms
+        for amplifier in [1, 10, 100, 1000] {
+            for amount_in in [1, 1_000_000, 123_456_789, 500_000_000_000] {
+                let x2y = quote_swap_no_fees(
+                    amount_in,
+                    1_000_000_000_000,
+                    1_000_000_000_000,
+                    Decimal::from("1"),
+                    Decimal::from("1"),
+                    6,
+                    6,
+                    amplifier,
+                    true,
+                    Decimal::from("1.0"),
+                    Decimal::from("1.0"),
+                )?;
+                let y2x = quote_swap_no_fees(
+                    amount_in,
+                    1_000_000_000_000,
+                    1_000_000_000_000,
+                    Decimal::from("1"),
+                    Decimal::from("1"),
+                    6,
+                    6,
+                    amplifier,
+                    false,
+                    Decimal::from("1.0"),
+                    Decimal::from("1.0"),
+                )?;
+
+                assert_eq!(x2y, y2x);
+                assert!(x2y <= amount_in);
+            }
+        }
+
+        // Any equal integer price cancels out, so quotes match the unit price
+        let unit_price = quote_swap_no_fees(
+            10_000_000,
+            1_000_000_000_000,
+            700_000_000_000,
+            Decimal::from("1"),
+            Decimal::from("1"),
+            9,
+            9,
+            10,
+            true,
+            Decimal::from("1.0"),
+            Decimal::from("1.0"),
+        )?;
+        let integer_price = quote_swap_no_fees(
+            10_000_000,
+            1_000_000_000_000,
+            700_000_000_000,
+            Decimal::from("3"),
+            Decimal::from("3"),
+            9,
+            9,
+            10,
+            true,
+            Decimal::from("1.0"),
+            Decimal::from("1.0"),
+        )?;
+        assert_eq!(unit_price, integer_price);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_y_zero_reserve_in() {
+        let (amp, d) = (u256(20_000), u256(2_000_000));
+
+        let err = get_y(U256::zero(), amp, d).unwrap_err();
+        assert_eq!(err.to_string(), "get_y called with a zero input reserve");
+        assert!(get_y_u256(U256::zero(), amp, d).is_err());
+        assert!(get_y_with_iterations(U256::zero(), amp, d).is_err());
+        assert_eq!(get_y_u128(0, 20_000, 2_000_000), None);
+    }
+
+    #[test]
+    fn test_pool_implied_price() -> Result<()> {
+        let one = Decimal::from(1u64);
+
+        for amplifier in [1, 10, 100, 1000] {
+            // 1_000 tokens on each side, with 9 and 6 decimals
+            let balanced_x2y =
+                pool_implied_price(1_000_000_000_000, 1_000_000_000, amplifier, 9, 6, true)?;
+            let balanced_y2x =
+                pool_implied_price(1_000_000_000_000, 1_000_000_000, amplifier, 9, 6, false)?;
+            assert!(balanced_x2y.within_bps(&one, 1));
+            assert!(balanced_y2x.within_bps(&one, 1));
+
+            // 1_000 x for 100 y: x is abundant, so it is cheaper than y
+            let imbalanced_x2y =
+                pool_implied_price(1_000_000_000_000, 100_000_000, amplifier, 9, 6, true)?;
+            let imbalanced_y2x =
+                pool_implied_price(1_000_000_000_000, 100_000_000, amplifier, 9, 6, false)?;
+            assert!(imbalanced_x2y < one);
+            assert!(imbalanced_y2x > one);
+            assert!(
+                imbalanced_x2y
+                    .checked_mul(&imbalanced_y2x)
+                    .unwrap()
+                    .within_bps(&one, 1)
+            );
+
+            // Matches the analytic marginal rate with unit prices
+            let analytic = spot_usd_rate(
+                1_000_000_000_000,
+                100_000_000,
+                one,
+                one,
+                9,
+                6,
+                amplifier,
+                true,
+                one,
+                one,
+            )?;
+            assert!(imbalanced_x2y.within_bps(&analytic, 1));
+        }
+
+        // A higher amplifier keeps the price closer to 1
+        let low_amp = pool_implied_price(1_000_000_000_000, 100_000_000, 1, 9, 6, true)?;
+        let high_amp = pool_implied_price(1_000_000_000_000, 100_000_000, 1000, 9, 6, true)?;
+        assert!(low_amp < high_amp);
+
+        assert!(pool_implied_price(0, 100_000_000, 10, 9, 6, true).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_usd_round_trip_error_bound() {
+        let amounts = [
+            1,
+            7,
+            999,
+            123_456_789,
+            1_000_000_000_000,
+            987_654_321_987_654,
+            u64::MAX / 1_000_000,
+        ];
+        let prices = [
+            "0.000001",
+            "0.0123456789",
+            "0.333333333333333333",
+            "1",
+            "1.000001",
+            "3.14159265358979",
+            "62000.123456",
+        ];
+
+        for decimals in [0, 6, 8, 9, 12, 18] {
+            for price in prices.map(Decimal::from) {
+                let bound = U256::from(10u64.pow(decimals)) / price.0 + U256::one();
+                for amount in amounts {
+                    let Ok(usd) = to_usd(amount, price, decimals) else {
+                        continue;
+                    };
+                    let round_trip = from_usd(usd, price, decimals).unwrap();
+                    assert!(round_trip <= amount);
+                    assert!(U256::from(amount - round_trip) <= bound);
+
+                    if decimals <= 9 {
+                        assert_eq!(round_trip, amount);
+                    }
+                }
+            }
+        }
+
+        // The worst case: an 18 decimal token priced at 0.000001
+        let price = Decimal::from("0.000001");
+        let amount = 1_999_999;
+        let usd = to_usd(amount, price, 18).unwrap();
+        assert_eq!(usd.0, U256::one());
+        assert_eq!(from_usd(usd, price, 18).unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn test_quote_swap_with_imbalance_penalty() -> Result<()> {
+        let one = Decimal::from("1.0");
+        let quote = |reserve_y, x2y, fee_mode| {
+            quote_swap_with_fee_mode(
+                10_000_000,
+                1_000_000_000_000, // 1_000 SUI
+                reserve_y,
+                Decimal::from("3"),
+                Decimal::from("1"),
+                9,
+                6,
+                10,
+                x2y,
+                one,
+                one,
+                30,
+                Decimal::from("0.001"),
+                Decimal::from("0.0001"),
+                fee_mode,
+            )
+            .map(|(quote, _)| quote)
+        };
+
+        // On a USD-balanced pool the marginal price is the oracle price, so
+        // the penalty does not apply
+        for x2y in [true, false] {
+            let default = quote(3_000_000_000, x2y, FeeMode::default())?;
+            let penalized = quote(3_000_000_000, x2y, FeeMode::PriceUncertaintyAndImbalance)?;
+            assert_eq!(default.amount_out, penalized.amount_out);
+            assert_eq!(default.total_fees(), penalized.total_fees());
+        }
+
+        // With 300 USDC against 3_000 USD of SUI, the pool quotes well away
+        // from the oracle in both directions
+        for x2y in [true, false] {
+            let default = quote(300_000_000, x2y, FeeMode::default())?;
+            let penalized = quote(300_000_000, x2y, FeeMode::PriceUncertaintyAndImbalance)?;
+            assert_eq!(
+                default.amount_out as u128 + default.total_fees(),
+                penalized.amount_out as u128 + penalized.total_fees()
+            );
+            assert!(penalized.amount_out < default.amount_out);
+            assert_eq!(default.realized_fee_bps(), 30);
+
+            let rate = spot_usd_rate(
+                1_000_000_000_000,
+                300_000_000,
+                Decimal::from("3"),
+                Decimal::from("1"),
+                9,
+                6,
+                10,
+                x2y,
+                one,
+                one,
+            )?;
+            let drift_bps = if x2y {
+                Decimal::ONE.saturating_sub(&rate)
+            } else {
+                rate.saturating_sub(&Decimal::ONE)
+            }
+            .checked_mul_int(BPS_SCALE)
+            .unwrap()
+            .checked_floor::<u64>()
+            .unwrap();
+            assert!(drift_bps > 30);
+            assert!(penalized.realized_fee_bps().abs_diff(drift_bps) <= 1);
+        }
+
+        Ok(())
+    }
+
+    /// Textbook 2-coin StableSwap on 18 decimal balances, with `ann = A * n^n`
+    /// and no amplifier precision scaling.
+    fn plain_stable_swap_out(
+        amount_in: u128,
+        reserve_in: u128,
+        reserve_out: u128,
+        a: u128,
+    ) -> u128 {
+        let (x, y, dx) = (
+            U256::from(reserve_in),
+            U256::from(reserve_out),
+            U256::from(amount_in),
+        );
+        let ann = U256::from(a * 4);
+        let two = U256::from(2u8);
+
+        let sum = x + y;
+        let mut d = sum;
+        loop {
+            let d_p = d * d / (two * x) * d / (two * y);
+            let d_prev = d;
+            d = (ann * sum + two * d_p) * d / ((ann - 1) * d + U256::from(3u8) * d_p);
+            if d.abs_diff(d_prev) <= U256::one() {
+                break;
+            }
+        }
+
+        let new_x = x + dx;
+        let c = d * d / (two * new_x) * d / (ann * two);
+        let b = new_x + d / ann;
+        let mut new_y = d;
+        loop {
+            let y_prev = new_y;
+            new_y = (new_y * new_y + c) / (two * new_y + b - d);
+            if new_y.abs_diff(y_prev) <= U256::one() {
+                break;
+            }
+        }
+
+        (y - new_y).as_u128()
+    }
+
+    #[test]
+    fn test_quote_stable_underlying() -> Result<()> {
+        let wad = 10u128.pow(18);
+        let pools: [(u64, u64, u32, u32); 4] = [
+            (1_000_000_000_000, 1_000_000_000_000, 6, 6),
+            (1_000_000_000_000, 400_000_000_000, 6, 6),
+            (1_000_000_000_000_000, 2_000_000_000_000, 9, 6),
+            (500_000_000, 800_000_000_000, 6, 9),
+        ];
+
+        for (reserve_x, reserve_y, decimals_x, decimals_y) in pools {
+            for amplifier in [1, 10, 100, 1000] {
+                for x2y in [true, false] {
+                    let (reserve_in, reserve_out, decimals_in, decimals_out) = if x2y {
+                        (reserve_x, reserve_y, decimals_x, decimals_y)
+                    } else {
+                        (reserve_y, reserve_x, decimals_y, decimals_x)
+                    };
+                    let amount_in = reserve_in / 20;
+                    let scale_in = wad / 10u128.pow(decimals_in);
+                    let scale_out = wad / 10u128.pow(decimals_out);
+
+                    let out = quote_stable_underlying(
+                        amount_in, reserve_x, reserve_y, amplifier, decimals_x, decimals_y, x2y,
+                    )?;
+                    let reference = plain_stable_swap_out(
+                        amount_in as u128 * scale_in,
+                        reserve_in as u128 * scale_in,
+                        reserve_out as u128 * scale_out,
+                        amplifier as u128,
+                    ) / scale_out;
+
+                    assert!(
+                        (out as u128).abs_diff(reference) <= 1,
+                        "out = {}, reference = {}",
+                        out,
+                        reference
+                    );
+                }
+            }
+        }
+
+        // Oracle prices of 1 on the full quoter give the same result
+        let one = Decimal::ONE;
+        assert_eq!(
+            quote_stable_underlying(
+                10_000_000,
+                1_000_000_000_000,
+                400_000_000_000,
+                10,
+                6,
+                6,
+                true
+            )?,
+            quote_swap_no_fees(
+                10_000_000,
+                1_000_000_000_000,
+                400_000_000_000,
+                one,
+                one,
+                6,
+                6,
+                10,
+                true,
+                one,
+                one
+            )?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_convergence_error_carries_last_iterate() {
+        let (reserve_a, reserve_b, amp) =
+            (u256(646_604_101_554_903), u256(430_825_829_860), u256(200));
+        let (d, iterations) = get_d_u256_with_iterations(reserve_a, reserve_b, amp, LIMIT).unwrap();
+        assert!(iterations > 2);
+
+        // Newton's method approaches D from above, so a truncated run stops
+        // short of it
+        let err = get_d_u256_with_iterations(reserve_a, reserve_b, amp, 2).unwrap_err();
+        let convergence = err.downcast_ref::<ConvergenceError>().unwrap();
+        assert_eq!(convergence.solver, "get_d");
+        assert_eq!(convergence.iterations, 2);
+        assert!(convergence.last > d);
+        assert!(convergence.delta > U256::one());
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "get_d did not converge after 2 iterations: last = {}, delta = {}",
+                convergence.last, convergence.delta
+            )
+        );
+
+        let reserve_in = reserve_a * u256(2);
+        let (y, _) = get_y_u256_with_iterations(reserve_in, amp, d, LIMIT).unwrap();
+        let err = get_y_u256_with_iterations(reserve_in, amp, d, 1).unwrap_err();
+        let convergence = err.downcast_ref::<ConvergenceError>().unwrap();
+        assert_eq!(convergence.solver, "get_y");
+        assert_eq!(convergence.iterations, 1);
+        assert_ne!(convergence.last, y);
+        assert!(convergence.delta > U256::one());
+
+        // At 1_500_000:1 with A = 1, the iterates keep moving by a few units
+        // and never settle
+        let err = get_d(u256(646_604_101_554_903), u256(430_825_829), u256(200)).unwrap_err();
+        let convergence = err.downcast_ref::<ConvergenceError>().unwrap();
+        assert_eq!(convergence.iterations, LIMIT);
+        assert!(convergence.delta < u256(100));
+
+        assert_eq!(
+            get_d(u256(0), u256(1_000), u256(200))
+                .unwrap_err()
+                .to_string(),
+            "get_d called with a zero reserve"
+        );
+    }
+
+    #[test]
+    fn test_confidence_from_percent() -> Result<()> {
+        assert_eq!(
+            confidence_from_percent(&Decimal::from("3"), 50)?,
+            Decimal::from("0.015")
+        );
+        assert_eq!(
+            confidence_from_percent(&Decimal::from("3"), 0)?,
+            Decimal::ZERO
+        );
+
+        // Rounding down here would give a ratio one basis point short
+        let price = Decimal::from("1.234567890123456789");
+        assert_eq!(
+            confidence_from_percent(&price, 7)?,
+            Decimal::from("0.000864197523086420")
+        );
+
+        for price in [
+            "3",
+            "0.000001",
+            "1.234567890123456789",
+            "65000.5",
+            "1000000000",
+        ] {
+            let price = Decimal::from(price);
+            for pct_bps in [0, 1, 7, 30, 100, 9_999, 10_000] {
+                let confidence = confidence_from_percent(&price, pct_bps)?;
+                assert_eq!(price_uncertainty_ratio(price, confidence)?, pct_bps);
+                assert_eq!(
+                    price_uncertainty_fee_bps(price, price, confidence, Decimal::ZERO)?,
+                    pct_bps
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_equal_integer_price_fast_path_matches_general_path() -> Result<()> {
+        let ratio = Decimal::from("1.0");
+
+        for (reserve_x, reserve_y, decimals) in [
+            (1_000_000_000_000, 700_000_000_000, 9),
+            (4_000_000_000_000_000_000, 4_000_000_000_000_000_000, 9),
+            (4_000_000_000_000_000_000, 3_000_000_000_000_000_000, 9),
+            (1_000_000_000_000, 1_000_000_000_000, 6),
+        ] {
+            for price in ["1", "2", "3"] {
+                let price = Decimal::from(price);
+                let fast = CurveState::new(
+                    reserve_x, reserve_y, price, price, decimals, decimals, 100, ratio, ratio,
+                )?;
+                let general = CurveState::with_prices(
+                    reserve_x, reserve_y, price, price, decimals, decimals, 100, ratio, ratio,
+                )?;
+
+                for x2y in [true, false] {
+                    for amount_in in [1, 1_000_000_000, 123_456_789_000] {
+                        assert_eq!(
+                            fast.quote(amount_in, x2y)?.0,
+                            general.quote(amount_in, x2y)?.0
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_d_and_get_y_overflow_is_an_error() {
+        let amp = COINS.scaled_amp(100);
+        let huge = U256::MAX / U256::from(4u8);
+
+        assert_eq!(
+            get_d_u256(huge, huge, amp).unwrap_err().to_string(),
+            "get_d overflow"
+        );
+        assert_eq!(
+            get_d(U256::from(1u8), huge, amp).unwrap_err().to_string(),
+            "get_d overflow"
+        );
+        assert!(get_d_u256(U256::MAX, U256::MAX, amp).is_err());
+        assert!(get_d_u256(U256::one(), U256::one(), U256::MAX).is_err());
+
+        assert_eq!(
+            get_y_u256(huge, amp, huge).unwrap_err().to_string(),
+            "get_y overflow"
+        );
+        assert!(get_y(U256::one(), U256::MAX, huge).is_err());
+    }
+
+    #[test]
+    fn test_quote_capped_by_reserves() -> Result<()> {
+        let ratio = Decimal::from("1.0");
+        let mut curve = CurveState::new(
+            1_000_000_000,
+            1_000_000_000,
+            Decimal::from("1"),
+            Decimal::from("1"),
+            6,
+            6,
+            100,
+            ratio,
+            ratio,
+        )?;
+
+        let (amount_out, diagnostics) = curve.quote(10_000_000, true)?;
+        assert!(amount_out > 0);
+        assert!(!diagnostics.capped_by_reserves);
+
+        // The curve pays out at most its own reserve, so the check only fires
+        // when the btoken reserve is below it
+        curve.b_token_reserve_y = amount_out - 1;
+        let (amount_out, diagnostics) = curve.quote(10_000_000, true)?;
+        assert_eq!(amount_out, 0);
+        assert!(diagnostics.capped_by_reserves);
+        assert!(!curve.quote(10_000_000, false)?.1.capped_by_reserves);
+
+        Ok(())
+    }
 }
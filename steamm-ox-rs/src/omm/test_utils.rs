@@ -0,0 +1,716 @@
+//! Shared helpers for the quoter test suites.
+
+use crate::math::{
+    decimal::Decimal,
+    u256::{U256, U512},
+};
+
+use anyhow::Result;
+
+use super::{QuoterType, omm_v2_legacy, omm_v2_new};
+
+/// Inputs accepted by `quote_swap_no_fees` in both quoter families.
+#[derive(Clone, Debug)]
+pub struct QuoterInputs {
+    pub b_token_amount_in: u64,
+    pub b_token_reserve_x: u64,
+    pub b_token_reserve_y: u64,
+    pub price_x: Decimal,
+    pub price_y: Decimal,
+    pub decimals_x: u32,
+    pub decimals_y: u32,
+    pub amplifier: u32,
+    pub x2y: bool,
+    pub b_token_ratio_x: Decimal,
+    pub b_token_ratio_y: Decimal,
+}
+
+/// Runs each input through both quoters and returns `(legacy, new)` outputs.
+///
+/// The two families price along different curves, so their outputs are not
+/// expected to match. Both start at the oracle price for an infinitesimal
+/// trade and diverge as the trade grows:
+///
+/// - The legacy curve (Newton-Raphson on `z(1 - 1/A) - ln(1 - z)/A = k`) only
+///   depends on the trade size relative to the output reserve. The input
+///   reserve does not enter the computation.
+/// - StableSwap depends on both reserves through `D`, so its price also
+///   reflects how balanced the pool is in USD terms.
+///
+/// Reference envelope for a USD-balanced SUI/USDC pool (3_000 SUI at $3 and
+/// 9_000 USDC at $1, 9 and 6 decimals), selling 300 SUI (10% of the reserve):
+///
+/// | A    | legacy out (USDC) | new out (USDC) | new vs legacy |
+/// |------|-------------------|----------------|---------------|
+/// | 1    | 856.463237        | 870.846674     | +1.68%        |
+/// | 10   | 895.228397        | 895.695946     | +0.05%        |
+/// | 100  | 899.518088        | 899.547990     | +0.003%       |
+/// | 1000 | 899.951760        | 899.954570     | +0.0003%      |
+/// | 8000 | 899.993969        | 899.994318     | +0.00004%     |
+///
+/// For a balanced pool StableSwap is never worse than the legacy curve, and
+/// the gap closes quickly as `A` grows since both curves flatten towards the
+/// oracle price. The choice of curve matters most for pools with a low `A`.
+pub fn compare_quoters(inputs: &[QuoterInputs]) -> Vec<(u64, u64)> {
+    try_compare_quoters(inputs)
+        .into_iter()
+        .map(|(legacy, new)| (legacy.unwrap(), new.unwrap()))
+        .collect()
+}
+
+/// Same as `compare_quoters`, keeping the errors so that inputs one family
+/// rejects can be compared too.
+pub fn try_compare_quoters(inputs: &[QuoterInputs]) -> Vec<(Result<u64>, Result<u64>)> {
+    inputs
+        .iter()
+        .map(|input| {
+            (
+                quote_swap_no_fees(QuoterType::Ommv2Legacy, input),
+                quote_swap_no_fees(QuoterType::Ommv2, input),
+            )
+        })
+        .collect()
+}
+
+/// Slow reference implementation of `omm_v2_new::quote_swap_no_fees`, used for
+/// differential testing.
+///
+/// Every amount is an explicit integer: prices and btoken ratios are taken as
+/// their raw WAD-scaled values, and USD values are WAD-scaled. Instead of
+/// Newton's method, `D` and the output reserve are found by bisection on the
+/// exact integer form of the invariant
+///
+/// `4xy * (Ann * (x + y) - (Ann - 1) * D) = D^3`
+///
+/// with `Ann = 4A`. `D` is rounded down and the output reserve is rounded up,
+/// so rounding always favours the pool.
+pub fn reference_quote_swap_no_fees(input: &QuoterInputs) -> u64 {
+    let wad = U512::from(10u64.pow(18));
+    let ratio_x = U512::from(input.b_token_ratio_x.to_scaled());
+    let ratio_y = U512::from(input.b_token_ratio_y.to_scaled());
+    let price_x = U512::from(input.price_x.to_scaled());
+    let price_y = U512::from(input.price_y.to_scaled());
+    let unit_x = U512::from(10u64).pow(input.decimals_x.into());
+    let unit_y = U512::from(10u64).pow(input.decimals_y.into());
+
+    let reserve_x = U512::from(input.b_token_reserve_x) * ratio_x / wad;
+    let reserve_y = U512::from(input.b_token_reserve_y) * ratio_y / wad;
+    let usd_x = reserve_x * price_x / unit_x;
+    let usd_y = reserve_y * price_y / unit_y;
+
+    // Ann and A_PRECISION, scaled by A_PRECISION = 100
+    let ann = U512::from(input.amplifier) * U512::from(400u64);
+    let one = U512::from(100u64);
+
+    // D is the largest value with 4xy * (Ann * S - (Ann - 1) * D) >= D^3
+    let sum = usd_x + usd_y;
+    let d = bisect(U512::zero(), sum, |d| {
+        U512::from(4u8) * usd_x * usd_y * (ann * sum - (ann - one) * d) >= one * d * d * d
+    });
+
+    let (b_token_ratio_in, price_in, unit_in, usd_reserve_in, usd_reserve_out) = if input.x2y {
+        (ratio_x, price_x, unit_x, usd_x, usd_y)
+    } else {
+        (ratio_y, price_y, unit_y, usd_y, usd_x)
+    };
+    let (b_token_reserve_out, b_token_ratio_out, price_out, unit_out, reserve_out) = if input.x2y {
+        (input.b_token_reserve_y, ratio_y, price_y, unit_y, reserve_y)
+    } else {
+        (input.b_token_reserve_x, ratio_x, price_x, unit_x, reserve_x)
+    };
+
+    let amount_in = U512::from(input.b_token_amount_in) * b_token_ratio_in / wad;
+    let usd_in = usd_reserve_in + amount_in * price_in / unit_in;
+
+    // The output reserve is the smallest value with
+    // 4xy * (Ann * (x + y) - (Ann - 1) * D) >= D^3. Adding to the input side
+    // can only shrink the output side.
+    let usd_out = bisect(U512::zero(), usd_reserve_out, |y| {
+        let (positive, negative) = (ann * (usd_in + y), (ann - one) * d);
+        positive >= negative
+            && U512::from(4u8) * usd_in * y * (positive - negative) >= one * d * d * d
+    });
+
+    // Rounded up
+    let reserve_out_after_trade = (usd_out * unit_out + price_out - U512::one()) / price_out;
+    if reserve_out_after_trade > reserve_out {
+        return 0;
+    }
+
+    let amount_out = (reserve_out - reserve_out_after_trade) * wad / b_token_ratio_out;
+    if amount_out > U512::from(b_token_reserve_out) {
+        return 0;
+    }
+
+    amount_out.as_u64()
+}
+
+/// Binary search over `[lo, hi]` for the boundary of a monotonic predicate.
+/// If `pred` is true on a prefix of the range, returns the last true value.
+/// If `pred` is true on a suffix of the range, returns the first true value.
+fn bisect(mut lo: U512, mut hi: U512, pred: impl Fn(U512) -> bool) -> U512 {
+    let prefix = pred(lo);
+    while lo < hi {
+        if prefix {
+            let mid = (lo + hi + U512::one()) / U512::from(2u8);
+            if pred(mid) {
+                lo = mid
+            } else {
+                hi = mid - U512::one()
+            }
+        } else {
+            let mid = (lo + hi) / U512::from(2u8);
+            if pred(mid) {
+                hi = mid
+            } else {
+                lo = mid + U512::one()
+            }
+        }
+    }
+    lo
+}
+
+/// Quotes `input` without fees using the given quoter family.
+pub fn quote_swap_no_fees(quoter_type: QuoterType, input: &QuoterInputs) -> Result<u64> {
+    let quote = match quoter_type {
+        QuoterType::Ommv2Legacy => omm_v2_legacy::quote_swap_no_fees,
+        QuoterType::Ommv2 => omm_v2_new::quote_swap_no_fees,
+    };
+    quote(
+        input.b_token_amount_in,
+        input.b_token_reserve_x,
+        input.b_token_reserve_y,
+        input.price_x,
+        input.price_y,
+        input.decimals_x,
+        input.decimals_y,
+        input.amplifier,
+        input.x2y,
+        input.b_token_ratio_x,
+        input.b_token_ratio_y,
+    )
+}
+
+/// Asserts that `quoter_type` reproduces an amount out observed on-chain to
+/// within `tolerance` btoken units. `expected_onchain_out` is the btoken
+/// amount out before fees, i.e. the swap's amount out plus its protocol and
+/// pool fees.
+pub fn assert_matches_onchain(
+    quoter_type: QuoterType,
+    inputs: &QuoterInputs,
+    expected_onchain_out: u64,
+    tolerance: u64,
+) {
+    let amount_out = quote_swap_no_fees(quoter_type, inputs)
+        .unwrap_or_else(|err| panic!("{:?} failed on {:?}: {}", quoter_type, inputs, err));
+    assert!(
+        amount_out.abs_diff(expected_onchain_out) <= tolerance,
+        "{:?} quoted {} but on-chain paid out {} (tolerance {}) for {:?}",
+        quoter_type,
+        amount_out,
+        expected_onchain_out,
+        tolerance,
+        inputs
+    );
+}
+
+/// A swap recorded from chain, for `assert_matches_onchain`.
+#[derive(Clone, Debug)]
+pub struct OnchainQuote {
+    /// Where the quote was captured from, e.g. the transaction digest
+    pub source: String,
+    pub quoter_type: QuoterType,
+    pub inputs: QuoterInputs,
+    pub expected_out: u64,
+    pub tolerance: u64,
+}
+
+/// Parses recorded on-chain quotes, as kept in `fixtures/onchain_quotes.json`:
+///
+/// ```json
+/// {
+///   "quotes": [
+///     {
+///       "source": "<transaction digest>",
+///       "quoter": "omm_v2_legacy",
+///       "b_token_amount_in": 10000000000,
+///       "b_token_reserve_x": 1000000000000,
+///       "b_token_reserve_y": 3000000000,
+///       "price_x": "3.41",
+///       "price_y": "0.9999",
+///       "decimals_x": 9,
+///       "decimals_y": 6,
+///       "amplifier": 100,
+///       "x2y": true,
+///       "b_token_ratio_x": "1.0234",
+///       "b_token_ratio_y": "1.0011",
+///       "expected_out": 33750000,
+///       "tolerance": 0
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `quoter` is any tag accepted by `QuoterType::from_version_tag`. Prices and
+/// btoken ratios are strings so that no precision is lost, and should be the
+/// values the pool read on-chain in the same transaction. `source` may also
+/// name a test case of the steamm Move contracts, whose outputs are the
+/// contract's own reference values.
+pub fn parse_onchain_quotes(json: &str) -> Result<Vec<OnchainQuote>> {
+    let document: serde_json::Value = serde_json::from_str(json)?;
+    let quotes = document["quotes"]
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("Missing quotes array"))?;
+
+    quotes
+        .iter()
+        .map(|quote| {
+            let field = |name: &str| {
+                quote
+                    .get(name)
+                    .ok_or_else(|| anyhow::anyhow!("Missing field {}", name))
+            };
+            let u64_field = |name: &str| {
+                field(name)?
+                    .as_u64()
+                    .ok_or_else(|| anyhow::anyhow!("Field {} is not a u64", name))
+            };
+            let str_field = |name: &str| {
+                field(name)?
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Field {} is not a string", name))
+            };
+            let decimal_field = |name: &str| str_field(name).map(Decimal::from);
+            let u32_field = |name: &str| Ok::<_, anyhow::Error>(u32::try_from(u64_field(name)?)?);
+
+            Ok(OnchainQuote {
+                source: str_field("source")?.to_string(),
+                quoter_type: QuoterType::from_version_tag(str_field("quoter")?)?,
+                inputs: QuoterInputs {
+                    b_token_amount_in: u64_field("b_token_amount_in")?,
+                    b_token_reserve_x: u64_field("b_token_reserve_x")?,
+                    b_token_reserve_y: u64_field("b_token_reserve_y")?,
+                    price_x: decimal_field("price_x")?,
+                    price_y: decimal_field("price_y")?,
+                    decimals_x: u32_field("decimals_x")?,
+                    decimals_y: u32_field("decimals_y")?,
+                    amplifier: u32_field("amplifier")?,
+                    x2y: field("x2y")?
+                        .as_bool()
+                        .ok_or_else(|| anyhow::anyhow!("Field x2y is not a bool"))?,
+                    b_token_ratio_x: decimal_field("b_token_ratio_x")?,
+                    b_token_ratio_y: decimal_field("b_token_ratio_y")?,
+                },
+                expected_out: u64_field("expected_out")?,
+                tolerance: u64_field("tolerance")?,
+            })
+        })
+        .collect()
+}
+
+/// xorshift64*, so the randomized inputs are reproducible without
+/// pulling in a dependency
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub(crate) fn range(&mut self, lo: u64, hi: u64) -> u64 {
+        lo + self.next() % (hi - lo + 1)
+    }
+
+    /// Fills `bytes` with the output, e.g. as input for `arbitrary`
+    pub(crate) fn fill_bytes(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    /// A value with 7 significant digits between 10^min_exp and
+    /// 10^(max_exp + 1)
+    pub(crate) fn decimal(&mut self, min_exp: i32, max_exp: i32) -> Decimal {
+        let mantissa = self.range(1_000_000, 9_999_999);
+        let exp = self.range(0, (max_exp - min_exp) as u64) as i32 + min_exp;
+        // mantissa has 7 digits, so scale it by 10^(18 + exp - 6)
+        let shift = 18 + exp - 6;
+        let scaled = if shift >= 0 {
+            U256::from(mantissa) * U256::from(10u64).pow(shift.into())
+        } else {
+            U256::from(mantissa) / U256::from(10u64).pow((-shift).into())
+        };
+        Decimal::from_scaled_u256(scaled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balanced_sui_usdc(amplifier: u32) -> QuoterInputs {
+        QuoterInputs {
+            b_token_amount_in: 300_000_000_000,   // 300 SUI
+            b_token_reserve_x: 3_000_000_000_000, // 3_000 SUI
+            b_token_reserve_y: 9_000_000_000,     // 9_000 USDC
+            price_x: Decimal::from("3"),
+            price_y: Decimal::from("1"),
+            decimals_x: 9,
+            decimals_y: 6,
+            amplifier,
+            x2y: true,
+            b_token_ratio_x: Decimal::from("1.0"),
+            b_token_ratio_y: Decimal::from("1.0"),
+        }
+    }
+
+    #[test]
+    fn test_onchain_fixtures() -> Result<()> {
+        let quotes = parse_onchain_quotes(include_str!("fixtures/onchain_quotes.json"))?;
+        assert!(!quotes.is_empty(), "No on-chain quotes in the fixture");
+        for quote in quotes {
+            assert_matches_onchain(
+                quote.quoter_type,
+                &quote.inputs,
+                quote.expected_out,
+                quote.tolerance,
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_onchain_quotes() -> Result<()> {
+        let json = r#"{
+            "quotes": [
+                {
+                    "source": "balanced SUI/USDC",
+                    "quoter": "omm_v2_legacy",
+                    "b_token_amount_in": 300000000000,
+                    "b_token_reserve_x": 3000000000000,
+                    "b_token_reserve_y": 9000000000,
+                    "price_x": "3",
+                    "price_y": "1",
+                    "decimals_x": 9,
+                    "decimals_y": 6,
+                    "amplifier": 100,
+                    "x2y": true,
+                    "b_token_ratio_x": "1.0",
+                    "b_token_ratio_y": "1.0",
+                    "expected_out": 899518088,
+                    "tolerance": 1
+                }
+            ]
+        }"#;
+
+        let quotes = parse_onchain_quotes(json)?;
+        assert_eq!(quotes.len(), 1);
+        let quote = &quotes[0];
+        assert_eq!(quote.source, "balanced SUI/USDC");
+        assert_eq!(quote.quoter_type, QuoterType::Ommv2Legacy);
+        assert_eq!(
+            format!("{:?}", quote.inputs),
+            format!("{:?}", balanced_sui_usdc(100))
+        );
+        assert_eq!((quote.expected_out, quote.tolerance), (899_518_088, 1));
+
+        assert_matches_onchain(quote.quoter_type, &quote.inputs, 899_518_089, 1);
+
+        let missing_field = json.replace(r#""tolerance": 1"#, r#""slack": 1"#);
+        assert_eq!(
+            parse_onchain_quotes(&missing_field)
+                .unwrap_err()
+                .to_string(),
+            "Missing field tolerance"
+        );
+        assert!(parse_onchain_quotes("{}").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "but on-chain paid out")]
+    fn test_assert_matches_onchain_outside_tolerance() {
+        assert_matches_onchain(
+            QuoterType::Ommv2Legacy,
+            &balanced_sui_usdc(100),
+            899_518_090,
+            1,
+        );
+    }
+
+    #[test]
+    fn test_compare_quoters_divergence_table() {
+        let amplifiers = [1, 10, 100, 1000, 8000];
+        let inputs: Vec<QuoterInputs> = amplifiers.iter().map(|a| balanced_sui_usdc(*a)).collect();
+
+        let expected = [
+            (856_463_237, 870_846_674),
+            (895_228_397, 895_695_946),
+            (899_518_088, 899_547_990),
+            (899_951_760, 899_954_570),
+            (899_993_969, 899_994_318),
+        ];
+
+        let results = compare_quoters(&inputs);
+        assert_eq!(results, expected);
+
+        // Neither curve beats the oracle price of 900 USDC, StableSwap is never
+        // worse than legacy on a balanced pool and the gap narrows with A
+        let mut previous_gap = u64::MAX;
+        for (legacy, new) in results {
+            assert!(new < 900_000_000);
+            assert!(new >= legacy);
+            assert!(new - legacy <= previous_gap);
+            previous_gap = new - legacy;
+        }
+    }
+
+    #[test]
+    fn test_reference_quote_swap_matches_production() {
+        let mut rng = Rng(0x5eed_cafe_f00d_d00d);
+
+        let mut cases = 0;
+        while cases < 2_000 {
+            let decimals_x = rng.range(0, 12) as u32;
+            let decimals_y = rng.range(0, 12) as u32;
+            let b_token_reserve_x = rng.range(10u64.pow(decimals_x), 10u64.pow(decimals_x + 6));
+            let b_token_reserve_y = rng.range(10u64.pow(decimals_y), 10u64.pow(decimals_y + 6));
+            let x2y = rng.next() % 2 == 0;
+            let b_token_reserve_in = if x2y {
+                b_token_reserve_x
+            } else {
+                b_token_reserve_y
+            };
+
+            let input = QuoterInputs {
+                b_token_amount_in: b_token_reserve_in / 1_000 * rng.range(1, 1_000),
+                b_token_reserve_x,
+                b_token_reserve_y,
+                price_x: rng.decimal(-4, 5),
+                price_y: rng.decimal(-4, 5),
+                decimals_x,
+                decimals_y,
+                amplifier: rng.range(1, 10_000) as u32,
+                x2y,
+                b_token_ratio_x: Decimal::from(1u64)
+                    .checked_add(&rng.decimal(-3, -1))
+                    .unwrap(),
+                b_token_ratio_y: Decimal::from(1u64)
+                    .checked_add(&rng.decimal(-3, -1))
+                    .unwrap(),
+            };
+
+            // Pools in a sane state, within 1:20 in USD terms
+            let usd = |reserve: u64, price: Decimal, decimals: u32| {
+                reserve as f64 * price.to_string().parse::<f64>().unwrap()
+                    / 10f64.powi(decimals as i32)
+            };
+            let usd_x = usd(b_token_reserve_x, input.price_x, decimals_x);
+            let usd_y = usd(b_token_reserve_y, input.price_y, decimals_y);
+            if usd_x / usd_y > 20.0 || usd_y / usd_x > 20.0 {
+                continue;
+            }
+            cases += 1;
+
+            let production = omm_v2_new::quote_swap_no_fees(
+                input.b_token_amount_in,
+                input.b_token_reserve_x,
+                input.b_token_reserve_y,
+                input.price_x,
+                input.price_y,
+                input.decimals_x,
+                input.decimals_y,
+                input.amplifier,
+                input.x2y,
+                input.b_token_ratio_x,
+                input.b_token_ratio_y,
+            )
+            .unwrap();
+            let reference = reference_quote_swap_no_fees(&input);
+
+            // The outputs currently match exactly. One unit of tolerance is
+            // allowed since Newton's method in production only stops within 1
+            // of the exact root, in WAD-scaled USD, and `from_usd` truncates
+            // before rounding up.
+            assert!(
+                production.abs_diff(reference) <= 1,
+                "production {} vs reference {} for {:?}",
+                production,
+                reference,
+                input
+            );
+        }
+    }
+
+    /// Inputs on which the two families differ in more than the curve shape.
+    #[test]
+    fn test_try_compare_quoters_edge_cases() {
+        let base = balanced_sui_usdc(100);
+        let inputs = [
+            // Dust rounds to zero in both
+            QuoterInputs {
+                b_token_amount_in: 1,
+                ..base.clone()
+            },
+            // Both reject invalid prices with the same error
+            QuoterInputs {
+                price_x: Decimal::ZERO,
+                ..base.clone()
+            },
+            // Intentional: the legacy curve ignores the input reserve, while
+            // StableSwap needs both sides to define D
+            QuoterInputs {
+                b_token_reserve_x: 0,
+                ..base.clone()
+            },
+            // Both reject an empty output side. StableSwap used to panic here
+            QuoterInputs {
+                b_token_reserve_y: 0,
+                ..base.clone()
+            },
+            // Both reject A = 0. StableSwap used to panic here
+            QuoterInputs {
+                amplifier: 0,
+                ..base.clone()
+            },
+            // Intentional: the legacy curve leaves exactly one unit in the
+            // pool for an oversized trade, StableSwap converges to the
+            // reserve more slowly
+            QuoterInputs {
+                b_token_amount_in: 100_000_000_000_000,
+                ..base.clone()
+            },
+            QuoterInputs {
+                b_token_amount_in: u64::MAX,
+                ..base.clone()
+            },
+        ];
+
+        let results: Vec<(Result<u64, String>, Result<u64, String>)> = try_compare_quoters(&inputs)
+            .into_iter()
+            .map(|(legacy, new)| {
+                (
+                    legacy.map_err(|e| e.to_string()),
+                    new.map_err(|e| e.to_string()),
+                )
+            })
+            .collect();
+
+        let price_err = "Price x must be greater than zero".to_string();
+        let expected = [
+            (Ok(0), Ok(0)),
+            (Err(price_err.clone()), Err(price_err)),
+            (
+                Ok(899_518_088),
+                Err("Pool reserves must be greater than zero".to_string()),
+            ),
+            (
+                Err("Zero division".to_string()),
+                Err("Pool reserves must be greater than zero".to_string()),
+            ),
+            (
+                Err("Zero division".to_string()),
+                Err("Amplifier must be greater than zero".to_string()),
+            ),
+            (Ok(8_999_999_999), Ok(8_999_959_469)),
+            (Ok(8_999_999_999), Ok(8_999_999_999)),
+        ];
+        assert_eq!(results, expected);
+    }
+
+    /// Prices far below one, as for meme tokens, lose precision in the USD
+    /// conversions but the quotes stay within the documented bounds.
+    #[test]
+    fn test_tiny_prices_quote_error_bounded() {
+        for price in [
+            "0.0000001",
+            "0.00000001",
+            "0.000000000123456789",
+            "0.0000000001",
+        ] {
+            let price_x = Decimal::from(price);
+            for decimals_x in [6, 9] {
+                // 10^18 units of the cheap token against the same USD value of
+                // USDC
+                let b_token_reserve_x = 1_000_000_000_000_000_000;
+                let b_token_reserve_y = Decimal::from(b_token_reserve_x)
+                    .checked_mul(&price_x)
+                    .and_then(|usd| usd.checked_div_pow10(decimals_x))
+                    .and_then(|usd| usd.to_token_amount(6))
+                    .unwrap();
+
+                for x2y in [true, false] {
+                    let input = QuoterInputs {
+                        b_token_amount_in: if x2y {
+                            b_token_reserve_x / 100
+                        } else {
+                            b_token_reserve_y / 100
+                        },
+                        b_token_reserve_x,
+                        b_token_reserve_y,
+                        price_x,
+                        price_y: Decimal::from("1"),
+                        decimals_x,
+                        decimals_y: 6,
+                        amplifier: 100,
+                        x2y,
+                        b_token_ratio_x: Decimal::from("1.0"),
+                        b_token_ratio_y: Decimal::from("1.0"),
+                    };
+                    // The same trade with both prices scaled so that neither
+                    // is below one
+                    let scaled = QuoterInputs {
+                        price_x: Decimal::from("1"),
+                        price_y: Decimal::from("1").checked_div(&price_x).unwrap(),
+                        ..input.clone()
+                    };
+
+                    // `from_usd` loses at most `1 + 10^decimals / (price *
+                    // 10^18)` units of the output token
+                    let (price_out, decimals_out) = if x2y {
+                        (Decimal::from("1"), 6)
+                    } else {
+                        (price_x, decimals_x)
+                    };
+                    let bound = 1 + Decimal::from(1u64)
+                        .checked_mul_pow10(decimals_out)
+                        .and_then(|unit| unit.checked_div(&price_out))
+                        .and_then(|units| units.checked_div_pow10(18))
+                        .and_then(|units| units.checked_ceil::<u64>())
+                        .unwrap();
+
+                    let new = quote_swap_no_fees(QuoterType::Ommv2, &input).unwrap();
+                    let reference = reference_quote_swap_no_fees(&input);
+                    assert!(
+                        new.abs_diff(reference) <= bound,
+                        "new {} vs reference {} for {:?}",
+                        new,
+                        reference,
+                        input
+                    );
+                    let new_scaled = quote_swap_no_fees(QuoterType::Ommv2, &scaled).unwrap();
+                    assert!(new.abs_diff(new_scaled) <= bound);
+
+                    // The legacy quoter holds prices in 64.64 fixed point, so
+                    // tiny prices only carry about 10 significant digits
+                    let legacy = quote_swap_no_fees(QuoterType::Ommv2Legacy, &input).unwrap();
+                    let legacy_scaled =
+                        quote_swap_no_fees(QuoterType::Ommv2Legacy, &scaled).unwrap();
+                    assert!(
+                        legacy.abs_diff(legacy_scaled) <= 1 + legacy / 1_000_000_000,
+                        "legacy {} vs scaled {} for {:?}",
+                        legacy,
+                        legacy_scaled,
+                        input
+                    );
+                    assert!(legacy > 0 && legacy <= new);
+                }
+            }
+        }
+    }
+}
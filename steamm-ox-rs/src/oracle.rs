@@ -0,0 +1,173 @@
+use crate::math::{decimal::Decimal, pow10, u256::U256};
+use anyhow::Result;
+
+/// Converts a Pyth price feed update into the `(price, confidence)` pair
+/// expected by the quoters.
+///
+/// Pyth reports `price` and `conf` as integers scaled by `10^expo`, where
+/// `expo` is usually negative (e.g. `-8`). Digits below the 18 decimal places
+/// supported by `Decimal` are truncated.
+pub fn from_pyth(price: i64, conf: u64, expo: i32) -> Result<(Decimal, Decimal)> {
+    if price < 0 {
+        return Err(anyhow::anyhow!("Negative oracle price: {}", price));
+    }
+
+    let price = scale_by_expo(U256::from(price), expo)?;
+    let conf = scale_by_expo(U256::from(conf), expo)?;
+
+    Ok((price, conf))
+}
+
+/// Converts a price stored on-chain as `mantissa * 10^expo` into a `Decimal`.
+///
+/// This covers both the oracle's `(base, expo)` pairs and raw WAD prices, which
+/// are a mantissa with an exponent of `-18`. Digits below the 18 decimal places
+/// supported by `Decimal` are truncated.
+pub fn from_onchain_price(mantissa: u128, expo: i8) -> Result<Decimal> {
+    scale_by_expo(U256::from(mantissa), expo.into())
+}
+
+/// Computes `value * 10^expo` as a `Decimal`.
+fn scale_by_expo(value: U256, expo: i32) -> Result<Decimal> {
+    let scaled_value = value * Decimal::wad();
+    let pow = pow10(expo.unsigned_abs());
+
+    let scaled_value = if expo >= 0 {
+        pow.and_then(|pow| scaled_value.checked_mul(pow))
+            .ok_or_else(|| anyhow::anyhow!("Oracle value overflow: {}e{}", value, expo))?
+    } else {
+        match pow {
+            Some(pow) => scaled_value / pow,
+            // The divisor exceeds U256, so the value rounds down to zero
+            None => U256::zero(),
+        }
+    };
+
+    Ok(Decimal::from_scaled_u256(scaled_value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_pyth() -> Result<()> {
+        // SUI/USD
+        let (price, conf) = from_pyth(341_234_567, 123_456, -8)?;
+        assert_eq!(price, Decimal::from("3.41234567"));
+        assert_eq!(conf, Decimal::from("0.00123456"));
+
+        // USDC/USD
+        let (price, conf) = from_pyth(99_990_123, 80_000, -8)?;
+        assert_eq!(price, Decimal::from("0.99990123"));
+        assert_eq!(conf, Decimal::from("0.0008"));
+
+        // BTC/USD
+        let (price, conf) = from_pyth(6_789_012_345_678, 3_500_000_000, -8)?;
+        assert_eq!(price, Decimal::from("67890.12345678"));
+        assert_eq!(conf, Decimal::from("35"));
+
+        // Non-negative exponents
+        let (price, conf) = from_pyth(42, 1, 0)?;
+        assert_eq!(price, Decimal::from("42"));
+        assert_eq!(conf, Decimal::from("1"));
+
+        let (price, conf) = from_pyth(42, 1, 3)?;
+        assert_eq!(price, Decimal::from("42000"));
+        assert_eq!(conf, Decimal::from("1000"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_pyth_truncates_beyond_wad_precision() -> Result<()> {
+        let (price, conf) = from_pyth(123_456_789, 1, -20)?;
+        assert_eq!(price, Decimal::from("0.000000000001234567"));
+        assert_eq!(conf, Decimal::from(0u64));
+
+        let (price, _) = from_pyth(i64::MAX, 0, -100)?;
+        assert_eq!(price, Decimal::from(0u64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_pyth_rejects_negative_price() {
+        assert!(from_pyth(-1, 0, -8).is_err());
+        assert!(from_pyth(i64::MIN, 0, -8).is_err());
+    }
+
+    #[test]
+    fn test_from_pyth_overflow() {
+        assert!(from_pyth(i64::MAX, 0, 60).is_err());
+    }
+
+    #[test]
+    fn test_from_onchain_price() -> Result<()> {
+        // Oracle prices stored as (base, expo)
+        assert_eq!(from_onchain_price(341, -2)?, Decimal::from("3.41"));
+        assert_eq!(
+            from_onchain_price(99_990_123, -8)?,
+            Decimal::from("0.99990123")
+        );
+        assert_eq!(from_onchain_price(42, 3)?, Decimal::from("42000"));
+
+        // WAD prices are a mantissa with an exponent of -18
+        assert_eq!(
+            from_onchain_price(3_410_000_000_000_000_000, -18)?,
+            Decimal::from("3.41")
+        );
+        assert_eq!(
+            from_onchain_price(1, -18)?,
+            Decimal::from("0.000000000000000001")
+        );
+
+        // Matches the Pyth conversion for the same encoding
+        assert_eq!(
+            from_onchain_price(6_789_012_345_678, -8)?,
+            from_pyth(6_789_012_345_678, 0, -8)?.0
+        );
+
+        assert_eq!(
+            from_onchain_price(123_456_789, -20)?,
+            Decimal::from("0.000000000001234567")
+        );
+        assert_eq!(from_onchain_price(u128::MAX, i8::MIN)?, Decimal::from(0u64));
+        assert!(from_onchain_price(u128::MAX, i8::MAX).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixedpoint64_to_decimal() -> Result<()> {
+        use crate::math::{
+            decimal_to_fixedpoint64, fixed_point::FixedPoint64, fixedpoint64_to_decimal,
+        };
+
+        // Prices stored as 64.64 fixed point
+        assert_eq!(
+            fixedpoint64_to_decimal(FixedPoint64::from_raw_value(3 << 63)?),
+            Decimal::from("1.5")
+        );
+        assert_eq!(
+            fixedpoint64_to_decimal(FixedPoint64::from(3)?),
+            Decimal::from("3")
+        );
+        assert_eq!(
+            fixedpoint64_to_decimal(FixedPoint64::from_rational(1, 3)?),
+            Decimal::from("0.333333333333333333")
+        );
+        assert_eq!(
+            fixedpoint64_to_decimal(FixedPoint64::ZERO),
+            Decimal::from(0u64)
+        );
+
+        // Round trips through fixed point lose at most a few units in the 18th place
+        for price in ["3.41", "0.0625", "67890.125"] {
+            let price = Decimal::from(price);
+            assert!(fixedpoint64_to_decimal(decimal_to_fixedpoint64(price)?).almost_eq(&price, 1));
+        }
+
+        Ok(())
+    }
+}